@@ -1,61 +1,82 @@
 use anyhow::{Context, Result, ensure};
-use std::process::Command;
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
 
-use super::SUDO_BIN;
+use super::{SUDO_BIN, grubenv::GrubEnv};
 
-static GRUB_EDITENV_BIN: &str = "/usr/bin/grub-editenv";
+static GRUBENV_WRITE_BIN: &str = "/usr/bin/grubenv_write.sh";
 static GRUB_ENV_FILE: &str = "/boot/EFI/BOOT/grubenv";
-static GRUB_CMD_LIST: &str = "list";
-static GRUB_CMD_SET: &str = "set";
-static GRUB_CMD_UNSET: &str = "unset";
 
-pub fn bootloader_env(key: &str) -> Result<String> {
-    let list = Command::new(GRUB_EDITENV_BIN)
-        .arg(GRUB_ENV_FILE)
-        .arg(GRUB_CMD_LIST)
-        .output()
-        .context("failed to execute 'grub-editenv list'")?;
-    ensure!(
-        list.status.success(),
-        "grub-editenv list: command returned with error"
-    );
-    let list = String::from_utf8(list.stdout)?;
-    let list = list.split('\n');
-    let mut value = "".to_string();
-    for i in list {
-        if let Some((k, v)) = i.split_once('=')
-            && k == key
-        {
-            value = v.trim().to_string();
-            break;
-        }
-    }
-    Ok(value)
+// serializes read-modify-write cycles of the environment block
+static GRUB_ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn load() -> Result<GrubEnv> {
+    let block = fs::read(GRUB_ENV_FILE).context(format!("failed to read {GRUB_ENV_FILE}"))?;
+    GrubEnv::parse(&block).context(format!("failed to parse {GRUB_ENV_FILE}"))
 }
 
-pub fn set_bootloader_env(key: &str, value: &str) -> Result<()> {
-    let set = format!("{key}={value}");
+// the environment block is owned by root, thus it is written by a helper
+// that only accepts changes of the variables we manage
+fn store(env: &GrubEnv) -> Result<()> {
+    let block = env.serialize()?;
+    let mut helper = Command::new(SUDO_BIN)
+        .arg(GRUBENV_WRITE_BIN)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context(format!("failed to execute \"sudo {GRUBENV_WRITE_BIN}\""))?;
+
+    // stdin is closed when dropped, thus the helper finishes
+    helper
+        .stdin
+        .take()
+        .context("failed to get stdin of grubenv helper")?
+        .write_all(&block)
+        .context(format!("failed to write to \"sudo {GRUBENV_WRITE_BIN}\""))?;
+
     ensure!(
-        Command::new(SUDO_BIN)
-            .args([GRUB_EDITENV_BIN, GRUB_ENV_FILE, GRUB_CMD_SET, set.as_str()])
-            .status()
-            .context(format!("failed to call \"sudo grub-editenv set {set}\""))?
+        helper
+            .wait()
+            .context(format!("failed to wait for \"sudo {GRUBENV_WRITE_BIN}\""))?
             .success(),
-        "\"sudo grub-editenv set {set}\" failed"
+        "\"sudo {GRUBENV_WRITE_BIN}\" failed"
     );
 
     Ok(())
 }
 
-pub fn unset_bootloader_env(key: &str) -> Result<()> {
-    ensure!(
-        Command::new(SUDO_BIN)
-            .args([GRUB_EDITENV_BIN, GRUB_ENV_FILE, GRUB_CMD_UNSET, key])
-            .status()
-            .context(format!("failed to call \"sudo grub-editenv unset {key}\""))?
-            .success(),
-        "\"sudo grub-editenv unset {key}\" failed"
-    );
+pub fn bootloader_env(key: &str) -> Result<String> {
+    Ok(load()?.get(key).unwrap_or_default().to_string())
+}
 
-    Ok(())
+/// Applies all changes with a single write of the environment block, thus
+/// either all or none of them are visible to grub. `None` unsets a key.
+pub fn update_bootloader_env(changes: &[(&str, Option<&str>)]) -> Result<()> {
+    let _lock = GRUB_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let env = load()?;
+    let mut new_env = env.clone();
+
+    for (key, value) in changes {
+        match value {
+            Some(value) => new_env.set(key, value)?,
+            None => new_env.unset(key),
+        }
+    }
+
+    if new_env == env {
+        return Ok(());
+    }
+
+    store(&new_env)
+}
+
+pub fn set_bootloader_env(key: &str, value: &str) -> Result<()> {
+    update_bootloader_env(&[(key, Some(value))])
+}
+
+pub fn unset_bootloader_env(key: &str) -> Result<()> {
+    update_bootloader_env(&[(key, None)])
 }
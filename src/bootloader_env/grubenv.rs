@@ -0,0 +1,201 @@
+use anyhow::{Context, Result, ensure};
+
+static GRUBENV_HEADER: &[u8] = b"# GRUB Environment Block\n";
+// grub-editenv creates blocks of this size, but keeps the size of existing ones
+static GRUBENV_BLOCK_SIZE: usize = 1024;
+
+/// grub environment block as read and written by grub and grub-editenv: a
+/// header line followed by "key=value" lines, padded with '#' to the block
+/// size. Backslashes and newlines in values are escaped by a backslash.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrubEnv {
+    // grub-editenv keeps the order of variables and appends new ones
+    vars: Vec<(String, String)>,
+    size: usize,
+}
+
+impl Default for GrubEnv {
+    fn default() -> Self {
+        GrubEnv {
+            vars: vec![],
+            size: GRUBENV_BLOCK_SIZE,
+        }
+    }
+}
+
+impl GrubEnv {
+    pub fn parse(block: &[u8]) -> Result<Self> {
+        ensure!(block.starts_with(GRUBENV_HEADER), "grubenv: invalid header");
+
+        let mut vars = vec![];
+        let mut line = vec![];
+        let mut escaped = false;
+
+        for b in &block[GRUBENV_HEADER.len()..] {
+            match b {
+                _ if escaped => {
+                    line.push(*b);
+                    escaped = false;
+                }
+                b'\\' => escaped = true,
+                b'\n' => {
+                    Self::parse_line(&line, &mut vars)?;
+                    line.clear();
+                }
+                _ => line.push(*b),
+            }
+        }
+        // the padding ends up here
+        Self::parse_line(&line, &mut vars)?;
+
+        Ok(GrubEnv {
+            vars,
+            size: block.len(),
+        })
+    }
+
+    fn parse_line(line: &[u8], vars: &mut Vec<(String, String)>) -> Result<()> {
+        // grub ignores comments and lines without assignment
+        if line.starts_with(b"#") {
+            return Ok(());
+        }
+
+        let Some(pos) = line.iter().position(|b| *b == b'=') else {
+            return Ok(());
+        };
+
+        let key = String::from_utf8(line[..pos].to_vec()).context("grubenv: invalid key")?;
+        let value =
+            String::from_utf8(line[pos + 1..].to_vec()).context("grubenv: invalid value")?;
+
+        vars.push((key, value));
+        Ok(())
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut block = GRUBENV_HEADER.to_vec();
+
+        for (key, value) in &self.vars {
+            block.extend_from_slice(key.as_bytes());
+            block.push(b'=');
+            for b in value.bytes() {
+                if b == b'\\' || b == b'\n' {
+                    block.push(b'\\');
+                }
+                block.push(b);
+            }
+            block.push(b'\n');
+        }
+
+        ensure!(
+            block.len() <= self.size,
+            "grubenv: variables exceed block size of {} bytes",
+            self.size
+        );
+
+        block.resize(self.size, b'#');
+        Ok(block)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        ensure!(
+            !key.is_empty() && !key.contains(['=', '\n', '\\']) && !key.starts_with('#'),
+            "grubenv: invalid key {key}"
+        );
+
+        match self.vars.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.vars.push((key.to_string(), value.to_string())),
+        }
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) {
+        self.vars.retain(|(k, _)| k != key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(content: &str) -> Vec<u8> {
+        let mut block = content.as_bytes().to_vec();
+        block.resize(GRUBENV_BLOCK_SIZE, b'#');
+        block
+    }
+
+    #[test]
+    fn parse_grubenv_block() {
+        let env = GrubEnv::parse(&block(
+            "# GRUB Environment Block\n\
+             omnect_os_bootpart=2\n\
+             # comment=ignored\n\
+             no assignment\n\
+             omnect_extra_bootargs=console=ttyS0 loglevel=7\n\
+             escaped=a\\\\b\\\nc\n",
+        ))
+        .unwrap();
+
+        assert_eq!(env.get("omnect_os_bootpart"), Some("2"));
+        assert_eq!(
+            env.get("omnect_extra_bootargs"),
+            Some("console=ttyS0 loglevel=7")
+        );
+        assert_eq!(env.get("escaped"), Some("a\\b\nc"));
+        assert_eq!(env.get("comment"), None);
+        assert_eq!(env.vars.len(), 3);
+
+        assert!(GrubEnv::parse(b"# no GRUB Environment Block\n").is_err());
+
+        let mut invalid_utf8 = block("# GRUB Environment Block\nkey=x\n");
+        invalid_utf8[GRUBENV_HEADER.len() + 4] = 0xff;
+        assert!(GrubEnv::parse(&invalid_utf8).is_err());
+    }
+
+    #[test]
+    fn serialize_grubenv_block() {
+        let content = "# GRUB Environment Block\nomnect_os_bootpart=2\nescaped=a\\\\b\\\nc\n";
+        let mut env = GrubEnv::parse(&block(content)).unwrap();
+
+        // roundtrip keeps order, escapes and block size
+        assert_eq!(env.serialize().unwrap(), block(content));
+
+        env.set("omnect_validate_update", "1").unwrap();
+        env.set("omnect_os_bootpart", "3").unwrap();
+        env.unset("escaped");
+        env.unset("unknown");
+
+        assert_eq!(
+            env.serialize().unwrap(),
+            block("# GRUB Environment Block\nomnect_os_bootpart=3\nomnect_validate_update=1\n")
+        );
+
+        assert!(env.set("invalid=key", "value").is_err());
+        assert!(env.set("", "value").is_err());
+
+        // a bigger block keeps its size
+        let mut big = block(content);
+        big.resize(2 * GRUBENV_BLOCK_SIZE, b'#');
+        assert_eq!(
+            GrubEnv::parse(&big).unwrap().serialize().unwrap().len(),
+            2 * GRUBENV_BLOCK_SIZE
+        );
+
+        env.set("too_long", &"x".repeat(GRUBENV_BLOCK_SIZE))
+            .unwrap();
+        assert!(env.serialize().is_err());
+
+        assert_eq!(
+            GrubEnv::default().serialize().unwrap(),
+            block("# GRUB Environment Block\n")
+        );
+    }
+}
@@ -1,7 +1,11 @@
 #[cfg(feature = "bootloader_grub")]
 mod grub;
+#[cfg(any(feature = "bootloader_grub", test))]
+mod grubenv;
 #[cfg(feature = "bootloader_uboot")]
 mod uboot;
+#[cfg(any(feature = "bootloader_uboot", test))]
+mod ubootenv;
 
 #[cfg(any(feature = "bootloader_grub", feature = "bootloader_uboot"))]
 static SUDO_BIN: &str = "/usr/bin/sudo";
@@ -10,12 +14,12 @@ use anyhow::Result;
 #[cfg(feature = "bootloader_grub")]
 use grub::{
     bootloader_env as get_inner, set_bootloader_env as set_inner,
-    unset_bootloader_env as unset_inner,
+    unset_bootloader_env as unset_inner, update_bootloader_env as update_inner,
 };
 #[cfg(feature = "bootloader_uboot")]
 use uboot::{
     bootloader_env as get_inner, set_bootloader_env as set_inner,
-    unset_bootloader_env as unset_inner,
+    unset_bootloader_env as unset_inner, update_bootloader_env as update_inner,
 };
 
 #[cfg(not(any(feature = "bootloader_grub", feature = "bootloader_uboot")))]
@@ -67,6 +71,55 @@ pub fn unset(key: &str) -> Result<()> {
     }
 }
 
+/// Sets and unsets staged to be committed with a single write of the
+/// environment, thus the bootloader never sees a part of them only.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    // applied in order, thus the last change of a key wins
+    changes: Vec<(String, Option<String>)>,
+}
+
+impl Transaction {
+    pub fn set(&mut self, key: &str, value: &str) -> &mut Self {
+        self.changes
+            .push((key.to_string(), Some(value.to_string())));
+        self
+    }
+
+    pub fn unset(&mut self, key: &str) -> &mut Self {
+        self.changes.push((key.to_string(), None));
+        self
+    }
+
+    #[allow(unreachable_code)]
+    pub fn commit(self) -> Result<()> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+
+        #[cfg(any(feature = "bootloader_grub", feature = "bootloader_uboot"))]
+        return update_inner(
+            &self
+                .changes
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_deref()))
+                .collect::<Vec<_>>(),
+        );
+
+        #[cfg(not(any(feature = "bootloader_grub", feature = "bootloader_uboot")))]
+        {
+            let mut guard = mock_store::store();
+            for (key, value) in self.changes {
+                match value {
+                    Some(value) => guard.insert(key, value),
+                    None => guard.remove(&key),
+                };
+            }
+            Ok(())
+        }
+    }
+}
+
 /// Clears the mock store. Call this at the start of each test that uses
 /// bootloader_env, to prevent state leaking between tests.
 #[cfg(all(
@@ -83,3 +136,33 @@ pub(crate) fn clear_mock() {
 /// this to prevent concurrent mutation.
 #[cfg(test)]
 pub static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(all(
+    test,
+    not(any(feature = "bootloader_grub", feature = "bootloader_uboot"))
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transaction_commits_all_changes() {
+        let _lock = TEST_LOCK.lock().unwrap();
+        clear_mock();
+        set("unset_me", "1").unwrap();
+
+        let mut tx = Transaction::default();
+        tx.set("key", "old").unset("unset_me").set("key", "new");
+
+        // nothing is visible before commit
+        assert_eq!(get("unset_me").unwrap(), "1");
+        assert!(get("key").unwrap().is_empty());
+
+        tx.commit().unwrap();
+
+        assert_eq!(get("key").unwrap(), "new");
+        assert!(get("unset_me").unwrap().is_empty());
+
+        Transaction::default().commit().unwrap();
+        assert_eq!(get("key").unwrap(), "new");
+    }
+}
@@ -1,55 +1,124 @@
-use anyhow::{Context, Result, bail, ensure};
-use std::process::Command;
+use anyhow::{Context, Result, ensure};
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
 
-use super::SUDO_BIN;
+use super::{
+    SUDO_BIN,
+    ubootenv::{EnvArea, UbootEnv, parse_config},
+};
 
-static FW_PRINTENV_BIN: &str = "/usr/bin/fw_printenv";
-static FW_SETENV_NO_SCRIPT_BIN: &str = "/usr/bin/fw_setenv_no_script.sh";
-static FW_SETENV_BIN: &str = "/usr/bin/fw_setenv";
+static FW_ENV_CONFIG: &str = "/etc/fw_env.config";
+static UBOOTENV_IO_BIN: &str = "/usr/bin/ubootenv_io.sh";
 
-pub fn bootloader_env(key: &str) -> Result<String> {
-    let value = Command::new(SUDO_BIN)
-        .arg(FW_PRINTENV_BIN)
-        .arg(key)
-        .output()?;
-    if !value.status.success() {
-        bail!("fw_printenv {key} failed");
-    }
-    let value = String::from_utf8(value.stdout)?;
-    let mut value = value
-        .split_once('=')
-        .context(format!("failed to get {key}'s value"))?
-        .1
-        .trim()
-        .to_string();
-    let len = value.trim_end_matches(&['\r', '\n'][..]).len();
-    value.truncate(len);
+// serializes read-modify-write cycles of the environment
+static UBOOT_ENV_LOCK: Mutex<()> = Mutex::new(());
 
-    Ok(value)
+fn areas() -> Result<Vec<EnvArea>> {
+    parse_config(
+        &fs::read_to_string(FW_ENV_CONFIG).context(format!("failed to read {FW_ENV_CONFIG}"))?,
+    )
 }
 
-pub fn set_bootloader_env(key: &str, value: &str) -> Result<()> {
+// the environment is only accessible by root, thus it is accessed by a helper
+// that is restricted to the areas configured in fw_env.config
+fn io_args(cmd: &str, area: &EnvArea) -> [String; 5] {
+    [
+        UBOOTENV_IO_BIN.to_string(),
+        cmd.to_string(),
+        area.device.clone(),
+        format!("{:#x}", area.offset),
+        format!("{:#x}", area.size),
+    ]
+}
+
+fn read_area(area: &EnvArea) -> Result<Vec<u8>> {
+    let args = io_args("read", area);
+    let output = Command::new(SUDO_BIN)
+        .args(&args)
+        .output()
+        .context(format!("failed to execute \"sudo {}\"", args.join(" ")))?;
+
     ensure!(
-        Command::new(SUDO_BIN)
-            .args([FW_SETENV_NO_SCRIPT_BIN, key, value])
-            .status()
-            .context(format!("failed to execute 'fw_setenv {key} {value}'"))?
-            .success(),
-        "\"fw_setenv {key} {value}\" failed"
+        output.status.success(),
+        "\"sudo {}\" failed",
+        args.join(" ")
     );
 
-    Ok(())
+    Ok(output.stdout)
 }
 
-pub fn unset_bootloader_env(key: &str) -> Result<()> {
+fn write_area(area: &EnvArea, data: &[u8]) -> Result<()> {
+    let args = io_args("write", area);
+    let mut helper = Command::new(SUDO_BIN)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context(format!("failed to execute \"sudo {}\"", args.join(" ")))?;
+
+    // stdin is closed when dropped, thus the helper finishes
+    helper
+        .stdin
+        .take()
+        .context("failed to get stdin of u-boot env helper")?
+        .write_all(data)
+        .context(format!("failed to write to \"sudo {}\"", args.join(" ")))?;
+
     ensure!(
-        Command::new(SUDO_BIN)
-            .args([FW_SETENV_BIN, key])
-            .status()
-            .context(format!("failed to execute \"fw_setenv {key}\""))?
+        helper
+            .wait()
+            .context(format!("failed to wait for \"sudo {}\"", args.join(" ")))?
             .success(),
-        "\"fw_setenv {key}\" failed"
+        "\"sudo {}\" failed",
+        args.join(" ")
     );
 
     Ok(())
 }
+
+fn load(areas: &[EnvArea]) -> Result<UbootEnv> {
+    let copies = areas
+        .iter()
+        .map(read_area)
+        .collect::<Result<Vec<Vec<u8>>>>()?;
+
+    UbootEnv::parse(&copies)
+}
+
+pub fn bootloader_env(key: &str) -> Result<String> {
+    Ok(load(&areas()?)?.get(key).unwrap_or_default().to_string())
+}
+
+/// Applies all changes with a single write of the environment, thus either
+/// all or none of them are visible to u-boot. `None` unsets a key.
+pub fn update_bootloader_env(changes: &[(&str, Option<&str>)]) -> Result<()> {
+    let _lock = UBOOT_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let areas = areas()?;
+    let env = load(&areas)?;
+    let mut new_env = env.clone();
+
+    for (key, value) in changes {
+        match value {
+            Some(value) => new_env.set(key, value)?,
+            None => new_env.unset(key),
+        }
+    }
+
+    if new_env == env {
+        return Ok(());
+    }
+
+    let (index, data) = new_env.serialize()?;
+    write_area(&areas[index], &data)
+}
+
+pub fn set_bootloader_env(key: &str, value: &str) -> Result<()> {
+    update_bootloader_env(&[(key, Some(value))])
+}
+
+pub fn unset_bootloader_env(key: &str) -> Result<()> {
+    update_bootloader_env(&[(key, None)])
+}
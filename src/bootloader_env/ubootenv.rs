@@ -0,0 +1,333 @@
+use anyhow::{Context, Result, bail, ensure};
+use std::collections::BTreeMap;
+
+const CRC_LEN: usize = 4;
+const FLAG_LEN: usize = 1;
+
+/// One copy of the environment as configured in fw_env.config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvArea {
+    pub device: String,
+    pub offset: u64,
+    pub size: usize,
+}
+
+/// Parses fw_env.config: "<device> <offset> <env size> [<sector size> [<sectors>]]"
+/// per line. A second line configures a redundant environment.
+pub fn parse_config(config: &str) -> Result<Vec<EnvArea>> {
+    let mut areas = vec![];
+
+    for line in config.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.is_empty() {
+            continue;
+        }
+
+        ensure!(fields.len() >= 3, "fw_env.config: invalid line \"{line}\"");
+
+        areas.push(EnvArea {
+            device: fields[0].to_string(),
+            offset: parse_number(fields[1])?,
+            size: parse_number(fields[2])? as usize,
+        });
+    }
+
+    ensure!(
+        matches!(areas.len(), 1 | 2),
+        "fw_env.config: expected one or two environments, found {}",
+        areas.len()
+    );
+    ensure!(
+        areas.iter().all(|a| a.size == areas[0].size),
+        "fw_env.config: sizes of redundant environments differ"
+    );
+
+    Ok(areas)
+}
+
+fn parse_number(number: &str) -> Result<u64> {
+    match number
+        .strip_prefix("0x")
+        .or_else(|| number.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => number.parse(),
+    }
+    .context(format!("fw_env.config: invalid number {number}"))
+}
+
+/// u-boot environment: a CRC32 of the data, in case of a redundant
+/// environment followed by a flag byte, and "key=value" entries terminated by
+/// '\0'. The end of the entries is marked by an additional '\0'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UbootEnv {
+    vars: BTreeMap<String, String>,
+    size: usize,
+    // index and flag of the current copy of a redundant environment
+    redundant: Option<(usize, u8)>,
+}
+
+impl UbootEnv {
+    /// Parses the content of all configured copies. Of a redundant environment
+    /// the copy with valid CRC and the newer flag is used.
+    pub fn parse(copies: &[Vec<u8>]) -> Result<Self> {
+        let header_len = match copies.len() {
+            1 => CRC_LEN,
+            2 => CRC_LEN + FLAG_LEN,
+            n => bail!("u-boot env: invalid number of copies {n}"),
+        };
+        let size = copies[0].len();
+
+        ensure!(
+            size > header_len && copies.iter().all(|c| c.len() == size),
+            "u-boot env: invalid size"
+        );
+
+        let valid: Vec<bool> = copies.iter().map(|c| crc_valid(c, header_len)).collect();
+        let current = match valid[..] {
+            [true] | [true, false] => 0,
+            [false, true] => 1,
+            [true, true] if second_is_current(copies[0][CRC_LEN], copies[1][CRC_LEN]) => 1,
+            [true, true] => 0,
+            _ => bail!("u-boot env: bad CRC"),
+        };
+
+        Ok(UbootEnv {
+            vars: parse_vars(&copies[current][header_len..])?,
+            size,
+            redundant: (copies.len() == 2).then_some((current, copies[current][CRC_LEN])),
+        })
+    }
+
+    /// Returns the index of the copy to be written and its content. The copy
+    /// of a redundant environment that is not current is written, thus the
+    /// current one stays valid until the new one was written completely.
+    pub fn serialize(&self) -> Result<(usize, Vec<u8>)> {
+        let (index, mut block) = match self.redundant {
+            None => (0, vec![0; CRC_LEN]),
+            Some((current, flag)) => {
+                let mut block = vec![0; CRC_LEN];
+                block.push(flag.wrapping_add(1));
+                (1 - current, block)
+            }
+        };
+        let header_len = block.len();
+
+        for (key, value) in &self.vars {
+            block.extend_from_slice(key.as_bytes());
+            block.push(b'=');
+            block.extend_from_slice(value.as_bytes());
+            block.push(0);
+        }
+        block.push(0);
+
+        ensure!(
+            block.len() <= self.size,
+            "u-boot env: variables exceed environment size of {} bytes",
+            self.size
+        );
+
+        block.resize(self.size, 0);
+
+        let crc = crc32(&block[header_len..]);
+        block[..CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        Ok((index, block))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        ensure!(
+            !key.is_empty() && !key.contains(['=', '\0']) && !value.contains('\0'),
+            "u-boot env: invalid variable {key}"
+        );
+
+        self.vars.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) {
+        self.vars.remove(key);
+    }
+}
+
+fn parse_vars(data: &[u8]) -> Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+
+    for entry in data.split(|b| *b == 0) {
+        if entry.is_empty() {
+            break;
+        }
+
+        let entry = std::str::from_utf8(entry).context("u-boot env: invalid entry")?;
+        let (key, value) = entry
+            .split_once('=')
+            .context(format!("u-boot env: invalid entry \"{entry}\""))?;
+
+        vars.insert(key.to_string(), value.to_string());
+    }
+
+    Ok(vars)
+}
+
+fn crc_valid(copy: &[u8], header_len: usize) -> bool {
+    let mut crc = [0; CRC_LEN];
+    crc.copy_from_slice(&copy[..CRC_LEN]);
+    u32::from_le_bytes(crc) == crc32(&copy[header_len..])
+}
+
+// flags are incremented on every write, so the higher one is current except
+// if the flag wrapped around (same as fw_setenv for block devices)
+fn second_is_current(first_flag: u8, second_flag: u8) -> bool {
+    match (first_flag, second_flag) {
+        (0xff, 0) => true,
+        (0, 0xff) => false,
+        (first, second) => second > first,
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn copies(files: &[&str]) -> Vec<Vec<u8>> {
+        files
+            .iter()
+            .map(|f| fs::read(format!("testfiles/positive/uboot_env/{f}")).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_fw_env_config() {
+        let areas = parse_config(
+            &fs::read_to_string("testfiles/positive/uboot_env/fw_env.config").unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            areas,
+            vec![
+                EnvArea {
+                    device: "/dev/mmcblk0".to_string(),
+                    offset: 0x400000,
+                    size: 0x1000
+                },
+                EnvArea {
+                    device: "/dev/mmcblk0".to_string(),
+                    offset: 0x404000,
+                    size: 0x1000
+                }
+            ]
+        );
+
+        assert!(parse_config("").is_err());
+        assert!(parse_config("/dev/mmcblk0 0x400000").is_err());
+        assert!(
+            parse_config("/dev/mmcblk0 0x400000 0x1000\n/dev/mmcblk0 0x404000 0x2000").is_err()
+        );
+        assert!(parse_config("/dev/mmcblk0 offset 0x1000").is_err());
+        assert_eq!(
+            parse_config("/dev/mmcblk0boot1 8192 4096 # single").unwrap()[0].size,
+            4096
+        );
+    }
+
+    #[test]
+    fn parse_single_env() {
+        let env = UbootEnv::parse(&copies(&["env.bin"])).unwrap();
+
+        assert_eq!(env.get("omnect_os_bootpart"), Some("2"));
+        assert_eq!(env.get("bootargs"), Some("console=ttyS0,115200 rootwait"));
+        assert_eq!(env.get("unknown"), None);
+        assert_eq!(env.redundant, None);
+
+        let mut corrupted = copies(&["env.bin"]);
+        corrupted[0][CRC_LEN] ^= 0xff;
+        assert!(UbootEnv::parse(&corrupted).is_err());
+    }
+
+    #[test]
+    fn parse_redundant_env() {
+        let mut redundant = copies(&["env_redundant_0.bin", "env_redundant_1.bin"]);
+        let env = UbootEnv::parse(&redundant).unwrap();
+
+        // the second copy has the newer flag
+        assert_eq!(env.get("omnect_os_bootpart"), Some("3"));
+        assert_eq!(env.redundant, Some((1, 2)));
+
+        // fall back to the older copy if the newer one is corrupted
+        redundant[1][CRC_LEN + FLAG_LEN] ^= 0xff;
+        let env = UbootEnv::parse(&redundant).unwrap();
+        assert_eq!(env.get("omnect_os_bootpart"), Some("2"));
+        assert_eq!(env.redundant, Some((0, 1)));
+
+        assert!(second_is_current(0xff, 0));
+        assert!(!second_is_current(0, 0xff));
+        assert!(!second_is_current(1, 1));
+    }
+
+    #[test]
+    fn serialize_env() {
+        let single = copies(&["env.bin"]);
+        let mut env = UbootEnv::parse(&single).unwrap();
+
+        // roundtrip
+        assert_eq!(env.serialize().unwrap(), (0, single[0].clone()));
+
+        env.set("omnect_validate_update", "1").unwrap();
+        env.set("omnect_os_bootpart", "3").unwrap();
+        env.unset("bootargs");
+
+        let (index, block) = env.serialize().unwrap();
+        assert_eq!(index, 0);
+        let env = UbootEnv::parse(&[block]).unwrap();
+        assert_eq!(env.get("omnect_validate_update"), Some("1"));
+        assert_eq!(env.get("omnect_os_bootpart"), Some("3"));
+        assert_eq!(env.get("bootargs"), None);
+
+        // the copy that is not current is written with incremented flag
+        let mut redundant = copies(&["env_redundant_0.bin", "env_redundant_1.bin"]);
+        let mut env = UbootEnv::parse(&redundant).unwrap();
+        env.set("omnect_os_bootpart", "2").unwrap();
+
+        let (index, block) = env.serialize().unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(block[CRC_LEN], 3);
+
+        redundant[index] = block;
+        let env = UbootEnv::parse(&redundant).unwrap();
+        assert_eq!(env.get("omnect_os_bootpart"), Some("2"));
+        assert_eq!(env.redundant, Some((0, 3)));
+
+        let mut env = env;
+        assert!(env.set("invalid=key", "value").is_err());
+        env.set("too_long", &"x".repeat(0x1000)).unwrap();
+        assert!(env.serialize().is_err());
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
@@ -115,6 +115,34 @@ where
     .context(format!("failed to deserialize json from: {path:?}"))
 }
 
+/// Merges `patch` into `target` as described by RFC 7396. If `keep_null` is
+/// set null values are merged like any other value instead of removing the
+/// key, which is needed to combine two patches into one.
+pub fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value, keep_null: bool) {
+    let Some(patch) = patch.as_object() else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+
+    let target = target.as_object_mut().expect("object");
+
+    for (key, value) in patch {
+        if value.is_null() && !keep_null {
+            target.remove(key);
+        } else {
+            merge_patch(
+                target.entry(key.clone()).or_insert(serde_json::Value::Null),
+                value,
+                keep_null,
+            );
+        }
+    }
+}
+
 pub fn path_ends_with<P>(path: P, end: &str) -> bool
 where
     P: AsRef<std::path::Path>,
@@ -1,6 +1,8 @@
 pub mod bootloader_env;
 pub mod common;
 pub mod logging;
+pub mod reboot_guard;
+pub mod reboot_inhibitor;
 pub mod reboot_reason;
 pub mod systemd;
 pub mod twin;
@@ -0,0 +1,370 @@
+use crate::{
+    common::{from_json_file, to_json_file},
+    reboot_reason::{self, Boot},
+};
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{env, path::Path, time::Duration};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+macro_rules! reboot_safe_mode_file_path {
+    () => {
+        env::var("REBOOT_SAFE_MODE_PATH")
+            .unwrap_or("/var/lib/omnect-device-service/reboot_safe_mode.json".to_string())
+    };
+}
+
+// reason of reboots requested via the reboot feature
+static ODS_REBOOT_REASON: &str = "ods-reboot";
+
+/// At most `count` boots within `window`, a count of 0 disables the limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Limit {
+    count: usize,
+    window: Duration,
+}
+
+impl Limit {
+    fn from_env(
+        count_var: &str,
+        count_default: usize,
+        window_var: &str,
+        window_default: u64,
+    ) -> Self {
+        Limit {
+            count: env::var(count_var)
+                .ok()
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(count_default),
+            window: Duration::from_secs(
+                env::var(window_var)
+                    .ok()
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(window_default),
+            ),
+        }
+    }
+
+    fn rate() -> Self {
+        Limit::from_env(
+            "REBOOT_RATE_LIMIT_COUNT",
+            5,
+            "REBOOT_RATE_LIMIT_WINDOW_SECS",
+            3600,
+        )
+    }
+
+    fn reboot_loop() -> Self {
+        Limit::from_env("REBOOT_LOOP_COUNT", 5, "REBOOT_LOOP_WINDOW_SECS", 1800)
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.count > 0
+    }
+
+    /// Number of boots matching `filter` since `now - window`.
+    fn boots_in_window(
+        &self,
+        boots: &[Boot],
+        now: OffsetDateTime,
+        filter: impl Fn(&Boot) -> bool,
+    ) -> usize {
+        let since = now - self.window;
+
+        boots
+            .iter()
+            .filter(|boot| filter(boot))
+            .filter(|boot| {
+                boot.boot_time
+                    .as_ref()
+                    .and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok())
+                    .is_some_and(|t| since <= t)
+            })
+            .count()
+    }
+}
+
+/// While in safe mode reboots are refused until an operator clears it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SafeMode {
+    pub since: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+struct State {
+    safe_mode: Option<SafeMode>,
+    // boots up to this one were seen by the operator who cleared safe mode
+    cleared_boot_id: Option<String>,
+}
+
+impl State {
+    fn load() -> Self {
+        let path = reboot_safe_mode_file_path!();
+
+        if !Path::new(&path).exists() {
+            return State::default();
+        }
+
+        from_json_file(&path).unwrap_or_else(|e| {
+            error!("ignore invalid reboot safe mode: {e:#}");
+            State::default()
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        to_json_file(self, reboot_safe_mode_file_path!(), true)
+    }
+}
+
+/// Returned if a reboot was requested while in safe mode.
+#[derive(Debug)]
+pub struct RebootSafeModeError {
+    pub reason: String,
+}
+
+impl std::fmt::Display for RebootSafeModeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "reboot refused in safe mode: {}", self.reason)
+    }
+}
+
+impl std::error::Error for RebootSafeModeError {}
+
+/// Returned if a reboot was requested although the reboot rate limit was
+/// reached.
+#[derive(Debug)]
+pub struct RebootRateLimitedError {
+    pub count: usize,
+    pub window_secs: u64,
+}
+
+impl std::fmt::Display for RebootRateLimitedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "reboot refused: limit of {} reboots within {}s reached",
+            self.count, self.window_secs
+        )
+    }
+}
+
+impl std::error::Error for RebootRateLimitedError {}
+
+// boots not requested by anyone, e.g. a reboot or an update must not be
+// mistaken for a reboot loop
+fn is_unrequested(boot: &Boot) -> bool {
+    boot.reason == "power-loss" || boot.reason.contains("watchdog") || boot.reason.contains("panic")
+}
+
+/// Detects a reboot loop by the unrequested boots since `cleared_boot_id`,
+/// `boots` newest first. The window ends with the current boot, since the
+/// clock might not be synchronized yet.
+fn detect_reboot_loop(
+    boots: &[Boot],
+    cleared_boot_id: Option<&str>,
+    limit: Limit,
+) -> Option<String> {
+    if !limit.is_enabled() {
+        return None;
+    }
+
+    let cleared = boots
+        .iter()
+        .position(|boot| Some(boot.boot_id.as_str()) == cleared_boot_id)
+        .unwrap_or(boots.len());
+    let boots = &boots[..cleared];
+    let current = boots.first()?;
+    let boot_time = OffsetDateTime::parse(current.boot_time.as_ref()?, &Rfc3339).ok()?;
+    let count = limit.boots_in_window(boots, boot_time, is_unrequested);
+
+    (count >= limit.count).then(|| {
+        format!(
+            "reboot loop detected: {count} boots within {}s, last reason {}",
+            limit.window.as_secs(),
+            current.reason
+        )
+    })
+}
+
+fn ensure_rate(boots: &[Boot], now: OffsetDateTime, limit: Limit) -> Result<()> {
+    if !limit.is_enabled() {
+        return Ok(());
+    }
+
+    let count = limit.boots_in_window(boots, now, |boot| boot.reason == ODS_REBOOT_REASON);
+
+    if count >= limit.count {
+        return Err(RebootRateLimitedError {
+            count: limit.count,
+            window_secs: limit.window.as_secs(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Enters safe mode if the reboot reason history shows a reboot loop. Returns
+/// the current safe mode.
+pub fn check_reboot_loop() -> Option<SafeMode> {
+    let mut state = State::load();
+
+    if state.safe_mode.is_some() {
+        return state.safe_mode;
+    }
+
+    let reason = detect_reboot_loop(
+        &reboot_reason::boots()?,
+        state.cleared_boot_id.as_deref(),
+        Limit::reboot_loop(),
+    )?;
+
+    error!("enter reboot safe mode: {reason}");
+
+    state.safe_mode = Some(SafeMode {
+        since: OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .inspect_err(|e| warn!("check_reboot_loop: failed to format time: {e:#}"))
+            .unwrap_or_default(),
+        reason,
+    });
+
+    if let Err(e) = state.save() {
+        error!("check_reboot_loop: failed to persist safe mode: {e:#}");
+    }
+
+    state.safe_mode
+}
+
+pub fn safe_mode() -> Option<SafeMode> {
+    State::load().safe_mode
+}
+
+/// Leaves safe mode. Boots so far are not considered by the reboot loop
+/// detection anymore. Returns false if not in safe mode.
+pub fn clear_safe_mode() -> Result<bool> {
+    let mut state = State::load();
+
+    if state.safe_mode.take().is_none() {
+        return Ok(false);
+    }
+
+    state.cleared_boot_id = reboot_reason::boots()
+        .and_then(|boots| boots.into_iter().next())
+        .map(|boot| boot.boot_id);
+    state.save().context("failed to clear reboot safe mode")?;
+
+    info!("reboot safe mode cleared");
+
+    Ok(true)
+}
+
+/// Fails with `RebootSafeModeError` while in safe mode and with
+/// `RebootRateLimitedError` if too many reboots were requested recently.
+/// Only requested reboots are limited, reboots of firmware updates, e.g. to
+/// recover from a failed update, must not be prevented.
+pub fn ensure_reboot_allowed() -> Result<()> {
+    if let Some(safe_mode) = safe_mode() {
+        return Err(RebootSafeModeError {
+            reason: safe_mode.reason,
+        }
+        .into());
+    }
+
+    ensure_rate(
+        &reboot_reason::boots().unwrap_or_default(),
+        OffsetDateTime::now_utc(),
+        Limit::rate(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boot(boot_id: &str, reason: &str, boot_time: &str) -> Boot {
+        Boot {
+            boot_id: boot_id.to_string(),
+            reason: reason.to_string(),
+            extra_info: String::new(),
+            os_version: "4.0.26.603211040".to_string(),
+            boot_time: Some(boot_time.to_string()),
+            reason_time: None,
+        }
+    }
+
+    fn limit(count: usize, window_secs: u64) -> Limit {
+        Limit {
+            count,
+            window: Duration::from_secs(window_secs),
+        }
+    }
+
+    #[test]
+    fn reboot_loop_detection() {
+        // newest first
+        let boots = vec![
+            boot("4", "systemd-watchdog", "2026-10-16T12:20:00Z"),
+            boot("3", "kernel-panic", "2026-10-16T12:10:00Z"),
+            boot("2", "power-loss", "2026-10-16T12:00:00Z"),
+            boot("1", "power-loss", "2026-10-15T12:00:00Z"),
+        ];
+
+        assert_eq!(
+            detect_reboot_loop(&boots, None, limit(3, 1800)),
+            Some(
+                "reboot loop detected: 3 boots within 1800s, last reason systemd-watchdog"
+                    .to_string()
+            )
+        );
+        assert_eq!(detect_reboot_loop(&boots, None, limit(4, 1800)), None);
+        assert_eq!(detect_reboot_loop(&boots, None, limit(3, 1000)), None);
+        assert_eq!(detect_reboot_loop(&boots, None, limit(0, 1800)), None);
+
+        // boots up to the cleared one don't count anymore
+        assert_eq!(detect_reboot_loop(&boots, Some("3"), limit(3, 1800)), None);
+        assert_eq!(detect_reboot_loop(&boots, Some("4"), limit(1, 1800)), None);
+        assert_eq!(detect_reboot_loop(&[], None, limit(1, 1800)), None);
+
+        // requested boots, e.g. reboots and updates, are no reboot loop
+        let boots = vec![
+            boot("4", "ods-reboot", "2026-10-16T12:20:00Z"),
+            boot("3", "swupdate", "2026-10-16T12:10:00Z"),
+            boot("2", "systemd-watchdog", "2026-10-16T12:05:00Z"),
+            boot("1", "ods-reboot", "2026-10-16T12:00:00Z"),
+        ];
+
+        assert_eq!(detect_reboot_loop(&boots, None, limit(2, 1800)), None);
+        assert!(detect_reboot_loop(&boots, None, limit(1, 1800)).is_some());
+    }
+
+    #[test]
+    fn reboot_rate_limit() {
+        let now = OffsetDateTime::parse("2026-10-16T12:30:00Z", &Rfc3339).unwrap();
+        let boots = vec![
+            boot("3", "ods-reboot", "2026-10-16T12:20:00Z"),
+            boot("2", "swupdate", "2026-10-16T12:10:00Z"),
+            boot("1", "ods-reboot", "2026-10-16T11:00:00Z"),
+        ];
+
+        assert!(ensure_rate(&boots, now, limit(2, 3600)).is_ok());
+        assert!(ensure_rate(&boots, now, limit(0, 3600)).is_ok());
+
+        let err = ensure_rate(&boots, now, limit(2, 7200)).unwrap_err();
+        assert!(err.is::<RebootRateLimitedError>());
+        assert_eq!(
+            err.to_string(),
+            "reboot refused: limit of 2 reboots within 7200s reached"
+        );
+
+        assert_eq!(
+            RebootSafeModeError {
+                reason: "reboot loop detected".to_string()
+            }
+            .to_string(),
+            "reboot refused in safe mode: reboot loop detected"
+        );
+    }
+}
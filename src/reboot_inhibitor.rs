@@ -0,0 +1,227 @@
+use crate::{
+    common::{from_json_file, to_json_file},
+    systemd::logind,
+};
+use anyhow::{Context, Result, ensure};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, path::Path, sync::Mutex, time::Duration};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+// registrations don't survive a reboot
+macro_rules! reboot_inhibitors_file_path {
+    () => {
+        env::var("REBOOT_INHIBITORS_PATH")
+            .unwrap_or("/run/omnect-device-service/reboot_inhibitors.json".to_string())
+    };
+}
+
+static REBOOT_INHIBITORS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Registration of an application that must not be interrupted by a reboot,
+/// e.g. while it writes data. Without timeout it lasts until it is removed.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RebootInhibitorRequest {
+    pub id: String,
+    pub why: String,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Registration {
+    why: String,
+    until: Option<String>,
+}
+
+impl Registration {
+    fn is_active(&self, now: OffsetDateTime) -> bool {
+        self.until.as_ref().is_none_or(|until| {
+            OffsetDateTime::parse(until, &Rfc3339).is_ok_and(|until| now < until)
+        })
+    }
+}
+
+type Registrations = BTreeMap<String, Registration>;
+
+/// Returned if a reboot was requested without `force` while it is inhibited.
+#[derive(Debug)]
+pub struct RebootInhibitedError {
+    pub by: Vec<String>,
+}
+
+impl std::fmt::Display for RebootInhibitedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "reboot inhibited by {}", self.by.join(", "))
+    }
+}
+
+impl std::error::Error for RebootInhibitedError {}
+
+fn load() -> Registrations {
+    let path = reboot_inhibitors_file_path!();
+
+    if !Path::new(&path).exists() {
+        return Registrations::default();
+    }
+
+    from_json_file(&path).unwrap_or_else(|e| {
+        error!("ignore invalid reboot inhibitors: {e:#}");
+        Registrations::default()
+    })
+}
+
+fn save(registrations: &Registrations) -> Result<()> {
+    to_json_file(registrations, reboot_inhibitors_file_path!(), true)
+}
+
+pub fn register(request: &RebootInhibitorRequest) -> Result<()> {
+    ensure!(!request.id.is_empty(), "id must not be empty");
+
+    let _lock = REBOOT_INHIBITORS_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let now = OffsetDateTime::now_utc();
+    let mut registrations = load();
+
+    registrations.retain(|_, r| r.is_active(now));
+    registrations.insert(
+        request.id.clone(),
+        Registration {
+            why: request.why.clone(),
+            until: request
+                .timeout_secs
+                .map(|secs| (now + Duration::from_secs(secs)).format(&Rfc3339))
+                .transpose()
+                .context("failed to format timeout")?,
+        },
+    );
+
+    save(&registrations)?;
+
+    info!("reboot inhibited by {}: {}", request.id, request.why);
+
+    Ok(())
+}
+
+pub fn unregister(id: &str) -> Result<()> {
+    let _lock = REBOOT_INHIBITORS_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let mut registrations = load();
+
+    if registrations.remove(id).is_some() {
+        save(&registrations)?;
+        info!("reboot no longer inhibited by {id}");
+    }
+
+    Ok(())
+}
+
+fn blocking(
+    inhibitors: &[logind::Inhibitor],
+    registrations: &Registrations,
+    now: OffsetDateTime,
+) -> Vec<String> {
+    inhibitors
+        .iter()
+        .filter(|i| i.blocks_reboot())
+        .map(|i| format!("{} ({})", i.who, i.why))
+        .chain(
+            registrations
+                .iter()
+                .filter(|(_, r)| r.is_active(now))
+                .map(|(id, r)| format!("{id} ({})", r.why)),
+        )
+        .collect()
+}
+
+/// Fails with `RebootInhibitedError` if systemd-logind holds a blocking
+/// shutdown lock or an application registered as reboot inhibitor, unless
+/// the reboot is forced.
+pub async fn ensure_reboot_allowed(force: bool) -> Result<()> {
+    let inhibitors = logind::inhibitors().await.unwrap_or_else(|e| {
+        warn!("ensure_reboot_allowed: ignore logind inhibitors: {e:#}");
+        vec![]
+    });
+    let by = blocking(&inhibitors, &load(), OffsetDateTime::now_utc());
+
+    if by.is_empty() {
+        return Ok(());
+    }
+
+    if force {
+        warn!("reboot forced although inhibited by {}", by.join(", "));
+        return Ok(());
+    }
+
+    Err(RebootInhibitedError { by }.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(s, &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn blocking_inhibitors() {
+        let now = utc("2026-10-16T12:00:00Z");
+        let inhibitors = vec![
+            logind::Inhibitor {
+                what: "shutdown:sleep".to_string(),
+                who: "backup".to_string(),
+                why: "backup in progress".to_string(),
+                mode: "block".to_string(),
+            },
+            logind::Inhibitor {
+                what: "shutdown".to_string(),
+                who: "NetworkManager".to_string(),
+                why: "cleanup".to_string(),
+                mode: "delay".to_string(),
+            },
+        ];
+        let registrations = Registrations::from([
+            (
+                "my-app".to_string(),
+                Registration {
+                    why: "writing data".to_string(),
+                    until: None,
+                },
+            ),
+            (
+                "expired".to_string(),
+                Registration {
+                    why: "done".to_string(),
+                    until: Some("2026-10-16T11:59:59Z".to_string()),
+                },
+            ),
+            (
+                "other-app".to_string(),
+                Registration {
+                    why: "calibrating".to_string(),
+                    until: Some("2026-10-16T12:00:01Z".to_string()),
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            blocking(&inhibitors, &registrations, now),
+            vec![
+                "backup (backup in progress)",
+                "my-app (writing data)",
+                "other-app (calibrating)"
+            ]
+        );
+        assert!(blocking(&[], &Registrations::default(), now).is_empty());
+
+        assert_eq!(
+            RebootInhibitedError {
+                by: vec!["my-app (writing data)".to_string()]
+            }
+            .to_string(),
+            "reboot inhibited by my-app (writing data)"
+        );
+    }
+}
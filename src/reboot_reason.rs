@@ -2,12 +2,18 @@ use crate::common::from_json_file;
 use anyhow::{Context, Result};
 use log::warn;
 use regex_lite::Regex;
-use std::env;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 
 #[cfg(not(feature = "mock"))]
 static REBOOT_REASON_SCRIPT: &str = "/usr/sbin/omnect_reboot_reason.sh";
 static REBOOT_REASON_DIR_REGEX: &str = r"^\d{6}\+\d{4}-\d{2}-\d{2}_\d{2}-\d{2}-\d{2}$";
 static REBOOT_REASON_FILE_NAME: &str = "reboot-reason.json";
+static REBOOT_REASON_HISTORY_LEN_DEFAULT: usize = 10;
 
 macro_rules! reboot_reason_dir_path {
     () => {
@@ -48,37 +54,178 @@ pub fn write_reboot_reason(reason: &str, extra_info: &str) -> Result<()> {
     Ok(())
 }
 
+/// Content of a reboot-reason.json as written by omnect_reboot_reason.sh on
+/// every boot: `report` describes the boot, `reboot_reason` the reason of
+/// the preceding shutdown.
+#[derive(Debug, Deserialize)]
+struct RebootReasonFile {
+    report: RebootReasonFileEntry,
+    reboot_reason: RebootReasonFileEntry,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RebootReasonFileEntry {
+    timeepoch: String,
+    boot_id: String,
+    os_version: String,
+    reason: String,
+    extra_info: String,
+}
+
+/// One boot of the reboot reason history, timestamps are RFC 3339.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Boot {
+    pub boot_id: String,
+    pub reason: String,
+    pub extra_info: String,
+    pub os_version: String,
+    pub boot_time: Option<String>,
+    pub reason_time: Option<String>,
+}
+
+impl From<RebootReasonFile> for Boot {
+    fn from(file: RebootReasonFile) -> Self {
+        Boot {
+            boot_id: file.report.boot_id,
+            reason: file.reboot_reason.reason,
+            extra_info: file.reboot_reason.extra_info,
+            os_version: file.report.os_version,
+            boot_time: rfc3339_from_epoch(&file.report.timeepoch),
+            reason_time: rfc3339_from_epoch(&file.reboot_reason.timeepoch),
+        }
+    }
+}
+
+/// Number of boots per reason over all recorded boots.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RebootReasonCounters {
+    pub power_loss: u64,
+    pub watchdog: u64,
+    pub swupdate: u64,
+    pub factory_reset: u64,
+    pub other: u64,
+}
+
+impl RebootReasonCounters {
+    fn count(&mut self, reason: &str) {
+        match reason {
+            "power-loss" => self.power_loss += 1,
+            "factory-reset" => self.factory_reset += 1,
+            r if r.starts_with("swupdate") => self.swupdate += 1,
+            r if r.contains("watchdog") => self.watchdog += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// The latest boots, newest first, and the counters of all recorded boots.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RebootReasonHistory {
+    pub boots: Vec<Boot>,
+    pub counters: RebootReasonCounters,
+}
+
+impl RebootReasonHistory {
+    fn new(mut boots: Vec<Boot>, len: usize) -> Self {
+        let mut counters = RebootReasonCounters::default();
+
+        for boot in &boots {
+            counters.count(&boot.reason);
+        }
+
+        boots.truncate(len);
+        RebootReasonHistory { boots, counters }
+    }
+}
+
+fn rfc3339_from_epoch(timeepoch: &str) -> Option<String> {
+    let timeepoch = timeepoch.parse::<i64>().ok()?;
+
+    OffsetDateTime::from_unix_timestamp(timeepoch)
+        .ok()?
+        .format(&Rfc3339)
+        .ok()
+}
+
+/// All dirs with format of REBOOT_REASON_DIR_REGEX, e.g.
+/// "000001+2025-04-03_17-42-53", oldest first.
+fn reboot_reason_dirs(path: &Path) -> Result<Vec<PathBuf>> {
+    let regex = Regex::new(REBOOT_REASON_DIR_REGEX)
+        .context("failed to create regex for reboot reason folder")?;
+    let dir = std::fs::read_dir(path).context("failed to read reboot reason directory")?;
+
+    let mut dirs: Vec<PathBuf> = dir
+        .flatten()
+        .filter(|f| {
+            let Ok(m) = f.metadata() else { return false };
+
+            if !m.is_dir() {
+                return false;
+            }
+
+            let name = f.file_name();
+            let Some(name) = name.as_os_str().to_str() else {
+                return false;
+            };
+
+            regex.is_match(name)
+        })
+        .map(|f| f.path())
+        .collect();
+
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// All recorded boots, newest first.
+fn read_boots(path: &Path) -> Result<Vec<Boot>> {
+    let dirs = reboot_reason_dirs(path)?;
+
+    // a broken entry must not hide the rest of the history
+    Ok(dirs
+        .iter()
+        .rev()
+        .filter_map(|dir| {
+            from_json_file::<_, RebootReasonFile>(dir.join(REBOOT_REASON_FILE_NAME))
+                .inspect_err(|e| warn!("read_boots: skip {}: {e:#}", dir.display()))
+                .ok()
+                .map(Boot::from)
+        })
+        .collect())
+}
+
+fn read_history(path: &Path, len: usize) -> Result<RebootReasonHistory> {
+    Ok(RebootReasonHistory::new(read_boots(path)?, len))
+}
+
+/// All recorded boots, newest first, i.e. the current boot is the first one.
+pub fn boots() -> Option<Vec<Boot>> {
+    read_boots(Path::new(&reboot_reason_dir_path!()))
+        .inspect_err(|e| warn!("boots: {e:#}"))
+        .ok()
+}
+
+pub fn reboot_reason_history() -> Option<RebootReasonHistory> {
+    let len = env::var("REBOOT_REASON_HISTORY_LEN")
+        .ok()
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(REBOOT_REASON_HISTORY_LEN_DEFAULT);
+
+    read_history(Path::new(&reboot_reason_dir_path!()), len)
+        .inspect_err(|e| warn!("reboot_reason_history: {e:#}"))
+        .ok()
+}
+
 pub fn current_reboot_reason() -> Option<serde_json::Value> {
     // use a closure here to be able to use anyhow::Context for error messages
     // and convert the result to an Option (incl. possibly inspecting errors) afterwards
     let current_reboot_reason_impl = || -> Result<serde_json::Value> {
-        let regex = Regex::new(REBOOT_REASON_DIR_REGEX)
-            .context("failed to create regex for reboot reason folder")?;
-        let dir = std::fs::read_dir(reboot_reason_dir_path!())
-            .context("failed to read reboot reason directory")?;
-
-        // 1. filter for all dirs with format of REBOOT_REASON_DIR_REGEX, e.g. "000001+2025-04-03_17-42-53"
-        // 2. return the latest one
-        let dir = dir
-            .flatten()
-            .filter(|f| {
-                let Ok(m) = f.metadata() else { return false };
-
-                if !m.is_dir() {
-                    return false;
-                }
-
-                let name = f.file_name();
-                let Some(name) = name.as_os_str().to_str() else {
-                    return false;
-                };
-
-                regex.is_match(name)
-            })
-            .max_by_key(|k| k.file_name())
+        let dir = reboot_reason_dirs(Path::new(&reboot_reason_dir_path!()))?
+            .pop()
             .context("failed to identify current reboot reason folder")?;
 
-        let json: serde_json::Value = from_json_file(dir.path().join(REBOOT_REASON_FILE_NAME))?;
+        let json: serde_json::Value = from_json_file(dir.join(REBOOT_REASON_FILE_NAME))?;
         let reason = &json["reboot_reason"]["reason"]
             .as_str()
             .context("failed to get reason")?;
@@ -118,4 +265,52 @@ mod tests {
             }))
         );
     }
+
+    #[test]
+    fn reboot_reason_history_ok() {
+        let history =
+            read_history(Path::new("testfiles/positive/reboot_reason_history"), 2).unwrap();
+
+        // the broken entry and the dir with invalid name are skipped
+        assert_eq!(
+            serde_json::to_value(history).unwrap(),
+            json!({
+                "boots": [
+                    {
+                        "boot_id": "d4c3b2a1-0f9e-4d8c-b7a6-5f4e3d2c1b0a",
+                        "reason": "ods-reboot",
+                        "extra_info": "initiated by portal or API",
+                        "os_version": "4.0.26.603211040",
+                        "boot_time": "2025-04-06T09:15:45Z",
+                        "reason_time": "2025-04-06T09:15:10Z"
+                    },
+                    {
+                        "boot_id": "9a1b2c3d-4e5f-4a6b-8c7d-8e9f0a1b2c3d",
+                        "reason": "systemd-watchdog",
+                        "extra_info": "omnect-device-service.service",
+                        "os_version": "4.0.26.603211040",
+                        "boot_time": "2025-04-05T12:30:00Z",
+                        "reason_time": "2025-04-05T12:29:20Z"
+                    }
+                ],
+                "counters": {
+                    "power_loss": 1,
+                    "watchdog": 1,
+                    "swupdate": 1,
+                    "factory_reset": 0,
+                    "other": 1
+                }
+            })
+        );
+
+        let history = read_history(Path::new("testfiles/positive/reboot_reason"), 10).unwrap();
+        assert_eq!(history.boots.len(), 1);
+        assert_eq!(history.boots[0].reason_time, None);
+        assert_eq!(
+            history.boots[0].boot_time,
+            Some("2025-04-03T17:42:53Z".to_string())
+        );
+
+        assert!(read_history(Path::new("testfiles/positive/not-existing"), 10).is_err());
+    }
 }
@@ -0,0 +1,80 @@
+use anyhow::Result;
+
+/// Inhibitor lock as listed by systemd-logind, e.g. taken by
+/// `systemd-inhibit --what=shutdown`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Inhibitor {
+    pub what: String,
+    pub who: String,
+    pub why: String,
+    pub mode: String,
+}
+
+impl Inhibitor {
+    /// "delay" locks only delay a reboot, "block" locks on "shutdown" prevent it.
+    pub fn blocks_reboot(&self) -> bool {
+        self.mode == "block" && self.what.split(':').any(|what| what == "shutdown")
+    }
+}
+
+#[cfg(not(feature = "mock"))]
+pub async fn inhibitors() -> Result<Vec<Inhibitor>> {
+    use anyhow::Context;
+    use log::debug;
+
+    let result = crate::systemd::system_connection()
+        .await?
+        .call_method(
+            Some("org.freedesktop.login1"),
+            "/org/freedesktop/login1",
+            Some("org.freedesktop.login1.Manager"),
+            "ListInhibitors",
+            &(),
+        )
+        .await
+        .context("inhibitors: call_method() failed")?;
+
+    let inhibitors: Vec<(String, String, String, String, u32, u32)> =
+        result
+            .body()
+            .deserialize()
+            .context("inhibitors: cannot deserialize body")?;
+
+    debug!("inhibitors: {inhibitors:?}");
+
+    Ok(inhibitors
+        .into_iter()
+        .map(|(what, who, why, mode, _uid, _pid)| Inhibitor {
+            what,
+            who,
+            why,
+            mode,
+        })
+        .collect())
+}
+
+#[cfg(feature = "mock")]
+pub async fn inhibitors() -> Result<Vec<Inhibitor>> {
+    Ok(vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_reboot() {
+        let inhibitor = |what: &str, mode: &str| Inhibitor {
+            what: what.to_string(),
+            who: "my-app".to_string(),
+            why: "writing data".to_string(),
+            mode: mode.to_string(),
+        };
+
+        assert!(inhibitor("shutdown", "block").blocks_reboot());
+        assert!(inhibitor("sleep:shutdown:idle", "block").blocks_reboot());
+        assert!(!inhibitor("shutdown", "delay").blocks_reboot());
+        assert!(!inhibitor("sleep:idle", "block").blocks_reboot());
+        assert!(!inhibitor("handle-power-key", "block").blocks_reboot());
+    }
+}
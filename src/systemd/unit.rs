@@ -1,6 +1,9 @@
 use anyhow::Result;
 pub use systemd_zbus::Mode;
 
+#[cfg(not(feature = "mock"))]
+static JOURNAL_LINES: &str = "20";
+
 #[derive(Copy, Clone, Debug)]
 pub enum UnitAction {
     Reload,
@@ -9,6 +12,74 @@ pub enum UnitAction {
     Stop,
 }
 
+/// Result of a systemd job as signaled by `JobRemoved`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobResult {
+    Done,
+    Canceled,
+    Timeout,
+    Failed,
+    Dependency,
+    Skipped,
+    Unknown(String),
+}
+
+impl From<&str> for JobResult {
+    fn from(result: &str) -> Self {
+        match result {
+            "done" => JobResult::Done,
+            "canceled" => JobResult::Canceled,
+            "timeout" => JobResult::Timeout,
+            "failed" => JobResult::Failed,
+            "dependency" => JobResult::Dependency,
+            "skipped" => JobResult::Skipped,
+            other => JobResult::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl JobResult {
+    /// `skipped` means the job didn't apply to the current state of the unit,
+    /// e.g. reloading an inactive unit, which is not considered a failure.
+    pub fn succeeded(&self) -> bool {
+        matches!(self, JobResult::Done | JobResult::Skipped)
+    }
+}
+
+impl std::fmt::Display for JobResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobResult::Done => write!(f, "done"),
+            JobResult::Canceled => write!(f, "canceled"),
+            JobResult::Timeout => write!(f, "timeout"),
+            JobResult::Failed => write!(f, "failed"),
+            JobResult::Dependency => write!(f, "dependency"),
+            JobResult::Skipped => write!(f, "skipped"),
+            JobResult::Unknown(result) => write!(f, "{result}"),
+        }
+    }
+}
+
+/// Returned by `unit_action` if the systemd job didn't succeed.
+#[derive(Debug)]
+pub struct UnitActionError {
+    pub unit: String,
+    pub unit_action: UnitAction,
+    pub result: JobResult,
+}
+
+impl std::fmt::Display for UnitActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} {} finished with result \"{}\"",
+            self.unit_action, self.unit, self.result
+        )
+    }
+}
+
+impl std::error::Error for UnitActionError {}
+
 #[cfg(not(feature = "mock"))]
 async fn system_manager() -> Result<systemd_zbus::ManagerProxy<'static>> {
     use anyhow::Context;
@@ -20,15 +91,27 @@ async fn system_manager() -> Result<systemd_zbus::ManagerProxy<'static>> {
         .context("failed to create systemd manager proxy")
 }
 
+/// Executes `unit_action` and waits for the resulting systemd job to finish.
+///
+/// Returns the job result if it succeeded (see `JobResult::succeeded`),
+/// otherwise `UnitActionError`. The recent journal of the unit is logged, it
+/// is not part of the error, since errors are reported to the caller.
 #[cfg(not(feature = "mock"))]
-pub async fn unit_action(unit: &str, unit_action: UnitAction, mode: Mode) -> Result<()> {
-    use anyhow::Context;
-    use log::debug;
+pub async fn unit_action(unit: &str, unit_action: UnitAction, mode: Mode) -> Result<JobResult> {
+    use anyhow::{Context, bail};
+    use log::{debug, error};
     use tokio_stream::StreamExt;
 
     debug!("unit_action: {unit} {unit_action:?} {mode:?}");
 
     let manager = system_manager().await?;
+
+    // systemd only broadcasts job signals to subscribed peers
+    manager
+        .subscribe()
+        .await
+        .context("failed to subscribe to systemd signals")?;
+
     let job_removed_stream = manager
         .receive_job_removed()
         .await
@@ -48,22 +131,66 @@ pub async fn unit_action(unit: &str, unit_action: UnitAction, mode: Mode) -> Res
         .context("unit action failed")?
         .into_inner();
 
-    job_removed_stream
-        .filter(|job_removed| {
-            if let Ok(args) = job_removed.args() {
-                return args.job() == &job && args.result == "done";
-            };
-            false
+    let Some(result) = job_removed_stream
+        .filter_map(|job_removed| {
+            let args = job_removed.args().ok()?;
+            (args.job() == &job).then(|| JobResult::from(&*args.result))
         })
         .next()
-        .await;
+        .await
+    else {
+        bail!("job_removed_stream ended before {unit_action:?} {unit} finished");
+    };
+
+    debug!("unit_action: {unit} {unit_action:?} finished with result {result}");
+
+    if result.succeeded() {
+        return Ok(result);
+    }
 
-    Ok(())
+    error!(
+        "unit_action: {unit_action:?} {unit} finished with result \"{result}\", recent journal:\n{}",
+        recent_journal(unit).await
+    );
+
+    Err(UnitActionError {
+        unit: unit.to_string(),
+        unit_action,
+        result,
+    }
+    .into())
 }
 
 #[cfg(feature = "mock")]
-pub async fn unit_action(_unit: &str, _unit_action: UnitAction, _mode: Mode) -> Result<()> {
-    Ok(())
+pub async fn unit_action(_unit: &str, _unit_action: UnitAction, _mode: Mode) -> Result<JobResult> {
+    Ok(JobResult::Done)
+}
+
+#[cfg(not(feature = "mock"))]
+async fn recent_journal(unit: &str) -> String {
+    match tokio::process::Command::new("sudo")
+        .args([
+            "journalctl",
+            "--no-pager",
+            "-o",
+            "cat",
+            "-n",
+            JOURNAL_LINES,
+            "-u",
+            unit,
+        ])
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string(),
+        Ok(output) => format!(
+            "failed to read journal: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ),
+        Err(e) => format!("failed to read journal: {e:#}"),
+    }
 }
 
 /// Names of currently-active units matching the given systemd instance/glob
@@ -87,6 +214,36 @@ pub async fn active_units_by_patterns(patterns: &[&str]) -> Result<Vec<String>>
     Ok(names)
 }
 
+/// Names of currently failed units, sorted.
+#[cfg(not(feature = "mock"))]
+pub async fn failed_units() -> Result<Vec<String>> {
+    use anyhow::Context;
+    use systemd_zbus::ActiveState;
+
+    let manager = system_manager().await?;
+    let mut names: Vec<String> = manager
+        .list_units_by_patterns(&[], &[])
+        .await
+        .context("failed_units: list_units_by_patterns failed")?
+        .into_iter()
+        .filter(|unit| unit.active == ActiveState::Failed)
+        .map(|unit| unit.name)
+        .collect();
+    names.sort();
+
+    Ok(names)
+}
+
+#[cfg(feature = "mock")]
+pub async fn active_units_by_patterns(_patterns: &[&str]) -> Result<Vec<String>> {
+    Ok(vec![])
+}
+
+#[cfg(feature = "mock")]
+pub async fn failed_units() -> Result<Vec<String>> {
+    Ok(vec![])
+}
+
 /// Stream of unit names whose systemd job just completed (`JobRemoved`).
 ///
 /// systemd only broadcasts Job signals to subscribed peers, so this subscribes
@@ -144,3 +301,42 @@ pub async fn job_removed_units() -> Result<impl futures::Stream<Item = String> +
         }
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_result_test() {
+        assert_eq!(JobResult::from("done"), JobResult::Done);
+        assert_eq!(JobResult::from("failed"), JobResult::Failed);
+        assert_eq!(JobResult::from("dependency"), JobResult::Dependency);
+        assert_eq!(
+            JobResult::from("something"),
+            JobResult::Unknown("something".to_string())
+        );
+
+        assert!(JobResult::Done.succeeded());
+        assert!(JobResult::Skipped.succeeded());
+        assert!(!JobResult::Canceled.succeeded());
+        assert!(!JobResult::Timeout.succeeded());
+        assert!(!JobResult::Failed.succeeded());
+        assert!(!JobResult::Dependency.succeeded());
+    }
+
+    #[test]
+    fn unit_action_error_test() {
+        let e = anyhow::Error::new(UnitActionError {
+            unit: "systemd-networkd.service".to_string(),
+            unit_action: UnitAction::Reload,
+            result: JobResult::Failed,
+        })
+        .context("recent journal of systemd-networkd.service:\nsome line");
+
+        assert!(e.is::<UnitActionError>());
+        assert_eq!(
+            e.root_cause().to_string(),
+            "Reload systemd-networkd.service finished with result \"failed\""
+        );
+    }
+}
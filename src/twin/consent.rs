@@ -9,6 +9,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{collections::HashMap, env, path::Path};
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 
 macro_rules! consent_path {
     () => {
@@ -84,7 +85,7 @@ impl Feature for DeviceUpdateConsent {
             .await
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         match cmd {
             Command::FsEvent(FsEventCommand {
                 kind: FsEventKind::FileModified,
@@ -209,11 +210,14 @@ mod tests {
 
         assert!(
             consent
-                .command(&Command::FsEvent(FsEventCommand {
-                    kind: FsEventKind::FileModified,
-                    feature_id: TypeId::of::<DeviceUpdateConsent>(),
-                    path: Path::new("my-path").to_path_buf(),
-                }))
+                .command(
+                    &Command::FsEvent(FsEventCommand {
+                        kind: FsEventKind::FileModified,
+                        feature_id: TypeId::of::<DeviceUpdateConsent>(),
+                        path: Path::new("my-path").to_path_buf(),
+                    }),
+                    CancellationToken::new()
+                )
                 .await
                 .unwrap_err()
                 .chain()
@@ -221,12 +225,15 @@ mod tests {
         );
 
         consent
-            .command(&Command::FsEvent(FsEventCommand {
-                kind: FsEventKind::FileModified,
-                feature_id: TypeId::of::<DeviceUpdateConsent>(),
-                path: Path::new("testfiles/positive/test_component/user_consent.json")
-                    .to_path_buf(),
-            }))
+            .command(
+                &Command::FsEvent(FsEventCommand {
+                    kind: FsEventKind::FileModified,
+                    feature_id: TypeId::of::<DeviceUpdateConsent>(),
+                    path: Path::new("testfiles/positive/test_component/user_consent.json")
+                        .to_path_buf(),
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
 
@@ -245,11 +252,12 @@ mod tests {
 
         assert!(
             consent
-                .command(&Command::DesiredGeneralConsent(
-                    DesiredGeneralConsentCommand {
+                .command(
+                    &Command::DesiredGeneralConsent(DesiredGeneralConsentCommand {
                         general_consent: vec![],
-                    },
-                ))
+                    },),
+                    CancellationToken::new()
+                )
                 .await
                 .unwrap_err()
                 .chain()
@@ -264,11 +272,12 @@ mod tests {
 
         assert!(
             consent
-                .command(&Command::DesiredGeneralConsent(
-                    DesiredGeneralConsentCommand {
+                .command(
+                    &Command::DesiredGeneralConsent(DesiredGeneralConsentCommand {
                         general_consent: vec![],
-                    },
-                ))
+                    },),
+                    CancellationToken::new()
+                )
                 .await
                 .unwrap_err()
                 .chain()
@@ -280,11 +289,12 @@ mod tests {
         std::fs::copy("testfiles/positive/consent_conf.json", &consent_conf_file).unwrap();
 
         consent
-            .command(&Command::DesiredGeneralConsent(
-                DesiredGeneralConsentCommand {
+            .command(
+                &Command::DesiredGeneralConsent(DesiredGeneralConsentCommand {
                     general_consent: vec![],
-                },
-            ))
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
 
@@ -308,11 +318,12 @@ mod tests {
         );
 
         consent
-            .command(&Command::DesiredGeneralConsent(
-                DesiredGeneralConsentCommand {
+            .command(
+                &Command::DesiredGeneralConsent(DesiredGeneralConsentCommand {
                     general_consent: vec!["foo".to_string(), "bar".to_string()],
-                },
-            ))
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
 
@@ -335,9 +346,12 @@ mod tests {
 
         assert!(
             consent
-                .command(&Command::UserConsent(UserConsentCommand {
-                    user_consent: HashMap::from([("foo/bar".to_string(), "bar".to_string())]),
-                }))
+                .command(
+                    &Command::UserConsent(UserConsentCommand {
+                        user_consent: HashMap::from([("foo/bar".to_string(), "bar".to_string())]),
+                    }),
+                    CancellationToken::new()
+                )
                 .await
                 .unwrap_err()
                 .chain()
@@ -348,9 +362,12 @@ mod tests {
 
         assert!(
             consent
-                .command(&Command::UserConsent(UserConsentCommand {
-                    user_consent: HashMap::from([("foo".to_string(), "bar".to_string())]),
-                }))
+                .command(
+                    &Command::UserConsent(UserConsentCommand {
+                        user_consent: HashMap::from([("foo".to_string(), "bar".to_string())]),
+                    }),
+                    CancellationToken::new()
+                )
                 .await
                 .unwrap_err()
                 .chain()
@@ -368,9 +385,12 @@ mod tests {
         .unwrap();
 
         consent
-            .command(&Command::UserConsent(UserConsentCommand {
-                user_consent: HashMap::from([("foo".to_string(), "bar".to_string())]),
-            }))
+            .command(
+                &Command::UserConsent(UserConsentCommand {
+                    user_consent: HashMap::from([("foo".to_string(), "bar".to_string())]),
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
     }
@@ -1,7 +1,7 @@
 use crate::{
     bootloader_env,
     common::from_json_file,
-    systemd,
+    reboot_inhibitor, systemd,
     twin::{Feature, feature::*},
     web_service,
 };
@@ -19,6 +19,7 @@ use std::{
     path::Path,
 };
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 
 macro_rules! result_path {
     () => {
@@ -54,6 +55,9 @@ pub enum FactoryResetMode {
 pub struct FactoryResetCommand {
     pub mode: FactoryResetMode,
     pub preserve: Vec<String>,
+    // ignore reboot inhibitors, not passed to the bootloader
+    #[serde(default, skip_serializing)]
+    pub force: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -134,7 +138,7 @@ impl Feature for FactoryReset {
         Ok(())
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         match cmd {
             Command::FsEvent(FsEventCommand {
                 kind: FsEventKind::DirModified,
@@ -256,6 +260,8 @@ impl FactoryReset {
             }
         }
 
+        reboot_inhibitor::ensure_reboot_allowed(cmd.force).await?;
+
         bootloader_env::set("factory-reset", &serde_json::to_string(&cmd)?)?;
         systemd::reboot("factory-reset", "initiated by portal or API").await?;
         Ok(None)
@@ -291,10 +297,14 @@ mod tests {
 
         assert!(
             factory_reset
-                .command(&Command::FactoryReset(FactoryResetCommand {
-                    mode: FactoryResetMode::Mode1,
-                    preserve: vec!["foo".to_string()],
-                }))
+                .command(
+                    &Command::FactoryReset(FactoryResetCommand {
+                        mode: FactoryResetMode::Mode1,
+                        preserve: vec!["foo".to_string()],
+                        force: false,
+                    }),
+                    CancellationToken::new()
+                )
                 .await
                 .unwrap_err()
                 .chain()
@@ -304,14 +314,18 @@ mod tests {
         );
 
         factory_reset
-            .command(&Command::FactoryReset(FactoryResetCommand {
-                mode: FactoryResetMode::Mode1,
-                preserve: vec![
-                    "network".to_string(),
-                    "firewall".to_string(),
-                    "certificates".to_string(),
-                ],
-            }))
+            .command(
+                &Command::FactoryReset(FactoryResetCommand {
+                    mode: FactoryResetMode::Mode1,
+                    preserve: vec![
+                        "network".to_string(),
+                        "firewall".to_string(),
+                        "certificates".to_string(),
+                    ],
+                    force: false,
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
 
@@ -442,11 +456,14 @@ mod tests {
         let mut factory_reset = FactoryReset::new(&mut fs_watcher).expect("FactoryReset::new");
 
         let result = factory_reset
-            .command(&Command::FsEvent(FsEventCommand {
-                kind: FsEventKind::DirModified,
-                feature_id: std::any::TypeId::of::<FactoryReset>(),
-                path: std::path::PathBuf::from("/unused"),
-            }))
+            .command(
+                &Command::FsEvent(FsEventCommand {
+                    kind: FsEventKind::DirModified,
+                    feature_id: std::any::TypeId::of::<FactoryReset>(),
+                    path: std::path::PathBuf::from("/unused"),
+                }),
+                CancellationToken::new(),
+            )
             .await;
         assert!(result.is_ok());
     }
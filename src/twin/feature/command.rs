@@ -1,12 +1,12 @@
 use crate::twin::{
     TwinUpdate, TwinUpdateState, consent, factory_reset, firmware_update, network, reboot,
-    ssh_tunnel, system_info,
+    ssh_tunnel, system_info, wifi_commissioning,
 };
 use anyhow::{Context, Result, bail};
 use azure_iot_sdk::client::DirectMethod;
 use futures::{Stream, StreamExt, stream};
 use log::{debug, error, info, warn};
-use std::{any::TypeId, future::Future, path::PathBuf, pin::Pin, time::Duration};
+use std::{any::TypeId, env, future::Future, path::PathBuf, pin::Pin, time::Duration};
 use tokio::{
     sync::{mpsc, oneshot},
     time::Interval,
@@ -18,6 +18,10 @@ use tokio_util::sync::CancellationToken;
 /// `Network` (networkd D-Bus signals) so both layers debounce identically.
 pub(crate) const COMMAND_EVENT_DEBOUNCE: Duration = Duration::from_secs(2);
 
+const COMMAND_TIMEOUT_SECS_DEFAULT: u64 = 60;
+const DOWNLOAD_FIRMWARE_UPDATE_TIMEOUT_SECS_DEFAULT: u64 = 3600;
+const LOAD_FIRMWARE_UPDATE_TIMEOUT_SECS_DEFAULT: u64 = 600;
+
 /// Await `deadline`, or never resolve if `None`. Used as the idle branch of
 /// `tokio::select!` arms that are only active while a debounce window is
 /// armed — `pending()` avoids periodic wake-ups from a far-future sentinel.
@@ -28,20 +32,34 @@ pub(crate) async fn wait_for_deadline(deadline: Option<tokio::time::Instant>) {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, strum_macros::IntoStaticStr)]
 pub enum Command {
+    CancelFirmwareUpdate,
+    CancelReboot,
+    CancelScheduledFirmwareUpdate,
+    ClearRebootSafeMode,
     CloseSshTunnel(ssh_tunnel::CloseSshTunnelCommand),
+    ConfirmUpdateValidation,
     DesiredGeneralConsent(consent::DesiredGeneralConsentCommand),
+    DesiredMaintenanceWindows(firmware_update::DesiredMaintenanceWindowsCommand),
     DesiredUpdateDeviceSshCa(ssh_tunnel::UpdateDeviceSshCaCommand),
+    DownloadFirmwareUpdate(firmware_update::DownloadUpdateCommand),
+    ExtendUpdateValidation(firmware_update::ExtendUpdateValidationCommand),
     FactoryReset(factory_reset::FactoryResetCommand),
     FleetId(system_info::FleetIdCommand),
     FsEvent(FsEventCommand),
+    GetFirmwareUpdateStatus,
     GetSshPubKey(ssh_tunnel::GetSshPubKeyCommand),
     LoadFirmwareUpdate(firmware_update::LoadUpdateCommand),
     OpenSshTunnel(ssh_tunnel::OpenSshTunnelCommand),
-    Reboot,
+    Poweroff(reboot::PoweroffCommand),
+    Reboot(reboot::RebootCommand),
+    RejectUpdateValidation,
     ReloadNetwork,
+    RestartUnit(reboot::RestartUnitCommand),
     RunFirmwareUpdate(firmware_update::RunUpdateCommand),
+    RunScheduledFirmwareUpdate,
+    RunScheduledReboot,
     SetWaitOnlineTimeout(reboot::SetWaitOnlineTimeoutCommand),
     Tick(TickCommand),
     UserConsent(consent::UserConsentCommand),
@@ -62,18 +80,32 @@ impl Command {
         use Command::*;
 
         match self {
+            CancelFirmwareUpdate => TypeId::of::<firmware_update::FirmwareUpdate>(),
+            CancelReboot => TypeId::of::<reboot::Reboot>(),
+            CancelScheduledFirmwareUpdate => TypeId::of::<firmware_update::FirmwareUpdate>(),
+            ClearRebootSafeMode => TypeId::of::<reboot::Reboot>(),
             CloseSshTunnel(_) => TypeId::of::<ssh_tunnel::SshTunnel>(),
+            ConfirmUpdateValidation => TypeId::of::<firmware_update::FirmwareUpdate>(),
             DesiredGeneralConsent(_) => TypeId::of::<consent::DeviceUpdateConsent>(),
+            DesiredMaintenanceWindows(_) => TypeId::of::<firmware_update::FirmwareUpdate>(),
             DesiredUpdateDeviceSshCa(_) => TypeId::of::<ssh_tunnel::SshTunnel>(),
+            DownloadFirmwareUpdate(_) => TypeId::of::<firmware_update::FirmwareUpdate>(),
+            ExtendUpdateValidation(_) => TypeId::of::<firmware_update::FirmwareUpdate>(),
             FactoryReset(_) => TypeId::of::<factory_reset::FactoryReset>(),
             FleetId(_) => TypeId::of::<system_info::SystemInfo>(),
             FsEvent(cmd) => cmd.feature_id,
+            GetFirmwareUpdateStatus => TypeId::of::<firmware_update::FirmwareUpdate>(),
             GetSshPubKey(_) => TypeId::of::<ssh_tunnel::SshTunnel>(),
             LoadFirmwareUpdate(_) => TypeId::of::<firmware_update::FirmwareUpdate>(),
             OpenSshTunnel(_) => TypeId::of::<ssh_tunnel::SshTunnel>(),
-            Reboot => TypeId::of::<reboot::Reboot>(),
+            Poweroff(_) => TypeId::of::<reboot::Reboot>(),
+            Reboot(_) => TypeId::of::<reboot::Reboot>(),
+            RejectUpdateValidation => TypeId::of::<firmware_update::FirmwareUpdate>(),
             ReloadNetwork => TypeId::of::<network::Network>(),
+            RestartUnit(_) => TypeId::of::<reboot::Reboot>(),
             RunFirmwareUpdate(_) => TypeId::of::<firmware_update::FirmwareUpdate>(),
+            RunScheduledFirmwareUpdate => TypeId::of::<firmware_update::FirmwareUpdate>(),
+            RunScheduledReboot => TypeId::of::<reboot::Reboot>(),
             SetWaitOnlineTimeout(_) => TypeId::of::<reboot::Reboot>(),
             Tick(cmd) => cmd.feature_id,
             UserConsent(_) => TypeId::of::<consent::DeviceUpdateConsent>(),
@@ -82,26 +114,96 @@ impl Command {
     }
 
     pub fn triggers_reboot(&self) -> bool {
-        matches!(
-            self,
-            Command::FactoryReset(_) | Command::Reboot | Command::RunFirmwareUpdate(_)
-        )
+        match self {
+            Command::FactoryReset(_)
+            | Command::Poweroff(_)
+            | Command::RejectUpdateValidation
+            | Command::RunScheduledFirmwareUpdate
+            | Command::RunScheduledReboot => true,
+            // a scheduled run only persists the schedule
+            Command::RunFirmwareUpdate(cmd) => cmd.schedule.is_none(),
+            Command::Reboot(cmd) => !cmd.is_deferred(),
+            _ => false,
+        }
+    }
+
+    /// Commands that may take long are executed as job, so that the twin run loop
+    /// is not blocked while they are running (see `twin::job::Jobs`).
+    pub fn is_long_running(&self) -> bool {
+        match self {
+            Command::DownloadFirmwareUpdate(_)
+            | Command::LoadFirmwareUpdate(_)
+            | Command::RunFirmwareUpdate(_)
+            | Command::RunScheduledFirmwareUpdate => true,
+            Command::Tick(cmd) => {
+                cmd.feature_id == TypeId::of::<wifi_commissioning::WifiCommissioning>()
+            }
+            _ => false,
+        }
+    }
+
+    /// Upper bound for the execution of the command, `None` if unbounded.
+    ///
+    /// The timeout of each command can be configured by `<COMMAND>_TIMEOUT_SECS`,
+    /// e.g. `RELOAD_NETWORK_TIMEOUT_SECS`. Commands without specific default use
+    /// `COMMAND_TIMEOUT_SECS`. A value of 0 disables the timeout.
+    ///
+    /// Running a firmware update is never bounded: swupdate cannot be
+    /// interrupted, thus the device might still reboot into the update after
+    /// the caller got a timeout.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_from(|var| env::var(var).ok())
+    }
+
+    // `env` looks up the configured timeouts, so that tests don't have to
+    // modify process-wide env vars
+    fn timeout_from(&self, env: impl Fn(&str) -> Option<String>) -> Option<Duration> {
+        let default = match self {
+            Command::RunFirmwareUpdate(_) | Command::RunScheduledFirmwareUpdate => return None,
+            Command::DownloadFirmwareUpdate(_) => DOWNLOAD_FIRMWARE_UPDATE_TIMEOUT_SECS_DEFAULT,
+            Command::LoadFirmwareUpdate(_) => LOAD_FIRMWARE_UPDATE_TIMEOUT_SECS_DEFAULT,
+            _ => timeout_secs_from_env(&env, "COMMAND_TIMEOUT_SECS", COMMAND_TIMEOUT_SECS_DEFAULT),
+        };
+        let name: &'static str = self.into();
+        let secs = timeout_secs_from_env(
+            &env,
+            &format!("{}_TIMEOUT_SECS", screaming_snake_case(name)),
+            default,
+        );
+
+        (secs != 0).then(|| Duration::from_secs(secs))
     }
 
     pub fn from_direct_method(direct_method: &DirectMethod) -> Result<Command> {
-        info!("direct method: {direct_method:?}");
+        // the payload is not logged, since it might contain credentials, e.g.
+        // headers of download_firmware_update
+        info!("direct method: {}", direct_method.name);
 
         let payload = &direct_method.payload;
 
         match direct_method.name.as_str() {
+            "cancel_firmware_update" => Ok(Command::CancelFirmwareUpdate),
+            "cancel_reboot" => Ok(Command::CancelReboot),
+            "cancel_scheduled_firmware_update" => Ok(Command::CancelScheduledFirmwareUpdate),
+            "clear_reboot_safe_mode" => Ok(Command::ClearRebootSafeMode),
             "close_ssh_tunnel" => Ok(Command::CloseSshTunnel(parse_payload(
                 payload,
                 "close_ssh_tunnel",
             )?)),
+            "confirm_update_validation" => Ok(Command::ConfirmUpdateValidation),
+            "download_firmware_update" => Ok(Command::DownloadFirmwareUpdate(parse_payload(
+                payload,
+                "download_firmware_update",
+            )?)),
+            "extend_update_validation" => Ok(Command::ExtendUpdateValidation(parse_payload(
+                payload,
+                "extend_update_validation",
+            )?)),
             "factory_reset" => Ok(Command::FactoryReset(parse_payload(
                 payload,
                 "factory_reset",
             )?)),
+            "get_firmware_update_status" => Ok(Command::GetFirmwareUpdateStatus),
             "get_ssh_pub_key" => Ok(Command::GetSshPubKey(parse_payload(
                 payload,
                 "get_ssh_pub_key",
@@ -110,7 +212,17 @@ impl Command {
                 payload,
                 "open_ssh_tunnel",
             )?)),
-            "reboot" => Ok(Command::Reboot),
+            // an empty payload powers off without force
+            "poweroff" if payload.is_null() => Ok(Command::Poweroff(Default::default())),
+            "poweroff" => Ok(Command::Poweroff(parse_payload(payload, "poweroff")?)),
+            // an empty payload reboots immediately
+            "reboot" if payload.is_null() => Ok(Command::Reboot(Default::default())),
+            "reboot" => Ok(Command::Reboot(parse_payload(payload, "reboot")?)),
+            "reject_update_validation" => Ok(Command::RejectUpdateValidation),
+            "restart_unit" => Ok(Command::RestartUnit(parse_payload(
+                payload,
+                "restart_unit",
+            )?)),
             "set_wait_online_timeout" => Ok(Command::SetWaitOnlineTimeout(parse_payload(
                 payload,
                 "set_wait_online_timeout",
@@ -130,6 +242,7 @@ impl Command {
     pub fn from_desired_property(update: TwinUpdate) -> Vec<Command> {
         info!("desired property: {update:?}");
         let mut cmds = vec![];
+        let complete = matches!(update.state, TwinUpdateState::Complete);
 
         let value = match update.state {
             TwinUpdateState::Partial => &update.value,
@@ -150,6 +263,12 @@ impl Command {
                             Err(e) => error!("from_desired_property: {e:#}"),
                         }
                     }
+                    "maintenance_windows" => cmds.push(Command::DesiredMaintenanceWindows(
+                        firmware_update::DesiredMaintenanceWindowsCommand {
+                            patch: value["maintenance_windows"].clone(),
+                            complete,
+                        },
+                    )),
                     "ssh_tunnel_ca_pub" => match parse_payload(value, "DesiredUpdateDeviceSshCa") {
                         Ok(c) => cmds.push(Command::DesiredUpdateDeviceSshCa(c)),
                         Err(e) => error!("from_desired_property: {e:#}"),
@@ -163,6 +282,49 @@ impl Command {
     }
 }
 
+fn timeout_secs_from_env(env: impl Fn(&str) -> Option<String>, var: &str, default: u64) -> u64 {
+    match env(var) {
+        Some(secs) => secs.parse::<u64>().unwrap_or_else(|_| {
+            error!("ignore invalid {var} {secs} and use default {default}");
+            default
+        }),
+        None => default,
+    }
+}
+
+fn screaming_snake_case(name: &str) -> String {
+    let mut s = String::new();
+
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i != 0 {
+            s.push('_');
+        }
+        s.push(c.to_ascii_uppercase());
+    }
+
+    s
+}
+
+/// Returned to the caller if a command exceeded its timeout.
+#[derive(Debug)]
+pub struct CommandTimeoutError {
+    pub command: &'static str,
+    pub timeout: Duration,
+}
+
+impl std::fmt::Display for CommandTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} timed out after {}s",
+            self.command,
+            self.timeout.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for CommandTimeoutError {}
+
 #[derive(Debug)]
 pub struct CommandRequest {
     pub command: Command,
@@ -337,24 +499,157 @@ mod tests {
 
     #[test]
     fn triggers_reboot_test() {
-        assert!(Command::Reboot.triggers_reboot());
+        assert!(Command::Reboot(Default::default()).triggers_reboot());
         assert!(
             Command::FactoryReset(factory_reset::FactoryResetCommand {
                 mode: factory_reset::FactoryResetMode::Mode1,
-                preserve: vec![]
+                preserve: vec![],
+                force: false
             })
             .triggers_reboot()
         );
         assert!(
             Command::RunFirmwareUpdate(firmware_update::RunUpdateCommand {
-                validate_iothub_connection: false
+                validate_iothub_connection: false,
+                schedule: None,
+                force: false,
             })
             .triggers_reboot()
         );
 
+        assert!(Command::RunScheduledFirmwareUpdate.triggers_reboot());
+        assert!(Command::RejectUpdateValidation.triggers_reboot());
+        assert!(Command::RunScheduledReboot.triggers_reboot());
+        assert!(Command::Poweroff(Default::default()).triggers_reboot());
+
         // Test some commands that should not trigger reboot
+        assert!(
+            !Command::Reboot(reboot::RebootCommand {
+                delay_secs: Some(300),
+                ..Default::default()
+            })
+            .triggers_reboot()
+        );
+        assert!(!Command::CancelReboot.triggers_reboot());
+        assert!(!Command::ClearRebootSafeMode.triggers_reboot());
+        assert!(
+            !Command::RestartUnit(reboot::RestartUnitCommand {
+                unit: "docker.service".to_string()
+            })
+            .triggers_reboot()
+        );
+        assert!(
+            !Command::RunFirmwareUpdate(firmware_update::RunUpdateCommand {
+                validate_iothub_connection: false,
+                schedule: Some(firmware_update::RunSchedule::MaintenanceWindow(
+                    "nightly".to_string()
+                )),
+                force: false,
+            })
+            .triggers_reboot()
+        );
         assert!(!Command::ReloadNetwork.triggers_reboot());
         assert!(!Command::ValidateUpdate(true).triggers_reboot());
+        assert!(!Command::ConfirmUpdateValidation.triggers_reboot());
+    }
+
+    #[test]
+    fn is_long_running_test() {
+        assert!(
+            Command::LoadFirmwareUpdate(firmware_update::LoadUpdateCommand {
+                update_file_path: PathBuf::from("/update.tar"),
+                version_options: firmware_update::VersionOptions::default(),
+            })
+            .is_long_running()
+        );
+        assert!(
+            Command::RunFirmwareUpdate(firmware_update::RunUpdateCommand {
+                validate_iothub_connection: false,
+                schedule: None,
+                force: false,
+            })
+            .is_long_running()
+        );
+        assert!(
+            Command::Tick(TickCommand {
+                feature_id: TypeId::of::<wifi_commissioning::WifiCommissioning>()
+            })
+            .is_long_running()
+        );
+
+        assert!(
+            !Command::Tick(TickCommand {
+                feature_id: TypeId::of::<network::Network>()
+            })
+            .is_long_running()
+        );
+        assert!(!Command::Reboot(Default::default()).is_long_running());
+        assert!(!Command::ValidateUpdate(true).is_long_running());
+    }
+
+    #[test]
+    fn timeout_test() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == var)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+
+        let run_update = Command::RunFirmwareUpdate(firmware_update::RunUpdateCommand {
+            validate_iothub_connection: false,
+            schedule: None,
+            force: false,
+        });
+
+        assert_eq!(
+            Command::ReloadNetwork.timeout_from(env(&[])),
+            Some(Duration::from_secs(COMMAND_TIMEOUT_SECS_DEFAULT))
+        );
+        assert_eq!(run_update.timeout_from(env(&[])), None);
+        assert_eq!(
+            Command::RunScheduledFirmwareUpdate.timeout_from(env(&[])),
+            None
+        );
+
+        let vars = env(&[
+            ("COMMAND_TIMEOUT_SECS", "5"),
+            ("RUN_FIRMWARE_UPDATE_TIMEOUT_SECS", "10"),
+        ]);
+        assert_eq!(
+            Command::Reboot(Default::default()).timeout_from(&vars),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(run_update.timeout_from(&vars), None);
+
+        assert_eq!(
+            Command::ReloadNetwork.timeout_from(env(&[
+                ("COMMAND_TIMEOUT_SECS", "5"),
+                ("RELOAD_NETWORK_TIMEOUT_SECS", "10"),
+            ])),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            Command::ReloadNetwork.timeout_from(env(&[
+                ("COMMAND_TIMEOUT_SECS", "5"),
+                ("RELOAD_NETWORK_TIMEOUT_SECS", "invalid"),
+            ])),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            Command::ReloadNetwork.timeout_from(env(&[("RELOAD_NETWORK_TIMEOUT_SECS", "0")])),
+            None
+        );
+    }
+
+    #[test]
+    fn screaming_snake_case_test() {
+        assert_eq!(screaming_snake_case("Reboot"), "REBOOT");
+        assert_eq!(
+            screaming_snake_case("LoadFirmwareUpdate"),
+            "LOAD_FIRMWARE_UPDATE"
+        );
     }
 
     #[test]
@@ -405,7 +700,8 @@ mod tests {
             .unwrap(),
             Command::FactoryReset(factory_reset::FactoryResetCommand {
                 mode: factory_reset::FactoryResetMode::Mode1,
-                preserve: vec!["1".to_string()]
+                preserve: vec!["1".to_string()],
+                force: false
             })
         );
 
@@ -422,7 +718,8 @@ mod tests {
             .unwrap(),
             Command::FactoryReset(factory_reset::FactoryResetCommand {
                 mode: factory_reset::FactoryResetMode::Mode1,
-                preserve: vec![]
+                preserve: vec![],
+                force: false
             })
         );
 
@@ -525,6 +822,64 @@ mod tests {
             })
         );
 
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert!(
+            Command::from_direct_method(&DirectMethod {
+                name: "download_firmware_update".to_string(),
+                payload: json!({"sha256": "abc"}),
+                responder,
+            })
+            .is_err()
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "download_firmware_update".to_string(),
+                payload: json!({
+                    "url": "https://my-host/update.tar",
+                    "headers": {"Authorization": "Bearer token"},
+                    "allow_downgrade": true,
+                }),
+                responder,
+            })
+            .unwrap(),
+            Command::DownloadFirmwareUpdate(firmware_update::DownloadUpdateCommand {
+                url: "https://my-host/update.tar".to_string(),
+                headers: std::collections::HashMap::from([(
+                    "Authorization".to_string(),
+                    "Bearer token".to_string()
+                )]),
+                sha256: None,
+                version_options: firmware_update::VersionOptions {
+                    allow_downgrade: true,
+                    allow_reinstall: false,
+                },
+            })
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "get_firmware_update_status".to_string(),
+                payload: json!({}),
+                responder,
+            })
+            .unwrap(),
+            Command::GetFirmwareUpdateStatus
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "cancel_firmware_update".to_string(),
+                payload: json!({}),
+                responder,
+            })
+            .unwrap(),
+            Command::CancelFirmwareUpdate
+        );
+
         let (responder, _rx) = oneshot::channel::<CommandResult>();
         assert_eq!(
             Command::from_direct_method(&DirectMethod {
@@ -533,7 +888,100 @@ mod tests {
                 responder,
             })
             .unwrap(),
-            Command::Reboot
+            Command::Reboot(Default::default())
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "reboot".to_string(),
+                payload: serde_json::Value::Null,
+                responder,
+            })
+            .unwrap(),
+            Command::Reboot(Default::default())
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "reboot".to_string(),
+                payload: json!({"at": "2026-10-17T02:00:00Z", "force": true}),
+                responder,
+            })
+            .unwrap(),
+            Command::Reboot(reboot::RebootCommand {
+                at: Some("2026-10-17T02:00:00Z".to_string()),
+                delay_secs: None,
+                force: true,
+                replace: false
+            })
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert!(
+            Command::from_direct_method(&DirectMethod {
+                name: "reboot".to_string(),
+                payload: json!({"delay_secs": "soon"}),
+                responder,
+            })
+            .is_err()
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "cancel_reboot".to_string(),
+                payload: json!({}),
+                responder,
+            })
+            .unwrap(),
+            Command::CancelReboot
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "clear_reboot_safe_mode".to_string(),
+                payload: json!({}),
+                responder,
+            })
+            .unwrap(),
+            Command::ClearRebootSafeMode
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "poweroff".to_string(),
+                payload: json!({"force": true}),
+                responder,
+            })
+            .unwrap(),
+            Command::Poweroff(reboot::PoweroffCommand { force: true })
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "restart_unit".to_string(),
+                payload: json!({"unit": "docker.service"}),
+                responder,
+            })
+            .unwrap(),
+            Command::RestartUnit(reboot::RestartUnitCommand {
+                unit: "docker.service".to_string()
+            })
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert!(
+            Command::from_direct_method(&DirectMethod {
+                name: "restart_unit".to_string(),
+                payload: json!({}),
+                responder,
+            })
+            .is_err()
         );
 
         let (responder, _rx) = oneshot::channel::<CommandResult>();
@@ -560,6 +1008,52 @@ mod tests {
             })
         );
 
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "confirm_update_validation".to_string(),
+                payload: json!({}),
+                responder,
+            })
+            .unwrap(),
+            Command::ConfirmUpdateValidation
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "reject_update_validation".to_string(),
+                payload: json!({}),
+                responder,
+            })
+            .unwrap(),
+            Command::RejectUpdateValidation
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert_eq!(
+            Command::from_direct_method(&DirectMethod {
+                name: "extend_update_validation".to_string(),
+                payload: json!({"secs": 3600, "hold": true}),
+                responder,
+            })
+            .unwrap(),
+            Command::ExtendUpdateValidation(firmware_update::ExtendUpdateValidationCommand {
+                secs: 3600,
+                hold: true,
+            })
+        );
+
+        let (responder, _rx) = oneshot::channel::<CommandResult>();
+        assert!(
+            Command::from_direct_method(&DirectMethod {
+                name: "extend_update_validation".to_string(),
+                payload: json!({"hold": true}),
+                responder,
+            })
+            .is_err()
+        );
+
         // Regression: the parse-error context must name the actual command.
         // A prior copy-paste bug reported "CloseSshTunnel" for a malformed
         // set_wait_online_timeout payload.
@@ -696,6 +1190,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn from_desired_property_maintenance_windows_test() {
+        let patch = json!({"nightly": {"start": "01:00", "duration_mins": 120}});
+
+        assert_eq!(
+            Command::from_desired_property(TwinUpdate {
+                state: TwinUpdateState::Partial,
+                value: json!({"maintenance_windows": patch}),
+            }),
+            vec![Command::DesiredMaintenanceWindows(
+                firmware_update::DesiredMaintenanceWindowsCommand {
+                    patch: patch.clone(),
+                    complete: false,
+                }
+            )]
+        );
+
+        assert_eq!(
+            Command::from_desired_property(TwinUpdate {
+                state: TwinUpdateState::Complete,
+                value: json!({"desired": {"maintenance_windows": patch}}),
+            }),
+            vec![Command::DesiredMaintenanceWindows(
+                firmware_update::DesiredMaintenanceWindowsCommand {
+                    patch,
+                    complete: true,
+                }
+            )]
+        );
+    }
+
     #[test]
     fn from_desired_property_ssh_tunnel_ca_pub_test() {
         // Valid ssh_tunnel_ca_pub
@@ -907,7 +1432,10 @@ mod tests {
         );
 
         // Spot-check fixed-feature commands
-        assert_eq!(Command::Reboot.feature_id(), TypeId::of::<reboot::Reboot>());
+        assert_eq!(
+            Command::Reboot(Default::default()).feature_id(),
+            TypeId::of::<reboot::Reboot>()
+        );
         assert_eq!(Command::ReloadNetwork.feature_id(), TypeId::of::<Network>());
         assert_eq!(
             Command::ValidateUpdate(true).feature_id(),
@@ -917,6 +1445,7 @@ mod tests {
             Command::FactoryReset(factory_reset::FactoryResetCommand {
                 mode: factory_reset::FactoryResetMode::Mode1,
                 preserve: vec![],
+                force: false
             })
             .feature_id(),
             TypeId::of::<factory_reset::FactoryReset>()
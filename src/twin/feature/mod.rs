@@ -4,6 +4,7 @@ use anyhow::Result;
 use azure_iot_sdk::client::IotMessage;
 pub use command::*;
 pub use fs_watcher::*;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
@@ -29,7 +30,107 @@ pub(crate) trait Feature {
         Ok(None)
     }
 
-    async fn command(&mut self, _cmd: &Command) -> CommandResult {
+    /// Answers requests while a job owns the feature (see `twin::job::Jobs`).
+    /// Without handler all requests are queued until the job finished.
+    fn busy_handler(&self) -> Option<Box<dyn BusyHandler>> {
+        None
+    }
+
+    /// `cancel` is triggered if the command exceeds its timeout (see
+    /// `Command::timeout`) or the service shuts down. The command future is
+    /// dropped afterwards, so features only have to observe `cancel` for work
+    /// that is not bound to the future, e.g. blocking tasks or child processes.
+    async fn command(&mut self, _cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         unimplemented!();
     }
 }
+
+pub(crate) trait BusyHandler {
+    /// Returns the result of `cmd` or `None` if it has to be queued until the
+    /// job finished. `job` cancels the running job.
+    fn handle(&self, cmd: &Command, job: &CancellationToken) -> Option<CommandResult>;
+}
+
+/// Executes `cmd` bounded by `Command::timeout`. If the timeout expires, `cancel`
+/// is triggered, the command future is dropped and `CommandTimeoutError` is
+/// returned.
+pub(crate) async fn command_with_timeout(
+    feature: &mut DynFeature<'static>,
+    cmd: &Command,
+    cancel: CancellationToken,
+) -> CommandResult {
+    command_with_timeout_of(feature, cmd, cmd.timeout(), cancel).await
+}
+
+async fn command_with_timeout_of(
+    feature: &mut DynFeature<'static>,
+    cmd: &Command,
+    timeout: Option<Duration>,
+    cancel: CancellationToken,
+) -> CommandResult {
+    let Some(timeout) = timeout else {
+        return feature.command(cmd, cancel).await;
+    };
+
+    match tokio::time::timeout(timeout, feature.command(cmd, cancel.clone())).await {
+        Ok(result) => result,
+        Err(_) => {
+            cancel.cancel();
+            Err(CommandTimeoutError {
+                command: cmd.into(),
+                timeout,
+            }
+            .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{any::TypeId, path::PathBuf};
+
+    struct HangingFeature;
+
+    impl Feature for HangingFeature {
+        fn name(&self) -> String {
+            "hanging".to_string()
+        }
+
+        fn version(&self) -> u8 {
+            1
+        }
+
+        fn is_enabled(&self) -> bool {
+            true
+        }
+
+        async fn command(&mut self, _cmd: &Command, _cancel: CancellationToken) -> CommandResult {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn command_with_timeout_test() {
+        let mut feature = DynFeature::new_box(HangingFeature);
+        let cancel = CancellationToken::new();
+        let cmd = Command::FsEvent(FsEventCommand {
+            kind: FsEventKind::FileModified,
+            feature_id: TypeId::of::<HangingFeature>(),
+            path: PathBuf::from("/unused"),
+        });
+
+        let err = command_with_timeout_of(
+            &mut feature,
+            &cmd,
+            Some(Duration::from_secs(1)),
+            cancel.clone(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(err.is::<CommandTimeoutError>());
+        assert_eq!(err.to_string(), "FsEvent timed out after 1s");
+        assert!(cancel.is_cancelled());
+    }
+}
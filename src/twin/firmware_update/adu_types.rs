@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Serialize, Deserialize)]
 pub struct UpdateId {
@@ -55,11 +55,30 @@ pub struct File {
     pub hashes: HashMap<String, String>,
 }
 
+/// Set of device properties an update is compatible with, e.g. manufacturer,
+/// model and compatibilityid.
 #[derive(Serialize, Deserialize)]
-pub struct Compatibility {
-    pub manufacturer: String,
-    pub model: String,
-    pub compatibilityid: String,
+pub struct Compatibility(pub BTreeMap<String, String>);
+
+impl Compatibility {
+    /// Returns the names of all properties of this entry that differ from the
+    /// properties of `agent` or that `agent` doesn't have. Properties only
+    /// known by `agent` don't matter.
+    pub fn mismatches<'a>(&'a self, agent: &'a Agent) -> Vec<&'a str> {
+        let properties = agent.properties();
+
+        properties
+            .iter()
+            .filter(|(k, v)| self.0.get(*k).is_some_and(|c| c.as_str() != *v))
+            .map(|(k, _)| *k)
+            .chain(
+                self.0
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|k| !properties.iter().any(|(p, _)| p == k)),
+            )
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -74,19 +93,36 @@ pub struct ImportManifest {
     pub manifest_version: String,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct AdditionalDeviceProperties {
-    pub compatibilityid: String,
-}
+#[derive(Default, Deserialize, Serialize)]
+pub struct AdditionalDeviceProperties(pub BTreeMap<String, String>);
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Agent {
     pub manufacturer: String,
     pub model: String,
+    #[serde(default)]
     pub additional_device_properties: AdditionalDeviceProperties,
 }
 
+impl Agent {
+    /// Manufacturer, model and all additional device properties.
+    pub fn properties(&self) -> Vec<(&str, &str)> {
+        [
+            ("manufacturer", self.manufacturer.as_str()),
+            ("model", self.model.as_str()),
+        ]
+        .into_iter()
+        .chain(
+            self.additional_device_properties
+                .0
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        )
+        .collect()
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct DeviceUpdateConfig {
     pub agents: Vec<Agent>,
@@ -1,34 +1,97 @@
 mod adu_types;
 mod common;
+mod download;
 mod os_version;
+mod progress;
+mod update_history;
+mod update_hooks;
+mod update_policy;
+mod update_schedule;
 pub mod update_validation;
+mod validation_policy;
 
 use crate::{
     bootloader_env,
     common::{RootPartition, from_json_file, path_ends_with, to_json_file},
-    systemd,
+    reboot_inhibitor, systemd,
     systemd::{unit::UnitAction, watchdog::WatchdogManager},
     twin::{
         Feature,
         feature::*,
-        firmware_update::{adu_types::*, common::*, os_version::*},
+        firmware_update::{
+            adu_types::*, common::*, os_version::*, progress::*, update_history::*,
+            update_hooks::*, update_policy::*, update_schedule::*,
+        },
     },
 };
-use anyhow::{Context, Result, bail, ensure};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use azure_iot_sdk::client::IotMessage;
 use base64::{Engine, prelude::BASE64_STANDARD};
+use futures::StreamExt;
+use lazy_static::lazy_static;
 use log::{debug, error, info, warn};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Verifier};
 use serde::Deserialize;
+use serde_json::json;
 use sha2::{Digest, Sha256};
 use std::{
     env, fs,
+    io::{self, Read, Write},
     path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
     time::Duration,
 };
 use tar::Archive;
+use time::OffsetDateTime;
+use tokio::{sync::mpsc::Sender, time::interval};
+use tokio_util::sync::CancellationToken;
 use update_validation::UpdateValidation;
 
+pub use download::DownloadUpdateCommand;
+pub use update_history::UpdateHistory;
+pub use update_policy::VersionOptions;
+pub use update_schedule::RunSchedule;
+pub use update_validation::ExtendUpdateValidationCommand;
+
+lazy_static! {
+    static ref UPDATE_SCHEDULE_CHECK_INTERVAL_SECS: u64 = {
+        const UPDATE_SCHEDULE_CHECK_INTERVAL_SECS_DEFAULT: &str = "60";
+        env::var("UPDATE_SCHEDULE_CHECK_INTERVAL_SECS")
+            .unwrap_or(UPDATE_SCHEDULE_CHECK_INTERVAL_SECS_DEFAULT.to_string())
+            .parse::<u64>()
+            .expect("cannot parse UPDATE_SCHEDULE_CHECK_INTERVAL_SECS env var")
+    };
+}
+
 static LOAD_UPDATE_WDT_INTERVAL_SECS: u64 = 120;
 static RUN_UPDATE_WDT_INTERVAL_SECS: u64 = 600;
+static MANIFEST_SIGNATURE_MAX_SIZE: u64 = 64 * 1024;
+
+// Blocking work (extraction, swupdate, rollback) keeps running if its command
+// future was dropped, e.g. on timeout. The lock prevents that a subsequent
+// load, run or rollback interferes with such a detached task.
+static BLOCKING_WORK_LOCK: Mutex<()> = Mutex::new(());
+
+// tees everything written to `inner` into `hasher`
+struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
 struct LoadUpdateGuard {
     wdt: Option<Duration>,
@@ -89,13 +152,17 @@ impl RunUpdateGuard {
             UnitAction::Stop,
             systemd_zbus::Mode::Replace,
         )
-        .await?;
+        .await
+        .context(format!(
+            "failed to stop {IOT_HUB_DEVICE_UPDATE_SERVICE_TIMER}"
+        ))?;
         systemd::unit::unit_action(
             IOT_HUB_DEVICE_UPDATE_SERVICE,
             UnitAction::Stop,
             systemd_zbus::Mode::Replace,
         )
-        .await?;
+        .await
+        .context(format!("failed to stop {IOT_HUB_DEVICE_UPDATE_SERVICE}"))?;
 
         debug!("stopped {IOT_HUB_DEVICE_UPDATE_SERVICE}");
 
@@ -115,15 +182,16 @@ impl RunUpdateGuard {
     /// them.  Extracted as a free-standing associated function so `Drop` can
     /// move the fields into `spawn_blocking` without borrowing `self`.
     fn do_rollback(bootloader_updated: bool, bootargs_omnect_backup: Option<PathBuf>) {
+        let _lock = BLOCKING_WORK_LOCK.lock();
+
         // cannot rollback a stable,bootloader image once flashed
         if bootloader_updated {
             warn!("bootloader was updated: rollback not possible");
             return;
         }
 
-        if let Err(e) = bootloader_env::unset(OMNECT_VALIDATE_EXTRA_BOOTARGS) {
-            error!("failed to unset {OMNECT_VALIDATE_EXTRA_BOOTARGS}: {e:#}");
-        }
+        let mut tx = bootloader_env::Transaction::default();
+        tx.unset(OMNECT_VALIDATE_EXTRA_BOOTARGS);
 
         if let Some(backup) = bootargs_omnect_backup {
             let omnect_file = bootargs_omnect_file_path!();
@@ -137,19 +205,20 @@ impl RunUpdateGuard {
                         fs::read_to_string(bootargs_custom_file_path!()).unwrap_or_default();
                     let new_bootargs = merge_bootargs(&omnect_args, &custom_args);
 
-                    let result = if new_bootargs.is_empty() {
-                        bootloader_env::unset(OMNECT_EXTRA_BOOTARGS)
+                    if new_bootargs.is_empty() {
+                        tx.unset(OMNECT_EXTRA_BOOTARGS);
                     } else {
-                        bootloader_env::set(OMNECT_EXTRA_BOOTARGS, &new_bootargs)
-                    };
-                    if let Err(e) = result {
-                        error!("failed to restore {OMNECT_EXTRA_BOOTARGS}: {e:#}");
+                        tx.set(OMNECT_EXTRA_BOOTARGS, &new_bootargs);
                     }
                 }
             }
             let _ = fs::remove_file(&backup);
         }
 
+        if let Err(e) = tx.commit() {
+            error!("failed to rollback bootloader environment: {e:#}");
+        }
+
         let _ = fs::remove_file(update_validation_config_path!());
     }
 }
@@ -205,20 +274,110 @@ impl Drop for RunUpdateGuard {
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct LoadUpdateCommand {
     pub update_file_path: PathBuf,
+    #[serde(flatten)]
+    pub version_options: VersionOptions,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct RunUpdateCommand {
     pub validate_iothub_connection: bool,
+    /// defer the installation instead of running it immediately
+    #[serde(default)]
+    pub schedule: Option<RunSchedule>,
+    /// ignore reboot inhibitors
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Desired `maintenance_windows` property, a json merge patch in case of a
+/// partial twin update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DesiredMaintenanceWindowsCommand {
+    pub patch: serde_json::Value,
+    pub complete: bool,
+}
+
+/// Status of the firmware update feature, shared in order to answer status
+/// requests while a job owns the feature.
+#[derive(Clone, Default)]
+struct SharedStatus {
+    loaded_manifest: Arc<Mutex<Option<serde_json::Value>>>,
+    installing: Arc<AtomicBool>,
+}
+
+impl SharedStatus {
+    fn status(&self) -> CommandResult {
+        let manifest = self
+            .loaded_manifest
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let update_folder = update_folder_path!();
+        let update_folder = Path::new(&update_folder);
+
+        Ok(Some(json!({
+            "loaded": manifest.is_some(),
+            "loaded_version": manifest.as_ref().map(|m| &m["updateId"]["version"]),
+            "manifest": manifest,
+            "current_version": OmnectOsVersion::from_sw_versions_file()
+                .ok()
+                .map(|v| v.to_string()),
+            "disk_usage": {
+                "update_folder": FirmwareUpdate::folder_size(update_folder),
+                "available": download::available_space(update_folder),
+            }
+        })))
+    }
+
+    fn set_installing(&self, installing: bool) {
+        self.installing.store(installing, Ordering::Relaxed);
+    }
+
+    fn set_loaded_manifest(&self, manifest: Option<&ImportManifest>) {
+        *self
+            .loaded_manifest
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) =
+            manifest.and_then(|m| serde_json::to_value(m).ok());
+    }
+}
+
+// a cancel interrupts a running job and clears the loaded update after it
+// finished, unless the installation already started
+impl BusyHandler for SharedStatus {
+    fn handle(&self, cmd: &Command, job: &CancellationToken) -> Option<CommandResult> {
+        match cmd {
+            Command::GetFirmwareUpdateStatus => Some(self.status()),
+            Command::CancelFirmwareUpdate if self.installing.load(Ordering::Relaxed) => Some(Err(
+                anyhow!("cannot cancel firmware update: installation already started"),
+            )),
+            Command::CancelFirmwareUpdate => {
+                info!("cancel running firmware update job");
+                job.cancel();
+                None
+            }
+            _ => None,
+        }
+    }
 }
 
 pub struct FirmwareUpdate {
     swu_file_path: Option<PathBuf>,
+    loaded_manifest: Option<ImportManifest>,
+    shared_status: SharedStatus,
+    // pre-load hooks run before there is a history entry to add them to
+    load_hook_results: Vec<HookResult>,
     update_validation: UpdateValidation,
+    tx_reported_properties: Option<Sender<serde_json::Value>>,
 }
 
 impl Drop for FirmwareUpdate {
     fn drop(&mut self) {
+        // a scheduled update must survive service restarts
+        if UpdateSchedule::load().scheduled_run.is_some() {
+            return;
+        }
+
         if let Err(e) = Self::clean_working_dir() {
             error!("failed to clean working directory: {e:#}")
         }
@@ -238,15 +397,98 @@ impl Feature for FirmwareUpdate {
         env::var("SUPPRESS_FIRMWARE_UPDATE") != Ok("true".to_string())
     }
 
+    async fn connect_twin(
+        &mut self,
+        tx_reported_properties: Sender<serde_json::Value>,
+        tx_outgoing_message: Sender<IotMessage>,
+    ) -> Result<()> {
+        self.update_validation
+            .connect_twin(tx_reported_properties.clone(), tx_outgoing_message)
+            .await;
+        UpdateHistory::load()
+            .report(Some(&tx_reported_properties))
+            .await?;
+        self.tx_reported_properties = Some(tx_reported_properties);
+        self.report_scheduled_run(UpdateSchedule::load().scheduled_run.as_ref())
+            .await
+    }
+
     async fn connect_web_service(&self) -> Result<()> {
         self.update_validation.report().await;
+        UpdateHistory::load().report(None).await?;
         Ok(())
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    fn command_request_stream(&mut self, _cancel: CancellationToken) -> CommandRequestStreamResult {
+        if !self.is_enabled() || 0 == *UPDATE_SCHEDULE_CHECK_INTERVAL_SECS {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            tokio_stream::wrappers::IntervalStream::new(interval(Duration::from_secs(
+                *UPDATE_SCHEDULE_CHECK_INTERVAL_SECS,
+            )))
+            .filter_map(|_| async {
+                UpdateSchedule::load()
+                    .is_due(OffsetDateTime::now_utc())
+                    .then_some(CommandRequest {
+                        command: Command::RunScheduledFirmwareUpdate,
+                        reply: None,
+                    })
+            })
+            .boxed(),
+        ))
+    }
+
+    fn busy_handler(&self) -> Option<Box<dyn BusyHandler>> {
+        Some(Box::new(self.shared_status.clone()))
+    }
+
+    async fn command(&mut self, cmd: &Command, cancel: CancellationToken) -> CommandResult {
         match cmd {
-            Command::LoadFirmwareUpdate(cmd) => self.load(&cmd.update_file_path).await,
-            Command::RunFirmwareUpdate(cmd) => self.run(cmd.validate_iothub_connection).await,
+            Command::DownloadFirmwareUpdate(cmd) => {
+                let path = download::download(cmd, &cancel).await?;
+                self.load(&path, cmd.version_options, cancel).await
+            }
+            Command::LoadFirmwareUpdate(cmd) => {
+                self.load(&cmd.update_file_path, cmd.version_options, cancel)
+                    .await
+            }
+            Command::RunFirmwareUpdate(RunUpdateCommand {
+                validate_iothub_connection,
+                schedule: Some(schedule),
+                force,
+            }) => {
+                self.schedule_run(*validate_iothub_connection, schedule, *force)
+                    .await
+            }
+            Command::RunFirmwareUpdate(cmd) => {
+                self.run(cmd.validate_iothub_connection, cmd.force).await
+            }
+            Command::RunScheduledFirmwareUpdate => self.run_scheduled().await,
+            Command::CancelScheduledFirmwareUpdate => {
+                self.cancel_scheduled_run().await?;
+                Ok(None)
+            }
+            Command::DesiredMaintenanceWindows(cmd) => {
+                let mut schedule = UpdateSchedule::load();
+                schedule.apply_maintenance_windows(&cmd.patch, cmd.complete)?;
+                schedule.save()?;
+                Ok(None)
+            }
+            Command::GetFirmwareUpdateStatus => self.shared_status.status(),
+            Command::CancelFirmwareUpdate => self.cancel().await,
+            Command::ConfirmUpdateValidation => {
+                self.update_validation.confirm().await?;
+                Ok(None)
+            }
+            Command::RejectUpdateValidation => {
+                self.update_validation.reject().await?;
+                Ok(None)
+            }
+            Command::ExtendUpdateValidation(cmd) => {
+                Ok(Some(self.update_validation.extend(cmd).await?))
+            }
             Command::ValidateUpdate(authenticated) => {
                 self.update_validation
                     .set_authenticated(*authenticated)
@@ -259,35 +501,131 @@ impl Feature for FirmwareUpdate {
 }
 
 impl FirmwareUpdate {
-    const FIRMWARE_UPDATE_VERSION: u8 = 1;
+    const FIRMWARE_UPDATE_VERSION: u8 = 2;
     const ID: &'static str = "firmware_update";
 
     pub async fn new() -> Result<Self> {
-        Ok(FirmwareUpdate {
+        let mut firmware_update = FirmwareUpdate {
             swu_file_path: None,
+            loaded_manifest: None,
+            shared_status: SharedStatus::default(),
+            load_hook_results: vec![],
             update_validation: UpdateValidation::new().await?,
-        })
+            tx_reported_properties: None,
+        };
+
+        firmware_update.restore_scheduled_run()?;
+
+        Ok(firmware_update)
+    }
+
+    // the update of a scheduled run stays loaded across service restarts
+    fn restore_scheduled_run(&mut self) -> Result<()> {
+        let mut schedule = UpdateSchedule::load();
+
+        let Some(run) = &schedule.scheduled_run else {
+            return Ok(());
+        };
+
+        if !run.swu_file_path.exists() {
+            warn!(
+                "drop scheduled run since {:?} doesn't exist anymore",
+                run.swu_file_path
+            );
+            schedule.scheduled_run = None;
+            return schedule.save();
+        }
+
+        info!("restore scheduled run of {:?}", run.swu_file_path);
+
+        self.swu_file_path = Some(run.swu_file_path.clone());
+        self.set_loaded_manifest(
+            fs::read_dir(update_folder_path!())
+                .ok()
+                .and_then(|entries| {
+                    entries
+                        .flatten()
+                        .map(|e| e.path())
+                        .find(|p| path_ends_with(p, ".swu.importManifest.json"))
+                })
+                .and_then(|p| from_json_file(p).ok()),
+        );
+
+        Ok(())
     }
 
-    async fn load<P>(&mut self, path: P) -> CommandResult
+    async fn load<P>(
+        &mut self,
+        path: P,
+        version_options: VersionOptions,
+        cancel: CancellationToken,
+    ) -> CommandResult
     where
         P: AsRef<Path>,
     {
+        // a newly loaded update replaces the scheduled one
+        self.cancel_scheduled_run().await?;
         self.swu_file_path = None;
+        self.set_loaded_manifest(None);
+
+        self.load_hook_results = run_hooks(HookPhase::PreLoad, None).await;
+        ensure_hooks_succeeded(&self.load_hook_results)?;
 
         let _guard = LoadUpdateGuard::new().await?;
+        let path = path.as_ref().to_path_buf();
+
+        // extracting and hashing large archives must not block the twin run loop
+        let (swu_path, manifest) =
+            tokio::task::spawn_blocking(move || Self::extract(&path, &cancel))
+                .await
+                .context("failed to join load task")??;
+
+        self.check_version(&manifest, version_options).await?;
+
+        let result = serde_json::to_value(&manifest).context("failed to serialize manifest")?;
+
+        self.swu_file_path = Some(swu_path);
+        self.set_loaded_manifest(Some(manifest));
+
+        Ok(Some(result))
+    }
+
+    fn set_loaded_manifest(&mut self, manifest: Option<ImportManifest>) {
+        self.shared_status.set_loaded_manifest(manifest.as_ref());
+        self.loaded_manifest = manifest;
+    }
+
+    async fn cancel(&mut self) -> CommandResult {
+        self.cancel_scheduled_run().await?;
+        self.swu_file_path = None;
+        self.set_loaded_manifest(None);
+
+        if Path::new(&update_folder_path!()).exists() {
+            Self::clean_working_dir().context("failed to clean working directory")?;
+        }
+
+        info!("firmware update cancelled");
+
+        Ok(None)
+    }
+
+    fn extract(path: &Path, cancel: &CancellationToken) -> Result<(PathBuf, ImportManifest)> {
+        let _lock = BLOCKING_WORK_LOCK.lock();
+
         let du_config: DeviceUpdateConfig = from_json_file(du_config_path!())?;
-        let current_version = OmnectOsVersion::from_sw_versions_file()?;
         let mut ar = Archive::new(fs::File::open(path).context("failed to open archive")?);
         let mut swu_path = None;
         let mut swu_sha = String::from("");
         let mut manifest_path = None;
         let mut manifest_sha1 = String::from("");
         let mut manifest_sha2 = String::from("");
+        let mut manifest_signature = None;
 
         Self::clean_working_dir().context("failed to clean working directory")?;
 
         for file in ar.entries().context("failed to get archive entries")? {
+            ensure!(!cancel.is_cancelled(), "load update cancelled");
+
             let mut file = file.context("failed to get archive entry")?;
             let path = file.path().context("failed to get entry path")?;
 
@@ -301,25 +639,29 @@ impl FirmwareUpdate {
             let path = Path::new(&update_folder_path!()).join(path);
 
             if path_ends_with(&path, ".swu") {
-                file.unpack(&path).context("failed to unpack *.swu")?;
-                swu_sha = BASE64_STANDARD.encode(Sha256::digest(
-                    std::fs::read(&path).context("failed to read *.swu for hash")?,
-                ));
+                swu_sha = BASE64_STANDARD.encode(
+                    Self::unpack_hashed(&mut file, &path).context("failed to unpack *.swu")?,
+                );
                 swu_path = Some(path);
             } else if path_ends_with(&path, ".swu.importManifest.json") {
-                file.unpack(&path)
-                    .context("failed to unpack *.swu.importManifest.json")?;
-                manifest_sha1 = Sha256::digest(
-                    std::fs::read(&path)
-                        .context("failed to read *.swu.importManifest.json for hash")?,
-                )
-                .iter()
-                .fold(String::new(), |mut s, b| {
-                    use std::fmt::Write;
-                    let _ = write!(s, "{b:02X}");
-                    s
-                });
+                manifest_sha1 = Self::unpack_hashed(&mut file, &path)
+                    .context("failed to unpack *.swu.importManifest.json")?
+                    .iter()
+                    .fold(String::new(), |mut s, b| {
+                        use std::fmt::Write;
+                        let _ = write!(s, "{b:02X}");
+                        s
+                    });
                 manifest_path = Some(path);
+            } else if path_ends_with(&path, ".swu.importManifest.json.sig") {
+                ensure!(
+                    file.header().size()? <= MANIFEST_SIGNATURE_MAX_SIZE,
+                    "*.swu.importManifest.json.sig too large"
+                );
+                let mut signature = vec![];
+                file.read_to_end(&mut signature)
+                    .context("failed to read *.swu.importManifest.json.sig")?;
+                manifest_signature = Some(signature);
             } else if path_ends_with(&path, ".swu.importManifest.json.sha256") {
                 file.unpack(&path)
                     .context("failed to unpack *.swu.importManifest.json.sha256")?;
@@ -344,6 +686,18 @@ impl FirmwareUpdate {
             bail!("*.swu.importManifest.json missing");
         };
 
+        // the manifest must not be trusted before its signature is verified
+        match manifest_signature {
+            Some(signature) => Self::verify_manifest_signature(&manifest_path, &signature)
+                .context("failed to verify *.swu.importManifest.json signature")?,
+            None if Self::manifest_signature_required() => {
+                bail!("*.swu.importManifest.json.sig missing")
+            }
+            None => warn!(
+                "*.swu.importManifest.json.sig missing: load unsigned update since REQUIRE_MANIFEST_SIGNATURE is false"
+            ),
+        }
+
         let Some(swu_path) = swu_path else {
             bail!("*.swu missing");
         };
@@ -369,62 +723,258 @@ impl FirmwareUpdate {
             "failed to verify *.swu hash"
         );
 
+        Self::verify_compatibility(&du_config, &manifest)?;
+
+        Ok((swu_path, manifest))
+    }
+
+    /// Only newer versions are installed, unless the caller requests a
+    /// downgrade or reinstall and the update policy permits it.
+    async fn check_version(
+        &self,
+        manifest: &ImportManifest,
+        version_options: VersionOptions,
+    ) -> Result<()> {
+        let current_version = OmnectOsVersion::from_sw_versions_file()?;
+        let new_version = OmnectOsVersion::from_string(&manifest.update_id.version)?;
+
+        let kind = if current_version == new_version {
+            if !version_options.allow_reinstall {
+                bail!("version {current_version} already installed")
+            }
+            VersionOverrideKind::Reinstall
+        } else if current_version > new_version {
+            if !version_options.allow_downgrade {
+                bail!("downgrades not allowed ({new_version} < {current_version} )")
+            }
+            VersionOverrideKind::Downgrade
+        } else {
+            info!(
+                "successfully loaded update (current version: {current_version} new version: {new_version})"
+            );
+            return Ok(());
+        };
+
+        let policy = UpdatePolicy::load().context("failed to load update policy")?;
+        let permitted = match kind {
+            VersionOverrideKind::Downgrade => policy.allow_downgrade,
+            VersionOverrideKind::Reinstall => policy.allow_reinstall,
+        };
+
+        if permitted {
+            warn!("{kind} from {current_version} to {new_version} permitted by update policy");
+        } else {
+            warn!("{kind} from {current_version} to {new_version} denied by update policy");
+        }
+
+        self.report_version_override(&VersionOverride {
+            kind,
+            current_version: current_version.to_string(),
+            new_version: new_version.to_string(),
+            permitted,
+        })
+        .await?;
+
         ensure!(
-            du_config.agents[0].manufacturer == manifest.compatibility[0].manufacturer,
-            "failed to verify compatibility: manufacturer"
+            permitted,
+            "{kind} from {current_version} to {new_version} not permitted by update policy"
         );
+
+        Ok(())
+    }
+
+    async fn report_version_override(&self, version_override: &VersionOverride) -> Result<()> {
+        let Some(tx) = &self.tx_reported_properties else {
+            warn!("report_version_override: skip since tx_reported_properties is None");
+            return Ok(());
+        };
+
+        tx.send(json!({
+            "firmware_update": {
+                "version_override": version_override
+            }
+        }))
+        .await
+        .context("report_version_override: send")
+    }
+
+    async fn schedule_run(
+        &mut self,
+        validate_iothub_connection: bool,
+        schedule: &RunSchedule,
+        force: bool,
+    ) -> CommandResult {
+        let Some(swu_file_path) = self.swu_file_path.clone() else {
+            bail!("no update loaded")
+        };
+
+        let mut update_schedule = UpdateSchedule::load();
+
+        update_schedule.validate(schedule)?;
+        update_schedule.scheduled_run = Some(ScheduledRun {
+            schedule: schedule.clone(),
+            validate_iothub_connection,
+            force,
+            swu_file_path,
+            version: self
+                .loaded_manifest
+                .as_ref()
+                .map(|m| m.update_id.version.clone()),
+        });
+        update_schedule.save()?;
+
+        info!("scheduled update: {schedule:?}");
+
+        self.report_scheduled_run(update_schedule.scheduled_run.as_ref())
+            .await?;
+
+        Ok(Some(
+            json!({"scheduled_run": update_schedule.scheduled_run}),
+        ))
+    }
+
+    async fn run_scheduled(&mut self) -> CommandResult {
+        let mut schedule = UpdateSchedule::load();
+
+        // the schedule might have been cancelled since it was found due. this
+        // must fail, since a successful command is expected to reboot.
         ensure!(
-            du_config.agents[0].model == manifest.compatibility[0].model,
-            "failed to verify compatibility: model"
+            schedule.is_due(OffsetDateTime::now_utc()),
+            "run_scheduled: no scheduled update due"
         );
+
+        let run = schedule
+            .scheduled_run
+            .take()
+            .context("run_scheduled: scheduled run missing")?;
+
+        // a failed run must not be repeated
+        schedule.save()?;
+        self.report_scheduled_run(None).await?;
+
         ensure!(
-            du_config.agents[0]
-                .additional_device_properties
-                .compatibilityid
-                == manifest.compatibility[0].compatibilityid,
-            "failed to verify compatibility: compatibilityid"
+            self.swu_file_path.as_ref() == Some(&run.swu_file_path),
+            "scheduled update {:?} is not loaded anymore",
+            run.swu_file_path
         );
 
-        let new_version = OmnectOsVersion::from_string(&manifest.update_id.version)?;
+        info!("run scheduled update");
 
-        if current_version == new_version {
-            bail!("version {current_version} already installed")
-        }
+        self.run(run.validate_iothub_connection, run.force).await
+    }
 
-        if current_version > new_version {
-            bail!("downgrades not allowed ({new_version} < {current_version} )")
+    async fn cancel_scheduled_run(&self) -> Result<()> {
+        let mut schedule = UpdateSchedule::load();
+
+        if schedule.scheduled_run.take().is_none() {
+            return Ok(());
         }
 
-        info!(
-            "successfully loaded update (current version: {current_version} new version: {new_version})"
-        );
+        schedule.save()?;
 
-        self.swu_file_path = Some(swu_path);
+        info!("scheduled update cancelled");
 
-        Ok(Some(
-            serde_json::to_value(manifest).context("failed to serialize manifest")?,
-        ))
+        self.report_scheduled_run(None).await
     }
 
-    async fn run(&mut self, validate_iothub_connection: bool) -> CommandResult {
-        let Some(ref swu_file_path) = self.swu_file_path else {
+    async fn report_scheduled_run(&self, scheduled_run: Option<&ScheduledRun>) -> Result<()> {
+        let Some(tx) = &self.tx_reported_properties else {
+            warn!("report_scheduled_run: skip since tx_reported_properties is None");
+            return Ok(());
+        };
+
+        tx.send(json!({
+            "firmware_update": {
+                "scheduled_run": scheduled_run
+            }
+        }))
+        .await
+        .context("report_scheduled_run: send")
+    }
+
+    async fn run(&mut self, validate_iothub_connection: bool, force: bool) -> CommandResult {
+        let Some(swu_file_path) = self.swu_file_path.clone() else {
             bail!("no update loaded")
         };
 
+        // the update is only installed if the device may reboot afterwards
+        reboot_inhibitor::ensure_reboot_allowed(force).await?;
+
+        let from_version = OmnectOsVersion::from_sw_versions_file()
+            .ok()
+            .map(|v| v.to_string());
+        let to_version = self
+            .loaded_manifest
+            .as_ref()
+            .map(|m| m.update_id.version.clone());
+
+        let mut entry = UpdateHistoryEntry::new(UpdateSource::Local, from_version, to_version);
+        entry.hooks = std::mem::take(&mut self.load_hook_results);
+
+        self.update_history(|h| h.start(entry)).await;
+
+        // swupdate cannot be interrupted, thus the installation cannot be
+        // cancelled once started
+        self.shared_status.set_installing(true);
+
+        let result = Self::install(
+            &swu_file_path,
+            self.loaded_manifest
+                .as_ref()
+                .map(|m| m.update_id.version.as_str()),
+            validate_iothub_connection,
+            force,
+            self.tx_reported_properties.clone(),
+        )
+        .await;
+
+        self.shared_status.set_installing(false);
+
+        // a successful installation is finished by the update validation
+        // after the reboot
+        if let Err(e) = result {
+            self.update_history(|h| h.finish(None, Some(format!("{e:#}"))))
+                .await;
+            return Err(e);
+        }
+
+        Ok(None)
+    }
+
+    async fn install(
+        swu_file_path: &Path,
+        version: Option<&str>,
+        validate_iothub_connection: bool,
+        force: bool,
+        tx_reported_properties: Option<Sender<serde_json::Value>>,
+    ) -> Result<()> {
         let target_partition = RootPartition::current()?.other();
 
+        Self::run_hooks(HookPhase::PreInstall, version).await?;
+
         let mut guard = RunUpdateGuard::new().await?;
 
         #[cfg(not(feature = "mock"))]
-        Self::swupdate(swu_file_path, target_partition.root_update_params()).context(format!(
+        observe(
+            UpdateStep::Root,
+            tx_reported_properties.clone(),
+            Self::swupdate(swu_file_path, target_partition.root_update_params()),
+        )
+        .await
+        .context(format!(
             "failed to update root partition: swupdate logs at {}",
             log_file_path!()
         ))?;
 
         let _ = fs::remove_file(no_bootloader_updated_file_path!());
 
-        let bootloader_updated =
-            Self::swupdate(swu_file_path, target_partition.bootloader_update_params()).is_ok();
+        let bootloader_updated = observe(
+            UpdateStep::Bootloader,
+            tx_reported_properties.clone(),
+            Self::swupdate(swu_file_path, target_partition.bootloader_update_params()),
+        )
+        .await
+        .is_ok();
 
         let _expected_file = if bootloader_updated {
             bootloader_updated_file_path!()
@@ -442,20 +992,44 @@ impl FirmwareUpdate {
 
         guard.bootloader_updated = bootloader_updated;
 
+        if let Err(e) = UpdateHistory::update(|h| {
+            if let Some(entry) = h.pending_mut() {
+                entry.bootloader_updated = Some(bootloader_updated);
+            }
+        }) {
+            error!("failed to update history: {e:#}");
+        }
+
         let omnect_file = bootargs_omnect_file_path!();
         let backup_file = PathBuf::from(bootargs_omnect_backup_file_path!());
         fs::copy(&omnect_file, &backup_file).context("failed to back up omnect bootargs file")?;
         guard.bootargs_omnect_backup = Some(backup_file);
 
         #[cfg(not(feature = "mock"))]
-        Self::swupdate(swu_file_path, target_partition.kernelargs_update_params()).context(
-            format!(
-                "failed to update kernelargs: swupdate logs at {}",
-                log_file_path!()
-            ),
-        )?;
+        observe(
+            UpdateStep::Kernelargs,
+            tx_reported_properties,
+            Self::swupdate(swu_file_path, target_partition.kernelargs_update_params()),
+        )
+        .await
+        .context(format!(
+            "failed to update kernelargs: swupdate logs at {}",
+            log_file_path!()
+        ))?;
+
+        // a failing pre-reboot hook rolls back the installation, thus the boot
+        // variables must not be written yet
+        Self::run_hooks(HookPhase::PreReboot, version).await?;
 
-        Self::apply_bootargs(bootloader_updated)?;
+        // reboots might have been inhibited during the installation, which
+        // rolls back the installation as well
+        reboot_inhibitor::ensure_reboot_allowed(force).await?;
+
+        // all bootloader variables are written at once, thus the device either
+        // boots the old or the new partition with matching bootargs
+        let mut tx = bootloader_env::Transaction::default();
+
+        Self::apply_bootargs(bootloader_updated, &mut tx)?;
 
         to_json_file(
             &UpdateValidationConfig {
@@ -466,15 +1040,17 @@ impl FirmwareUpdate {
         )?;
 
         if bootloader_updated {
-            bootloader_env::set(OMNECT_BOOTLOADER_UPDATED, "1")?;
-            bootloader_env::set(OMNECT_OS_BOOTPART, &target_partition.index().to_string())?;
+            tx.set(OMNECT_BOOTLOADER_UPDATED, "1")
+                .set(OMNECT_OS_BOOTPART, &target_partition.index().to_string());
         } else {
-            bootloader_env::set(
+            tx.set(
                 OMNECT_VALIDATE_UPDATE_PART,
                 &target_partition.index().to_string(),
-            )?;
+            );
         }
 
+        tx.commit()?;
+
         // explicitly finalize even if reboot fails
         // we don't want to rollback if the reboot call fails, but a system reboot is actually triggered
         guard.finalize();
@@ -483,10 +1059,45 @@ impl FirmwareUpdate {
 
         info!("update succeeded");
 
-        Ok(None)
+        Ok(())
+    }
+
+    // runs the hooks of `phase` and adds their results to the pending history entry
+    async fn run_hooks(phase: HookPhase, version: Option<&str>) -> Result<()> {
+        let results = run_hooks(phase, version).await;
+
+        if !results.is_empty()
+            && let Err(e) = UpdateHistory::update(|h| {
+                if let Some(entry) = h.pending_mut() {
+                    entry.hooks.extend(results.iter().cloned());
+                }
+            })
+        {
+            error!("failed to update history: {e:#}");
+        }
+
+        ensure_hooks_succeeded(&results)
     }
 
-    fn apply_bootargs(bootloader_updated: bool) -> Result<()> {
+    // a broken history must never break an update
+    async fn update_history<F>(&self, f: F)
+    where
+        F: FnOnce(&mut UpdateHistory),
+    {
+        match UpdateHistory::update(f) {
+            Ok(history) => {
+                if let Err(e) = history.report(self.tx_reported_properties.as_ref()).await {
+                    error!("failed to report update history: {e:#}");
+                }
+            }
+            Err(e) => error!("failed to update history: {e:#}"),
+        }
+    }
+
+    fn apply_bootargs(
+        bootloader_updated: bool,
+        tx: &mut bootloader_env::Transaction,
+    ) -> Result<()> {
         let current_bootargs = bootloader_env::get(OMNECT_EXTRA_BOOTARGS).unwrap_or_default();
         let omnect_bootargs = fs::read_to_string(bootargs_omnect_file_path!())?; // has to exist
         let custom_bootargs = match fs::read_to_string(bootargs_custom_file_path!()) {
@@ -498,23 +1109,30 @@ impl FirmwareUpdate {
 
         if current_bootargs != new_bootargs {
             match (bootloader_updated, new_bootargs.is_empty()) {
-                (true, true) => bootloader_env::unset(OMNECT_EXTRA_BOOTARGS)?,
-                (true, false) => bootloader_env::set(OMNECT_EXTRA_BOOTARGS, &new_bootargs)?,
-                (false, true) => {
-                    bootloader_env::set(OMNECT_VALIDATE_EXTRA_BOOTARGS, NOARGS_SENTINEL)?
-                }
-                (false, false) => {
-                    bootloader_env::set(OMNECT_VALIDATE_EXTRA_BOOTARGS, &new_bootargs)?
-                }
-            }
+                (true, true) => tx.unset(OMNECT_EXTRA_BOOTARGS),
+                (true, false) => tx.set(OMNECT_EXTRA_BOOTARGS, &new_bootargs),
+                (false, true) => tx.set(OMNECT_VALIDATE_EXTRA_BOOTARGS, NOARGS_SENTINEL),
+                (false, false) => tx.set(OMNECT_VALIDATE_EXTRA_BOOTARGS, &new_bootargs),
+            };
         }
         Ok(())
     }
 
-    fn swupdate<P>(swu_file_path: P, selection: &str) -> Result<()>
-    where
-        P: AsRef<std::ffi::OsStr>,
-    {
+    async fn swupdate(swu_file_path: &Path, selection: &str) -> Result<()> {
+        let swu_file_path = swu_file_path.to_path_buf();
+        let selection = selection.to_string();
+
+        // swupdate runs for minutes, so it must not block the twin run loop
+        tokio::task::spawn_blocking(move || Self::swupdate_blocking(&swu_file_path, &selection))
+            .await
+            .context("failed to join swupdate task")?
+    }
+
+    fn swupdate_blocking(swu_file_path: &Path, selection: &str) -> Result<()> {
+        // swupdate is never interrupted, since aborting e.g. a bootloader update
+        // could leave the device unbootable
+        let _lock = BLOCKING_WORK_LOCK.lock();
+
         let stdio_logfile = std::fs::OpenOptions::new()
             .write(true)
             .open(log_file_path!())
@@ -531,7 +1149,7 @@ impl FirmwareUpdate {
                 .arg("swupdate")
                 .arg("-v")
                 .arg("-i")
-                .arg(&swu_file_path)
+                .arg(swu_file_path)
                 .arg("-k")
                 .arg(pubkey_file_path!())
                 .arg("-e")
@@ -546,6 +1164,112 @@ impl FirmwareUpdate {
         Ok(())
     }
 
+    /// An update is compatible if any agent matches any compatibility entry of
+    /// the manifest in all of its properties.
+    fn verify_compatibility(
+        du_config: &DeviceUpdateConfig,
+        manifest: &ImportManifest,
+    ) -> Result<()> {
+        ensure!(
+            !du_config.agents.is_empty(),
+            "failed to verify compatibility: no agents in device update config"
+        );
+        ensure!(
+            !manifest.compatibility.is_empty(),
+            "failed to verify compatibility: no compatibility entries in manifest"
+        );
+
+        let mut mismatches = vec![];
+
+        for (a, agent) in du_config.agents.iter().enumerate() {
+            for (c, compatibility) in manifest.compatibility.iter().enumerate() {
+                let fields = compatibility.mismatches(agent);
+
+                if fields.is_empty() {
+                    debug!("agent {a} matches compatibility {c}");
+                    return Ok(());
+                }
+
+                mismatches.push(format!(
+                    "agent {a} / compatibility {c}: {}",
+                    fields.join(", ")
+                ));
+            }
+        }
+
+        bail!("failed to verify compatibility: {}", mismatches.join("; "))
+    }
+
+    fn manifest_signature_required() -> bool {
+        env::var("REQUIRE_MANIFEST_SIGNATURE") != Ok("false".to_string())
+    }
+
+    /// Verifies the detached signature of the import manifest against the
+    /// public key configured by `MANIFEST_PUBKEY_PATH`, or the swupdate public
+    /// key if not configured.
+    fn verify_manifest_signature(manifest_path: &Path, signature: &[u8]) -> Result<()> {
+        let pubkey_path = match env::var("MANIFEST_PUBKEY_PATH") {
+            Ok(path) => {
+                info!("verify manifest signature with MANIFEST_PUBKEY_PATH {path}");
+                path
+            }
+            Err(_) => {
+                let path = pubkey_file_path!();
+                info!("verify manifest signature with swupdate public key {path}");
+                path
+            }
+        };
+        let pubkey = PKey::public_key_from_pem(
+            &fs::read(&pubkey_path).context(format!("failed to read {pubkey_path}"))?,
+        )
+        .context(format!("failed to parse public key {pubkey_path}"))?;
+        let manifest = fs::read(manifest_path).context("failed to read manifest")?;
+        let mut verifier =
+            Verifier::new(MessageDigest::sha256(), &pubkey).context("failed to create verifier")?;
+
+        verifier
+            .update(&manifest)
+            .context("failed to hash manifest")?;
+
+        ensure!(
+            verifier.verify(signature).context("invalid signature")?,
+            "signature mismatch"
+        );
+
+        Ok(())
+    }
+
+    /// Unpacks `entry` to `path` and returns the SHA-256 of its content. The
+    /// hash is computed while writing, so the file is never read into memory.
+    fn unpack_hashed(entry: &mut impl Read, path: &Path) -> Result<Vec<u8>> {
+        let mut writer = HashWriter {
+            inner: fs::File::create(path).context(format!("failed to create {path:?}"))?,
+            hasher: Sha256::new(),
+        };
+
+        io::copy(entry, &mut writer).context(format!("failed to write {path:?}"))?;
+        writer
+            .flush()
+            .context(format!("failed to flush {path:?}"))?;
+
+        Ok(writer.hasher.finalize().to_vec())
+    }
+
+    fn folder_size(path: &Path) -> u64 {
+        let Ok(entries) = fs::read_dir(path) else {
+            return 0;
+        };
+
+        entries
+            .flatten()
+            .map(|e| match e.metadata() {
+                Ok(m) if m.is_dir() => Self::folder_size(&e.path()),
+                Ok(m) => m.len(),
+                Err(_) => 0,
+            })
+            .sum()
+    }
+
     fn clean_working_dir() -> Result<()> {
         for entry in fs::read_dir(update_folder_path!())? {
             let entry = entry?;
@@ -568,6 +1292,10 @@ mod tests {
 
     use crate::bootloader_env::TEST_LOCK as BOOTARGS_TEST_LOCK;
 
+    // load tests configure the same process-wide env vars, e.g. the public key
+    // of the manifest signature
+    static LOAD_TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
     // helper
     fn setup_bootargs_files(tmp: &tempfile::TempDir, omnect: &str, custom: &str) {
         let omnect_path = tmp.path().join("bootargs_omnect");
@@ -578,53 +1306,171 @@ mod tests {
         crate::common::set_env_var("BOOTARGS_CUSTOM_FILE_PATH", &custom_path);
     }
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn load_ok() {
-        let tmp_dir = tempfile::tempdir().unwrap();
-        let update_folder = tmp_dir.path().join("local_update");
-        let du_config_file = tmp_dir.path().join("du-config.json");
-        let sw_versions_file = tmp_dir.path().join("sw-versions");
-        std::fs::copy("testfiles/positive/du-config.json", &du_config_file).unwrap();
-        std::fs::copy("testfiles/positive/sw-versions", &sw_versions_file).unwrap();
-        std::fs::create_dir_all(&update_folder).unwrap();
+    // sets up update folder, device update config, sw-versions and the public
+    // key of the manifest signature for loading testfiles/*/update*.tar and
+    // returns the feature as well as the paths of du-config and sw-versions
+    async fn setup_load(tmp: &tempfile::TempDir) -> (FirmwareUpdate, PathBuf, PathBuf) {
+        let update_folder = tmp.path().join("local_update");
+        let du_config_file = tmp.path().join("du-config.json");
+        let sw_versions_file = tmp.path().join("sw-versions");
+        fs::copy("testfiles/positive/du-config.json", &du_config_file).unwrap();
+        fs::copy("testfiles/positive/sw-versions", &sw_versions_file).unwrap();
+        fs::create_dir_all(&update_folder).unwrap();
         crate::common::set_env_var("UPDATE_FOLDER_PATH", update_folder);
-        crate::common::set_env_var("DEVICE_UPDATE_PATH", du_config_file);
-        crate::common::set_env_var("SW_VERSIONS_PATH", sw_versions_file);
-        let update_validation = UpdateValidation::new().await.unwrap();
+        crate::common::set_env_var("DEVICE_UPDATE_PATH", &du_config_file);
+        crate::common::set_env_var(
+            "MANIFEST_PUBKEY_PATH",
+            "testfiles/positive/manifest-pub.pem",
+        );
+        crate::common::set_env_var("SW_VERSIONS_PATH", &sw_versions_file);
+        crate::common::remove_env_var("REQUIRE_MANIFEST_SIGNATURE");
 
-        let mut firmware_update = FirmwareUpdate {
+        let firmware_update = FirmwareUpdate {
             swu_file_path: None,
-            update_validation,
+            loaded_manifest: None,
+            shared_status: SharedStatus::default(),
+            load_hook_results: vec![],
+            update_validation: UpdateValidation::new().await.unwrap(),
+            tx_reported_properties: None,
         };
 
+        (firmware_update, du_config_file, sw_versions_file)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn load_ok() {
+        let _lock = LOAD_TEST_LOCK.lock().await;
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (mut firmware_update, _, _) = setup_load(&tmp_dir).await;
+
         firmware_update
-            .load("testfiles/positive/update.tar")
+            .load(
+                "testfiles/positive/update.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
+
+        let status = firmware_update.shared_status.status().unwrap().unwrap();
+        assert_eq!(status["loaded"], json!(true));
+        assert_eq!(status["current_version"], json!("4.0.10.0"));
+        assert_eq!(status["loaded_version"], json!("4.0.24.557123921"));
+        assert!(status["disk_usage"]["update_folder"].as_u64().unwrap() > 0);
+
+        firmware_update.cancel().await.unwrap();
+
+        let status = firmware_update.shared_status.status().unwrap().unwrap();
+        assert_eq!(status["loaded"], json!(false));
+        assert_eq!(status["manifest"], json!(null));
+        assert_eq!(status["disk_usage"]["update_folder"], json!(0));
+        assert!(firmware_update.swu_file_path.is_none());
+    }
+
+    #[test]
+    fn cancel_refused_while_installing() {
+        let status = SharedStatus::default();
+        let job = CancellationToken::new();
+
+        status.set_installing(true);
+
+        let err = status
+            .handle(&Command::CancelFirmwareUpdate, &job)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot cancel firmware update: installation already started"
+        );
+        assert!(!job.is_cancelled());
+
+        status.set_installing(false);
+
+        // cancelled job, the request itself is queued
+        assert!(
+            status
+                .handle(&Command::CancelFirmwareUpdate, &job)
+                .is_none()
+        );
+        assert!(job.is_cancelled());
     }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn load_sw_versions_fail() {
+    async fn load_signature_fail() {
+        let _lock = LOAD_TEST_LOCK.lock().await;
         let tmp_dir = tempfile::tempdir().unwrap();
-        let update_folder = tmp_dir.path().join("local_update");
-        let du_config_file = tmp_dir.path().join("du-config.json");
-        let sw_versions_file = tmp_dir.path().join("sw-versions");
-        std::fs::copy("testfiles/positive/du-config.json", &du_config_file).unwrap();
-        std::fs::create_dir_all(&update_folder).unwrap();
-        crate::common::set_env_var("UPDATE_FOLDER_PATH", update_folder);
-        crate::common::set_env_var("DEVICE_UPDATE_PATH", du_config_file);
-        crate::common::set_env_var("SW_VERSIONS_PATH", &sw_versions_file);
-        let update_validation = UpdateValidation::new().await.unwrap();
+        let (mut firmware_update, _, _) = setup_load(&tmp_dir).await;
 
-        let mut firmware_update = FirmwareUpdate {
-            swu_file_path: None,
-            update_validation,
-        };
+        let err = firmware_update
+            .load(
+                "testfiles/negative/update-unsigned.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.chain()
+                .any(|e| e.to_string() == "*.swu.importManifest.json.sig missing")
+        );
+
+        let err = firmware_update
+            .load(
+                "testfiles/negative/update-invalid-signature.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.chain().any(|e| {
+            e.to_string()
+                .starts_with("failed to verify *.swu.importManifest.json signature")
+        }));
+        assert!(firmware_update.swu_file_path.is_none());
+
+        // unsigned updates are only loaded if configured, invalid signatures never
+        crate::common::set_env_var("REQUIRE_MANIFEST_SIGNATURE", "false");
+
+        assert!(
+            firmware_update
+                .load(
+                    "testfiles/negative/update-invalid-signature.tar",
+                    VersionOptions::default(),
+                    CancellationToken::new(),
+                )
+                .await
+                .is_err()
+        );
+
+        firmware_update
+            .load(
+                "testfiles/negative/update-unsigned.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert!(firmware_update.swu_file_path.is_some());
+
+        crate::common::remove_env_var("REQUIRE_MANIFEST_SIGNATURE");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn load_sw_versions_fail() {
+        let _lock = LOAD_TEST_LOCK.lock().await;
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (mut firmware_update, _, sw_versions_file) = setup_load(&tmp_dir).await;
 
         fs::write(&sw_versions_file, "dobi-OMNECT-gateway-devel 40.0.0.0").unwrap();
 
         let err = firmware_update
-            .load("testfiles/positive/update.tar")
+            .load(
+                "testfiles/positive/update.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
             .await
             .unwrap_err();
 
@@ -640,7 +1486,11 @@ mod tests {
         .unwrap();
 
         let err = firmware_update
-            .load("testfiles/positive/update.tar")
+            .load(
+                "testfiles/positive/update.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
             .await
             .unwrap_err();
 
@@ -651,30 +1501,90 @@ mod tests {
     }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn load_compatibility_fail() {
+    async fn load_downgrade_policy() {
+        let _lock = LOAD_TEST_LOCK.lock().await;
         let tmp_dir = tempfile::tempdir().unwrap();
-        let update_folder = tmp_dir.path().join("local_update");
-        let du_config_file = tmp_dir.path().join("du-config.json");
-        let sw_versions_file = tmp_dir.path().join("sw-versions");
-        std::fs::copy("testfiles/positive/sw-versions", &sw_versions_file).unwrap();
-        std::fs::create_dir_all(&update_folder).unwrap();
-        crate::common::set_env_var("UPDATE_FOLDER_PATH", update_folder);
-        crate::common::set_env_var("DEVICE_UPDATE_PATH", &du_config_file);
-        crate::common::set_env_var("SW_VERSIONS_PATH", sw_versions_file);
-        let update_validation = UpdateValidation::new().await.unwrap();
-
-        let mut firmware_update = FirmwareUpdate {
-            swu_file_path: None,
-            update_validation,
+        let policy_file = tmp_dir.path().join("update_policy.json");
+        let (mut firmware_update, _, sw_versions_file) = setup_load(&tmp_dir).await;
+        fs::write(&sw_versions_file, "dobi-OMNECT-gateway-devel 40.0.0.0").unwrap();
+        crate::common::set_env_var("UPDATE_POLICY_PATH", &policy_file);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        firmware_update.tx_reported_properties = Some(tx);
+        let allow_downgrade = VersionOptions {
+            allow_downgrade: true,
+            allow_reinstall: false,
         };
 
+        // requested, but no policy
+        let err = firmware_update
+            .load(
+                "testfiles/positive/update.tar",
+                allow_downgrade,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "downgrade from 40.0.0.0 to 4.0.24.557123921 not permitted by update policy"
+        );
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            json!({"firmware_update": {"version_override": {
+                "kind": "downgrade",
+                "current_version": "40.0.0.0",
+                "new_version": "4.0.24.557123921",
+                "permitted": false
+            }}})
+        );
+        assert!(firmware_update.swu_file_path.is_none());
+
+        // permitted by policy
+        fs::write(&policy_file, r#"{"allow_downgrade": true}"#).unwrap();
+
+        firmware_update
+            .load(
+                "testfiles/positive/update.tar",
+                allow_downgrade,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            rx.recv().await.unwrap()["firmware_update"]["version_override"]["permitted"],
+            json!(true)
+        );
+        assert!(firmware_update.swu_file_path.is_some());
+
+        // policy permits downgrade, but it was not requested
+        let err = firmware_update
+            .load(
+                "testfiles/positive/update.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().starts_with("downgrades not allowed"));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn load_compatibility_fail() {
+        let _lock = LOAD_TEST_LOCK.lock().await;
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (mut firmware_update, du_config_file, _) = setup_load(&tmp_dir).await;
+
         let mut du_config = DeviceUpdateConfig {
             agents: vec![Agent {
                 manufacturer: "".to_string(),
                 model: "".to_string(),
-                additional_device_properties: AdditionalDeviceProperties {
-                    compatibilityid: "".to_string(),
-                },
+                additional_device_properties: AdditionalDeviceProperties(
+                    [("compatibilityid".to_string(), "".to_string())].into(),
+                ),
             }],
         };
 
@@ -685,13 +1595,16 @@ mod tests {
         .unwrap();
 
         let err = firmware_update
-            .load("testfiles/positive/update.tar")
+            .load(
+                "testfiles/positive/update.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
             .await
             .unwrap_err();
 
         assert!(err.chain().any(|e| {
-            e.to_string()
-                .starts_with("failed to verify compatibility: manufacturer")
+            e.to_string() == "failed to verify compatibility: agent 0 / compatibility 0: manufacturer, model, compatibilityid"
         }));
 
         du_config.agents[0].manufacturer = "conplement-ag".to_string();
@@ -703,13 +1616,17 @@ mod tests {
         .unwrap();
 
         let err = firmware_update
-            .load("testfiles/positive/update.tar")
+            .load(
+                "testfiles/positive/update.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
             .await
             .unwrap_err();
 
         assert!(err.chain().any(|e| {
             e.to_string()
-                .starts_with("failed to verify compatibility: model")
+                == "failed to verify compatibility: agent 0 / compatibility 0: model, compatibilityid"
         }));
 
         du_config.agents[0].model = "omnect-raspberrypi4-64-gateway-devel".to_string();
@@ -721,16 +1638,96 @@ mod tests {
         .unwrap();
 
         let err = firmware_update
-            .load("testfiles/positive/update.tar")
+            .load(
+                "testfiles/positive/update.tar",
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
             .await
             .unwrap_err();
 
         assert!(err.chain().any(|e| {
             e.to_string()
-                .starts_with("failed to verify compatibility: compatibilityid")
+                == "failed to verify compatibility: agent 0 / compatibility 0: compatibilityid"
         }));
     }
 
+    #[test]
+    fn verify_compatibility_any_agent_any_entry() {
+        let agent = |model: &str, props: &[(&str, &str)]| Agent {
+            manufacturer: "conplement-ag".to_string(),
+            model: model.to_string(),
+            additional_device_properties: AdditionalDeviceProperties(
+                props
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+        };
+        let compatibility = |props: &[(&str, &str)]| {
+            Compatibility(
+                props
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+        let mut manifest: ImportManifest = serde_json::from_str(
+            &fs::read_to_string("testfiles/positive/import-manifest.json").unwrap(),
+        )
+        .unwrap();
+        manifest.compatibility = vec![
+            compatibility(&[
+                ("manufacturer", "conplement-ag"),
+                ("model", "model-a"),
+                ("compatibilityid", "1"),
+            ]),
+            compatibility(&[
+                ("manufacturer", "conplement-ag"),
+                ("model", "model-b"),
+                ("compatibilityid", "2"),
+                ("variant", "x"),
+            ]),
+        ];
+
+        // second agent matches second entry in all properties
+        let du_config = DeviceUpdateConfig {
+            agents: vec![
+                agent("model-c", &[("compatibilityid", "1")]),
+                agent("model-b", &[("compatibilityid", "2"), ("variant", "x")]),
+            ],
+        };
+        assert!(FirmwareUpdate::verify_compatibility(&du_config, &manifest).is_ok());
+
+        // additional property only known by the manifest
+        let du_config = DeviceUpdateConfig {
+            agents: vec![agent("model-b", &[("compatibilityid", "2")])],
+        };
+        assert_eq!(
+            FirmwareUpdate::verify_compatibility(&du_config, &manifest)
+                .unwrap_err()
+                .to_string(),
+            "failed to verify compatibility: agent 0 / compatibility 0: model, compatibilityid; agent 0 / compatibility 1: variant"
+        );
+
+        // additional property only known by the agent
+        let du_config = DeviceUpdateConfig {
+            agents: vec![agent(
+                "model-a",
+                &[("compatibilityid", "1"), ("location", "hall-3")],
+            )],
+        };
+        assert!(FirmwareUpdate::verify_compatibility(&du_config, &manifest).is_ok());
+
+        // empty lists are rejected instead of panicking
+        assert!(
+            FirmwareUpdate::verify_compatibility(&DeviceUpdateConfig { agents: vec![] }, &manifest)
+                .is_err()
+        );
+        manifest.compatibility.clear();
+        assert!(FirmwareUpdate::verify_compatibility(&du_config, &manifest).is_err());
+    }
+
     #[test]
     fn bootargs_no_update_new_args_sets_validate_key() {
         let _lock = BOOTARGS_TEST_LOCK.lock().unwrap();
@@ -739,7 +1736,9 @@ mod tests {
         setup_bootargs_files(&tmp, "console=ttyS0,115200", "loglevel=7");
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").unwrap();
-        FirmwareUpdate::apply_bootargs(false).unwrap();
+        let mut tx = bootloader_env::Transaction::default();
+        FirmwareUpdate::apply_bootargs(false, &mut tx).unwrap();
+        tx.commit().unwrap();
 
         assert_eq!(
             bootloader_env::get(OMNECT_VALIDATE_EXTRA_BOOTARGS).unwrap(),
@@ -760,7 +1759,9 @@ mod tests {
         setup_bootargs_files(&tmp, "   ", ""); // whitespace only → normalizes to empty
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").unwrap();
-        FirmwareUpdate::apply_bootargs(false).unwrap();
+        let mut tx = bootloader_env::Transaction::default();
+        FirmwareUpdate::apply_bootargs(false, &mut tx).unwrap();
+        tx.commit().unwrap();
 
         assert_eq!(
             bootloader_env::get(OMNECT_VALIDATE_EXTRA_BOOTARGS).unwrap(),
@@ -776,7 +1777,9 @@ mod tests {
         setup_bootargs_files(&tmp, "quiet", "systemd.log_level=debug");
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").unwrap();
-        FirmwareUpdate::apply_bootargs(true).unwrap();
+        let mut tx = bootloader_env::Transaction::default();
+        FirmwareUpdate::apply_bootargs(true, &mut tx).unwrap();
+        tx.commit().unwrap();
 
         assert_eq!(
             bootloader_env::get(OMNECT_EXTRA_BOOTARGS).unwrap(),
@@ -792,7 +1795,9 @@ mod tests {
         setup_bootargs_files(&tmp, "", "");
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").unwrap();
-        FirmwareUpdate::apply_bootargs(true).unwrap();
+        let mut tx = bootloader_env::Transaction::default();
+        FirmwareUpdate::apply_bootargs(true, &mut tx).unwrap();
+        tx.commit().unwrap();
 
         assert!(
             bootloader_env::get(OMNECT_EXTRA_BOOTARGS)
@@ -810,7 +1815,9 @@ mod tests {
         setup_bootargs_files(&tmp, "  arg1   arg2\n", "\n  arg3  ");
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").unwrap();
-        FirmwareUpdate::apply_bootargs(true).unwrap();
+        let mut tx = bootloader_env::Transaction::default();
+        FirmwareUpdate::apply_bootargs(true, &mut tx).unwrap();
+        tx.commit().unwrap();
 
         assert_eq!(
             bootloader_env::get(OMNECT_EXTRA_BOOTARGS).unwrap(),
@@ -826,7 +1833,9 @@ mod tests {
         setup_bootargs_files(&tmp, "stable_arg", "");
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "stable_arg").unwrap();
-        FirmwareUpdate::apply_bootargs(false).unwrap();
+        let mut tx = bootloader_env::Transaction::default();
+        FirmwareUpdate::apply_bootargs(false, &mut tx).unwrap();
+        tx.commit().unwrap();
 
         assert_eq!(
             bootloader_env::get(OMNECT_EXTRA_BOOTARGS).unwrap(),
@@ -854,7 +1863,10 @@ mod tests {
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").unwrap();
 
-        assert!(FirmwareUpdate::apply_bootargs(false).is_err());
+        assert!(
+            FirmwareUpdate::apply_bootargs(false, &mut bootloader_env::Transaction::default())
+                .is_err()
+        );
     }
 
     #[test]
@@ -870,7 +1882,9 @@ mod tests {
         crate::common::set_env_var("BOOTARGS_CUSTOM_FILE_PATH", tmp.path().join("no_custom"));
 
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").unwrap();
-        FirmwareUpdate::apply_bootargs(false).unwrap();
+        let mut tx = bootloader_env::Transaction::default();
+        FirmwareUpdate::apply_bootargs(false, &mut tx).unwrap();
+        tx.commit().unwrap();
 
         assert_eq!(
             bootloader_env::get(OMNECT_VALIDATE_EXTRA_BOOTARGS).unwrap(),
@@ -1075,4 +2089,141 @@ mod tests {
             "expected update validation config to be removed"
         );
     }
+
+    // reads `remaining` bytes of `byte` and records the largest read buffer
+    struct ChunkRecorder {
+        remaining: u64,
+        byte: u8,
+        max_read: usize,
+    }
+
+    impl Read for ChunkRecorder {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.max_read = self.max_read.max(buf.len());
+            let n = buf.len().min(self.remaining as usize);
+            buf[..n].fill(self.byte);
+            self.remaining -= n as u64;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn unpack_hashed_reads_chunked() {
+        const SWU_SIZE: u64 = 4 * 1024 * 1024;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let swu_path = tmp_dir.path().join("update.swu");
+
+        let mut entry = ChunkRecorder {
+            remaining: SWU_SIZE,
+            byte: 0xab,
+            max_read: 0,
+        };
+
+        let hash = FirmwareUpdate::unpack_hashed(&mut entry, &swu_path).unwrap();
+
+        assert_eq!(hash, Sha256::digest(vec![0xab; SWU_SIZE as usize]).to_vec());
+        assert_eq!(fs::metadata(&swu_path).unwrap().len(), SWU_SIZE);
+        // the content is never read into memory at once
+        assert!(
+            entry.max_read <= 64 * 1024,
+            "expected chunked reads, but read {} bytes at once",
+            entry.max_read
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn load_large_archive() {
+        const SWU_SIZE: u64 = 32 * 1024 * 1024;
+        const SWU_NAME: &str = "OMNECT-gateway-devel_4.0.24.557123921_raspberrypi4-64.swu";
+
+        let _lock = LOAD_TEST_LOCK.lock().await;
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (mut firmware_update, _, _) = setup_load(&tmp_dir).await;
+
+        // sign with a generated key, since the key of the test archives is not
+        // available
+        let key = PKey::from_ec_key(
+            openssl::ec::EcKey::generate(
+                &openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1)
+                    .unwrap(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let pubkey_path = tmp_dir.path().join("manifest-pub.pem");
+        fs::write(&pubkey_path, key.public_key_to_pem().unwrap()).unwrap();
+        crate::common::set_env_var("MANIFEST_PUBKEY_PATH", &pubkey_path);
+
+        let mut manifest: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string("testfiles/positive/import-manifest.json").unwrap(),
+        )
+        .unwrap();
+        manifest["files"][0]["sizeInBytes"] = json!(SWU_SIZE);
+        manifest["files"][0]["hashes"]["sha256"] =
+            json!(BASE64_STANDARD.encode(Sha256::digest(vec![0xab; SWU_SIZE as usize])));
+        let manifest = serde_json::to_vec_pretty(&manifest).unwrap();
+        let manifest_sha256 = Sha256::digest(&manifest)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer.update(&manifest).unwrap();
+        let signature = signer.sign_to_vec().unwrap();
+
+        // the .swu is streamed into the archive, so it is never held in memory
+        let archive_path = tmp_dir.path().join("update.tar");
+        let mut archive = tar::Builder::new(fs::File::create(&archive_path).unwrap());
+        let mut append = |name: String, size: u64, data: &mut dyn Read| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(size);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, name, data).unwrap();
+        };
+
+        append(
+            SWU_NAME.to_string(),
+            SWU_SIZE,
+            &mut io::repeat(0xab).take(SWU_SIZE),
+        );
+        append(
+            format!("{SWU_NAME}.importManifest.json"),
+            manifest.len() as u64,
+            &mut manifest.as_slice(),
+        );
+        append(
+            format!("{SWU_NAME}.importManifest.json.sha256"),
+            manifest_sha256.len() as u64,
+            &mut manifest_sha256.as_bytes(),
+        );
+        append(
+            format!("{SWU_NAME}.importManifest.json.sig"),
+            signature.len() as u64,
+            &mut signature.as_slice(),
+        );
+        archive.into_inner().unwrap();
+
+        firmware_update
+            .load(
+                &archive_path,
+                VersionOptions::default(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        let swu_path = firmware_update.swu_file_path.clone().unwrap();
+        assert!(path_ends_with(&swu_path, SWU_NAME));
+        assert_eq!(fs::metadata(&swu_path).unwrap().len(), SWU_SIZE);
+        assert_eq!(
+            firmware_update
+                .loaded_manifest
+                .as_ref()
+                .unwrap()
+                .update_id
+                .version,
+            "4.0.24.557123921"
+        );
+    }
 }
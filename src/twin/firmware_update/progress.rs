@@ -0,0 +1,390 @@
+use crate::web_service;
+use anyhow::{Result, ensure};
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::json;
+use std::{env, future::Future, path::Path};
+use tokio::{
+    io::AsyncReadExt,
+    net::UnixStream,
+    sync::mpsc::{self, Sender},
+    time::{Duration, sleep},
+};
+use tokio_util::sync::CancellationToken;
+
+macro_rules! swupdate_progress_socket_path {
+    () => {
+        env::var("SWUPDATE_PROGRESS_SOCKET_PATH").unwrap_or("/tmp/swupdateprog".to_string())
+    };
+}
+
+// size of swupdate's `struct progress_msg` incl. trailing padding
+const PROGRESS_MSG_SIZE: usize = 2416;
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+// swupdate's RECOVERY_STATUS
+const SWUPDATE_STATUS_FAILURE: u32 = 4;
+
+// root and kernelargs are not installed in mock builds
+#[cfg_attr(feature = "mock", allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStep {
+    Root,
+    Bootloader,
+    Kernelargs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressStatus {
+    Running,
+    Succeeded,
+    Failed,
+    // a package doesn't necessarily contain a bootloader
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UpdateProgress {
+    pub step: UpdateStep,
+    pub percent: u8,
+    pub status: ProgressStatus,
+    pub error: Option<String>,
+}
+
+/// The parts of swupdate's `struct progress_msg` we are interested in.
+#[derive(Debug, PartialEq)]
+struct ProgressMsg {
+    status: u32,
+    nsteps: u32,
+    cur_step: u32,
+    cur_percent: u32,
+    info: String,
+}
+
+impl ProgressMsg {
+    fn parse(buf: &[u8]) -> Result<Self> {
+        ensure!(
+            buf.len() == PROGRESS_MSG_SIZE,
+            "unexpected progress message size {}",
+            buf.len()
+        );
+
+        let u32_at = |offset: usize| {
+            u32::from_ne_bytes(buf[offset..offset + 4].try_into().expect("4 bytes"))
+        };
+        let infolen = (u32_at(360) as usize).min(2048);
+
+        Ok(ProgressMsg {
+            status: u32_at(4),
+            nsteps: u32_at(24),
+            cur_step: u32_at(28),
+            cur_percent: u32_at(32),
+            info: String::from_utf8_lossy(&buf[364..364 + infolen])
+                .trim_end_matches('\0')
+                .to_string(),
+        })
+    }
+
+    /// Percentage over all steps of one swupdate run.
+    fn percent(&self) -> u8 {
+        if self.nsteps == 0 {
+            return 0;
+        }
+
+        let done = self.cur_step.saturating_sub(1).min(self.nsteps) * 100;
+        ((done + self.cur_percent.min(100)) / self.nsteps).min(100) as u8
+    }
+}
+
+struct ProgressReporter {
+    tx_reported_properties: Option<Sender<serde_json::Value>>,
+    progress: UpdateProgress,
+    last_reported: Option<UpdateProgress>,
+}
+
+impl ProgressReporter {
+    async fn update(&mut self, msg: ProgressMsg) {
+        self.progress.percent = self.progress.percent.max(msg.percent());
+
+        if msg.status == SWUPDATE_STATUS_FAILURE && !msg.info.is_empty() {
+            self.progress.error = Some(msg.info);
+        }
+
+        self.report().await
+    }
+
+    async fn finish(&mut self, result: &Result<()>) {
+        match result {
+            Ok(()) => {
+                self.progress.percent = 100;
+                self.progress.status = ProgressStatus::Succeeded;
+                self.progress.error = None;
+            }
+            Err(_) if self.progress.step == UpdateStep::Bootloader => {
+                self.progress.status = ProgressStatus::Skipped;
+                self.progress.error = None;
+            }
+            Err(e) => {
+                self.progress.status = ProgressStatus::Failed;
+                self.progress.error = Some(format!("{e:#}"));
+            }
+        }
+
+        self.report().await
+    }
+
+    // local consumers get every change, the twin only status changes and
+    // every 10 percent
+    async fn report(&mut self) {
+        if self.last_reported.as_ref() == Some(&self.progress) {
+            return;
+        }
+
+        web_service::publish(
+            web_service::PublishChannel::FirmwareUpdateProgressV1,
+            json!(self.progress),
+        )
+        .await;
+
+        let report_twin = self.last_reported.as_ref().is_none_or(|last| {
+            last.step != self.progress.step
+                || last.status != self.progress.status
+                || last.error != self.progress.error
+                || last.percent / 10 != self.progress.percent / 10
+        });
+
+        self.last_reported = Some(self.progress.clone());
+
+        if !report_twin {
+            return;
+        }
+
+        let Some(tx) = &self.tx_reported_properties else {
+            debug!("report: skip since tx_reported_properties is None");
+            return;
+        };
+
+        if let Err(e) = tx
+            .send(json!({
+                "firmware_update": {
+                    "progress": self.progress
+                }
+            }))
+            .await
+        {
+            warn!("report: failed to send progress: {e:#}");
+        }
+    }
+}
+
+/// Runs `swupdate` and reports the progress it announces on its progress
+/// socket until it finished.
+pub async fn observe<F>(
+    step: UpdateStep,
+    tx_reported_properties: Option<Sender<serde_json::Value>>,
+    swupdate: F,
+) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    observe_socket(
+        swupdate_progress_socket_path!(),
+        step,
+        tx_reported_properties,
+        swupdate,
+    )
+    .await
+}
+
+async fn observe_socket<F>(
+    socket_path: String,
+    step: UpdateStep,
+    tx_reported_properties: Option<Sender<serde_json::Value>>,
+    swupdate: F,
+) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let mut reporter = ProgressReporter {
+        tx_reported_properties,
+        progress: UpdateProgress {
+            step,
+            percent: 0,
+            status: ProgressStatus::Running,
+            error: None,
+        },
+        last_reported: None,
+    };
+    let (tx_msg, mut rx_msg) = mpsc::channel(16);
+    let stop = CancellationToken::new();
+    let reader = tokio::spawn(read_progress(socket_path, tx_msg, stop.clone()));
+
+    reporter.report().await;
+
+    tokio::pin!(swupdate);
+
+    let result = loop {
+        tokio::select! {
+            result = &mut swupdate => break result,
+            Some(msg) = rx_msg.recv() => reporter.update(msg).await,
+        }
+    };
+
+    // the reader must not block on a full channel while we wait for it
+    drop(rx_msg);
+    stop.cancel();
+    let _ = reader.await;
+
+    reporter.finish(&result).await;
+
+    result
+}
+
+// swupdate creates its progress socket only after it started, thus we
+// (re)connect until we get stopped
+async fn read_progress(socket_path: String, tx_msg: Sender<ProgressMsg>, stop: CancellationToken) {
+    let mut buf = vec![0u8; PROGRESS_MSG_SIZE];
+
+    while !stop.is_cancelled() {
+        let mut stream = match UnixStream::connect(Path::new(&socket_path)).await {
+            Ok(stream) => stream,
+            Err(_) => {
+                tokio::select! {
+                    _ = stop.cancelled() => return,
+                    _ = sleep(CONNECT_RETRY_INTERVAL) => continue,
+                }
+            }
+        };
+
+        debug!("connected to swupdate progress socket {socket_path}");
+
+        loop {
+            let read = tokio::select! {
+                _ = stop.cancelled() => return,
+                read = stream.read_exact(&mut buf) => read,
+            };
+
+            if let Err(e) = read {
+                debug!("swupdate progress socket closed: {e}");
+                break;
+            }
+
+            match ProgressMsg::parse(&buf) {
+                Ok(msg) => {
+                    if tx_msg.send(msg).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warn!("ignore swupdate progress message: {e:#}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncWriteExt, net::UnixListener};
+
+    fn progress_msg(
+        status: u32,
+        nsteps: u32,
+        cur_step: u32,
+        cur_percent: u32,
+        info: &str,
+    ) -> Vec<u8> {
+        let mut buf = vec![0u8; PROGRESS_MSG_SIZE];
+        buf[4..8].copy_from_slice(&status.to_ne_bytes());
+        buf[24..28].copy_from_slice(&nsteps.to_ne_bytes());
+        buf[28..32].copy_from_slice(&cur_step.to_ne_bytes());
+        buf[32..36].copy_from_slice(&cur_percent.to_ne_bytes());
+        buf[360..364].copy_from_slice(&(info.len() as u32).to_ne_bytes());
+        buf[364..364 + info.len()].copy_from_slice(info.as_bytes());
+        buf
+    }
+
+    #[test]
+    fn parse_progress_msg_ok() {
+        let msg = ProgressMsg::parse(&progress_msg(2, 2, 2, 50, "")).unwrap();
+        assert_eq!(msg.percent(), 75);
+
+        let msg = ProgressMsg::parse(&progress_msg(4, 1, 1, 10, "image broken")).unwrap();
+        assert_eq!(msg.status, SWUPDATE_STATUS_FAILURE);
+        assert_eq!(msg.info, "image broken");
+
+        assert!(ProgressMsg::parse(&[0u8; 16]).is_err());
+    }
+
+    #[tokio::test]
+    async fn observe_progress_socket_ok() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let socket_path = tmp_dir.path().join("swupdateprog");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let (tx, mut rx) = mpsc::channel(100);
+
+        let swupdate = async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for percent in [5, 15, 16, 100] {
+                stream
+                    .write_all(&progress_msg(2, 1, 1, percent, ""))
+                    .await
+                    .unwrap();
+            }
+            // give the reader the chance to consume all messages
+            sleep(Duration::from_millis(200)).await;
+            Ok(())
+        };
+
+        observe_socket(
+            socket_path.to_str().unwrap().to_string(),
+            UpdateStep::Root,
+            Some(tx),
+            swupdate,
+        )
+        .await
+        .unwrap();
+
+        let mut reported = vec![];
+        while let Ok(value) = rx.try_recv() {
+            reported.push(
+                value["firmware_update"]["progress"]["percent"]
+                    .as_u64()
+                    .unwrap(),
+            );
+        }
+
+        // 5% and 16% are in the same 10 percent range as the previous report,
+        // the last report is the final status
+        assert_eq!(reported, vec![0, 15, 100, 100]);
+    }
+
+    #[tokio::test]
+    async fn observe_bootloader_failure_is_skipped() {
+        let (tx, mut rx) = mpsc::channel(100);
+
+        let result = observe_socket(
+            "/nonexistent/swupdateprog".to_string(),
+            UpdateStep::Bootloader,
+            Some(tx),
+            async { anyhow::bail!("no bootloader") },
+        )
+        .await;
+
+        assert!(result.is_err());
+
+        let mut last = None;
+        while let Ok(value) = rx.try_recv() {
+            last = Some(value);
+        }
+        assert_eq!(
+            last.unwrap(),
+            json!({"firmware_update": {"progress": {
+                "step": "bootloader",
+                "percent": 0,
+                "status": "skipped",
+                "error": null
+            }}})
+        );
+    }
+}
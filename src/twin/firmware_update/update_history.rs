@@ -0,0 +1,268 @@
+use super::{update_hooks::HookResult, update_validation::UpdateValidationStatus};
+use crate::{
+    common::{from_json_file, to_json_file},
+    reboot_reason, web_service,
+};
+use anyhow::{Context, Result};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::VecDeque, env, fs, path::Path, sync::Mutex};
+use time::format_description::well_known::Rfc3339;
+use tokio::sync::mpsc::Sender;
+
+macro_rules! update_history_file_path {
+    () => {
+        env::var("UPDATE_HISTORY_PATH")
+            .unwrap_or("/var/lib/omnect-device-service/update_history.json".to_string())
+    };
+}
+
+const UPDATE_HISTORY_MAX_ENTRIES_DEFAULT: usize = 20;
+
+// serializes read-modify-write cycles of the history file, which happen from
+// the feature as well as from the update validation task
+static UPDATE_HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateSource {
+    Local,
+    Cloud,
+}
+
+/// One firmware update. The entry is pending until the update was validated,
+/// recovered or failed before the reboot.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct UpdateHistoryEntry {
+    pub source: UpdateSource,
+    pub from_version: Option<String>,
+    pub to_version: Option<String>,
+    pub started: Option<String>,
+    pub finished: Option<String>,
+    pub bootloader_updated: Option<bool>,
+    pub validation: Option<UpdateValidationStatus>,
+    pub error: Option<String>,
+    pub reboot_reason: Option<serde_json::Value>,
+    #[serde(default)]
+    pub hooks: Vec<HookResult>,
+}
+
+impl UpdateHistoryEntry {
+    pub fn new(
+        source: UpdateSource,
+        from_version: Option<String>,
+        to_version: Option<String>,
+    ) -> Self {
+        UpdateHistoryEntry {
+            source,
+            from_version,
+            to_version,
+            started: now(),
+            finished: None,
+            bootloader_updated: None,
+            validation: None,
+            error: None,
+            reboot_reason: None,
+            hooks: vec![],
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.finished.is_none()
+    }
+}
+
+/// Rotating on-disk history of firmware updates, oldest entry first.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct UpdateHistory(VecDeque<UpdateHistoryEntry>);
+
+impl UpdateHistory {
+    /// Returns an empty history if there is none or it cannot be read.
+    pub fn load() -> Self {
+        Self::load_from(update_history_file_path!())
+    }
+
+    /// Loads, modifies and saves the history and returns the modified one.
+    pub fn update<F>(f: F) -> Result<Self>
+    where
+        F: FnOnce(&mut Self),
+    {
+        let _lock = UPDATE_HISTORY_LOCK.lock();
+        let path = update_history_file_path!();
+        let mut history = Self::load_from(&path);
+
+        f(&mut history);
+        history.rotate(max_entries());
+        history.save_to(&path)?;
+
+        Ok(history)
+    }
+
+    /// Appends `entry`. A previous entry that is still pending will never be
+    /// finished and thus is marked as interrupted.
+    pub fn start(&mut self, entry: UpdateHistoryEntry) {
+        if let Some(last) = self.0.back_mut().filter(|e| e.is_pending()) {
+            warn!("mark pending update history entry as interrupted");
+            last.finished = now();
+            last.error = Some("interrupted".to_string());
+        }
+
+        self.0.push_back(entry);
+    }
+
+    pub fn last(&self) -> Option<&UpdateHistoryEntry> {
+        self.0.back()
+    }
+
+    pub fn pending_mut(&mut self) -> Option<&mut UpdateHistoryEntry> {
+        self.0.back_mut().filter(|e| e.is_pending())
+    }
+
+    /// Finishes the pending entry, if any.
+    pub fn finish(&mut self, validation: Option<UpdateValidationStatus>, error: Option<String>) {
+        let Some(entry) = self.pending_mut() else {
+            debug!("no pending update history entry");
+            return;
+        };
+
+        entry.finished = now();
+        entry.validation = validation;
+        entry.error = error;
+        entry.reboot_reason = reboot_reason::current_reboot_reason();
+    }
+
+    pub async fn report(
+        &self,
+        tx_reported_properties: Option<&Sender<serde_json::Value>>,
+    ) -> Result<()> {
+        web_service::publish(
+            web_service::PublishChannel::FirmwareUpdateHistoryV1,
+            json!({"history": self}),
+        )
+        .await;
+
+        let Some(tx) = tx_reported_properties else {
+            debug!("report: skip since tx_reported_properties is None");
+            return Ok(());
+        };
+
+        tx.send(json!({
+            "firmware_update": {
+                "history": self
+            }
+        }))
+        .await
+        .context("report: send")
+    }
+
+    fn rotate(&mut self, max_entries: usize) {
+        while self.0.len() > max_entries {
+            self.0.pop_front();
+        }
+    }
+
+    fn load_from<P>(path: P) -> Self
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        if !path.as_ref().exists() {
+            return Self::default();
+        }
+
+        from_json_file(&path).unwrap_or_else(|e| {
+            error!("ignore invalid update history: {e:#}");
+            Self::default()
+        })
+    }
+
+    // write to a temporary file first, so that readers never see a partial
+    // history
+    fn save_to(&self, path: &str) -> Result<()> {
+        let tmp_path = format!("{path}.tmp");
+
+        to_json_file(self, &tmp_path, true)?;
+        fs::rename(&tmp_path, path).context(format!("failed to rename {tmp_path}"))
+    }
+}
+
+fn now() -> Option<String> {
+    time::OffsetDateTime::now_utc().format(&Rfc3339).ok()
+}
+
+fn max_entries() -> usize {
+    match env::var("UPDATE_HISTORY_MAX_ENTRIES") {
+        Ok(max) => max.parse::<usize>().unwrap_or_else(|_| {
+            error!(
+                "ignore invalid UPDATE_HISTORY_MAX_ENTRIES {max} and use default {UPDATE_HISTORY_MAX_ENTRIES_DEFAULT}"
+            );
+            UPDATE_HISTORY_MAX_ENTRIES_DEFAULT
+        }),
+        Err(_) => UPDATE_HISTORY_MAX_ENTRIES_DEFAULT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(to_version: &str) -> UpdateHistoryEntry {
+        UpdateHistoryEntry::new(
+            UpdateSource::Local,
+            Some("1.0.0.0".to_string()),
+            Some(to_version.to_string()),
+        )
+    }
+
+    #[test]
+    fn update_history_lifecycle() {
+        let mut history = UpdateHistory::default();
+
+        history.start(entry("2.0.0.0"));
+        history.pending_mut().unwrap().bootloader_updated = Some(false);
+
+        // a new update interrupts the pending one
+        history.start(entry("3.0.0.0"));
+        assert_eq!(history.0[0].error, Some("interrupted".to_string()));
+        assert!(!history.0[0].is_pending());
+
+        history.finish(Some(UpdateValidationStatus::Succeeded), None);
+        assert!(history.pending_mut().is_none());
+        assert_eq!(
+            history.0[1].validation,
+            Some(UpdateValidationStatus::Succeeded)
+        );
+
+        // finishing twice doesn't touch finished entries
+        history.finish(Some(UpdateValidationStatus::Recovered), None);
+        assert_eq!(
+            history.0[1].validation,
+            Some(UpdateValidationStatus::Succeeded)
+        );
+
+        history.start(entry("4.0.0.0"));
+        history.rotate(2);
+        assert_eq!(history.0.len(), 2);
+        assert_eq!(history.0[0].to_version, Some("3.0.0.0".to_string()));
+    }
+
+    #[test]
+    fn update_history_load_and_save() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("update_history.json");
+        let path = path.to_str().unwrap();
+
+        assert_eq!(UpdateHistory::load_from(path), UpdateHistory::default());
+
+        let mut history = UpdateHistory::default();
+        history.start(entry("2.0.0.0"));
+        history.save_to(path).unwrap();
+
+        assert_eq!(UpdateHistory::load_from(path), history);
+        assert!(!Path::new(&format!("{path}.tmp")).exists());
+
+        fs::write(path, "{invalid}").unwrap();
+        assert_eq!(UpdateHistory::load_from(path), UpdateHistory::default());
+    }
+}
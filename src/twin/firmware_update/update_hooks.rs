@@ -0,0 +1,326 @@
+use anyhow::{Result, bail};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fmt, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Instant,
+};
+use tokio::time::{Duration, timeout};
+
+macro_rules! update_hooks_dir_path {
+    () => {
+        env::var("UPDATE_HOOKS_DIR_PATH").unwrap_or("/etc/omnect/update-hooks.d".to_string())
+    };
+}
+
+const UPDATE_HOOK_TIMEOUT_SECS_DEFAULT: u64 = 60;
+// stderr of a failed hook is added to its result, but must not bloat the report
+const HOOK_ERROR_MAX_LEN: usize = 512;
+
+/// Phases of a firmware update hooks are invoked at. The hooks of a phase are
+/// the executables in the phase's subdirectory of the hooks directory, e.g.
+/// `/etc/omnect/update-hooks.d/pre-install/`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookPhase {
+    PreLoad,
+    PreInstall,
+    PreReboot,
+    Validate,
+}
+
+impl fmt::Display for HookPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self {
+            HookPhase::PreLoad => "pre-load",
+            HookPhase::PreInstall => "pre-install",
+            HookPhase::PreReboot => "pre-reboot",
+            HookPhase::Validate => "validate",
+        };
+        write!(f, "{phase}")
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HookResult {
+    pub phase: HookPhase,
+    pub hook: String,
+    pub success: bool,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Runs the hooks of `phase` in lexical order. The first failing hook aborts
+/// the phase, thus the last result is the only one that might have failed.
+pub async fn run_hooks(phase: HookPhase, version: Option<&str>) -> Vec<HookResult> {
+    run_hooks_in(
+        Path::new(&update_hooks_dir_path!()),
+        phase,
+        version,
+        hook_timeout(),
+    )
+    .await
+}
+
+/// Fails if one of the hooks failed.
+pub fn ensure_hooks_succeeded(results: &[HookResult]) -> Result<()> {
+    if let Some(failed) = results.iter().find(|r| !r.success) {
+        bail!(
+            "{} hook {} failed: {}",
+            failed.phase,
+            failed.hook,
+            failed.error.as_deref().unwrap_or("unknown error")
+        )
+    }
+    Ok(())
+}
+
+async fn run_hooks_in(
+    dir: &Path,
+    phase: HookPhase,
+    version: Option<&str>,
+    hook_timeout: Duration,
+) -> Vec<HookResult> {
+    let mut results = vec![];
+
+    for hook in hooks(&dir.join(phase.to_string())) {
+        let result = run_hook(&hook, phase, version, hook_timeout).await;
+        let success = result.success;
+
+        results.push(result);
+
+        if !success {
+            break;
+        }
+    }
+
+    results
+}
+
+fn hooks(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        debug!("no update hooks in {dir:?}");
+        return vec![];
+    };
+
+    let mut hooks: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            let executable =
+                fs::metadata(p).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+            if !executable {
+                warn!("ignore update hook {p:?} since it is not an executable file");
+            }
+            executable
+        })
+        .collect();
+
+    hooks.sort();
+    hooks
+}
+
+async fn run_hook(
+    hook: &Path,
+    phase: HookPhase,
+    version: Option<&str>,
+    hook_timeout: Duration,
+) -> HookResult {
+    let name = hook
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    info!("run {phase} update hook {name}");
+
+    let mut cmd = tokio::process::Command::new(hook);
+    cmd.arg(phase.to_string())
+        .env("OMNECT_UPDATE_PHASE", phase.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // a hook that timed out must not outlive us
+        .kill_on_drop(true);
+
+    if let Some(version) = version {
+        cmd.env("OMNECT_UPDATE_VERSION", version);
+    }
+
+    let start = Instant::now();
+
+    let error = match timeout(hook_timeout, cmd.output()).await {
+        Ok(Ok(output)) => {
+            debug!(
+                "{name} stdout: {}",
+                String::from_utf8_lossy(&output.stdout).trim_end()
+            );
+
+            if output.status.success() {
+                None
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stderr = tail(stderr.trim_end(), HOOK_ERROR_MAX_LEN);
+
+                if stderr.is_empty() {
+                    Some(output.status.to_string())
+                } else {
+                    Some(format!("{}: {stderr}", output.status))
+                }
+            }
+        }
+        Ok(Err(e)) => Some(format!("failed to execute: {e}")),
+        Err(_) => Some(format!("timed out after {hook_timeout:?}")),
+    };
+
+    if let Some(e) = &error {
+        error!("{phase} update hook {name} failed: {e}");
+    }
+
+    HookResult {
+        phase,
+        hook: name,
+        success: error.is_none(),
+        duration_ms: start.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+fn tail(s: &str, max_len: usize) -> &str {
+    let start = (s.len().saturating_sub(max_len)..=s.len())
+        .find(|i| s.is_char_boundary(*i))
+        .unwrap_or(s.len());
+    &s[start..]
+}
+
+fn hook_timeout() -> Duration {
+    let mut timeout = Duration::from_secs(UPDATE_HOOK_TIMEOUT_SECS_DEFAULT);
+    if let Ok(secs) = env::var("UPDATE_HOOK_TIMEOUT_SECS") {
+        match secs.parse::<u64>() {
+            Ok(secs) => {
+                timeout = Duration::from_secs(secs);
+            }
+            _ => error!(
+                "ignore invalid update hook timeout {secs}s and use default {}s",
+                timeout.as_secs()
+            ),
+        };
+    }
+    timeout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(dir: &Path, phase: HookPhase, name: &str, script: &str) {
+        let phase_dir = dir.join(phase.to_string());
+        fs::create_dir_all(&phase_dir).unwrap();
+
+        let path = phase_dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_hooks_in_order() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let out = tmp_dir.path().join("out");
+
+        hook(
+            tmp_dir.path(),
+            HookPhase::PreInstall,
+            "20-second",
+            &format!("echo \"$1 $OMNECT_UPDATE_VERSION\" >> {}", out.display()),
+        );
+        hook(
+            tmp_dir.path(),
+            HookPhase::PreInstall,
+            "10-first",
+            &format!("echo first >> {}", out.display()),
+        );
+        // not executable
+        fs::write(tmp_dir.path().join("pre-install/30-ignored"), "").unwrap();
+
+        let results = run_hooks_in(
+            tmp_dir.path(),
+            HookPhase::PreInstall,
+            Some("1.2.3.4"),
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(ensure_hooks_succeeded(&results).is_ok());
+        assert_eq!(
+            fs::read_to_string(out).unwrap(),
+            "first\npre-install 1.2.3.4\n"
+        );
+
+        // other phases have no hooks
+        assert!(
+            run_hooks_in(
+                tmp_dir.path(),
+                HookPhase::PreReboot,
+                None,
+                Duration::from_secs(5)
+            )
+            .await
+            .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_hook_aborts_phase() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        hook(
+            tmp_dir.path(),
+            HookPhase::Validate,
+            "10-fail",
+            "echo \"smoke test failed\" >&2; exit 3",
+        );
+        hook(tmp_dir.path(), HookPhase::Validate, "20-never", "exit 0");
+
+        let results = run_hooks_in(
+            tmp_dir.path(),
+            HookPhase::Validate,
+            None,
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].success);
+        assert!(
+            results[0]
+                .error
+                .as_ref()
+                .unwrap()
+                .ends_with("smoke test failed")
+        );
+
+        let e = ensure_hooks_succeeded(&results).unwrap_err().to_string();
+        assert!(e.starts_with("validate hook 10-fail failed: exit status: 3"));
+    }
+
+    #[tokio::test]
+    async fn hook_times_out() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        hook(tmp_dir.path(), HookPhase::PreReboot, "10-hang", "sleep 10");
+
+        let results = run_hooks_in(
+            tmp_dir.path(),
+            HookPhase::PreReboot,
+            None,
+            Duration::from_millis(200),
+        )
+        .await;
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].error, Some("timed out after 200ms".to_string()));
+    }
+}
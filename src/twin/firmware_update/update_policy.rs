@@ -0,0 +1,62 @@
+use crate::common::from_json_file;
+use anyhow::Result;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{env, path::Path};
+
+macro_rules! update_policy_file_path {
+    () => {
+        env::var("UPDATE_POLICY_PATH").unwrap_or("/etc/omnect/update_policy.json".to_string())
+    };
+}
+
+/// Device-side policy that gates overrides requested by the caller of a local
+/// update. Without policy file all overrides are denied.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct UpdatePolicy {
+    #[serde(default)]
+    pub allow_downgrade: bool,
+    #[serde(default)]
+    pub allow_reinstall: bool,
+}
+
+impl UpdatePolicy {
+    pub fn load() -> Result<Self> {
+        let path = update_policy_file_path!();
+
+        if !Path::new(&path).exists() {
+            debug!("no update policy at {path}: deny overrides");
+            return Ok(Self::default());
+        }
+
+        from_json_file(path)
+    }
+}
+
+/// Options of a load request to install a version that is not newer than the
+/// current one. Only effective if permitted by `UpdatePolicy`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct VersionOptions {
+    #[serde(default)]
+    pub allow_downgrade: bool,
+    #[serde(default)]
+    pub allow_reinstall: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, strum_macros::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum VersionOverrideKind {
+    Downgrade,
+    Reinstall,
+}
+
+/// Decision about a requested downgrade or reinstall as it is reported to the
+/// twin.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VersionOverride {
+    pub kind: VersionOverrideKind,
+    pub current_version: String,
+    pub new_version: String,
+    pub permitted: bool,
+}
@@ -0,0 +1,305 @@
+use crate::common::{from_json_file, merge_patch, to_json_file};
+use anyhow::{Context, Result, bail, ensure};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
+use time::{OffsetDateTime, Time, Weekday, format_description::well_known::Rfc3339};
+
+macro_rules! update_schedule_file_path {
+    () => {
+        env::var("UPDATE_SCHEDULE_PATH")
+            .unwrap_or("/var/lib/omnect-device-service/update_schedule.json".to_string())
+    };
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl From<Weekday> for Day {
+    fn from(weekday: Weekday) -> Self {
+        match weekday {
+            Weekday::Monday => Day::Mon,
+            Weekday::Tuesday => Day::Tue,
+            Weekday::Wednesday => Day::Wed,
+            Weekday::Thursday => Day::Thu,
+            Weekday::Friday => Day::Fri,
+            Weekday::Saturday => Day::Sat,
+            Weekday::Sunday => Day::Sun,
+        }
+    }
+}
+
+/// Recurring window in UTC, e.g. every night from 01:00 for 3 hours. A window
+/// without days recurs every day.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MaintenanceWindow {
+    pub start: String,
+    pub duration_mins: u32,
+    #[serde(default)]
+    pub days: Vec<Day>,
+}
+
+impl MaintenanceWindow {
+    fn start_time(&self) -> Result<Time> {
+        let (hour, minute) = self
+            .start
+            .split_once(':')
+            .context("start must be formatted as HH:MM")?;
+        let hour = hour.parse::<u8>().context("invalid start hour")?;
+        let minute = minute.parse::<u8>().context("invalid start minute")?;
+
+        Time::from_hms(hour, minute, 0).context("invalid start time")
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.start_time()?;
+        ensure!(self.duration_mins > 0, "duration_mins must be positive");
+        Ok(())
+    }
+
+    pub fn is_open(&self, now: OffsetDateTime) -> bool {
+        let Ok(start_time) = self.start_time() else {
+            return false;
+        };
+        let duration = time::Duration::minutes(self.duration_mins.into());
+
+        // a window might have been opened on one of the previous days
+        (0..=i64::from(self.duration_mins / (24 * 60)) + 1).any(|days_ago| {
+            let start = now.replace_time(start_time) - time::Duration::days(days_ago);
+
+            (self.days.is_empty() || self.days.contains(&start.weekday().into()))
+                && start <= now
+                && now < start + duration
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunSchedule {
+    /// RFC 3339 timestamp
+    At(String),
+    MaintenanceWindow(String),
+}
+
+/// Installation of a loaded update that is deferred until its schedule is due.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ScheduledRun {
+    pub schedule: RunSchedule,
+    pub validate_iothub_connection: bool,
+    #[serde(default)]
+    pub force: bool,
+    pub swu_file_path: PathBuf,
+    pub version: Option<String>,
+}
+
+/// Maintenance windows as configured by desired properties and the pending
+/// scheduled run. Both are persisted to survive service restarts.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct UpdateSchedule {
+    #[serde(default)]
+    pub maintenance_windows: BTreeMap<String, MaintenanceWindow>,
+    #[serde(default)]
+    pub scheduled_run: Option<ScheduledRun>,
+}
+
+impl UpdateSchedule {
+    pub fn load() -> Self {
+        let path = update_schedule_file_path!();
+
+        if !Path::new(&path).exists() {
+            return Self::default();
+        }
+
+        from_json_file(&path).unwrap_or_else(|e| {
+            error!("ignore invalid update schedule: {e:#}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        to_json_file(self, update_schedule_file_path!(), true)
+    }
+
+    /// Applies a desired `maintenance_windows` property. Partial twin updates
+    /// are json merge patches, thus `null` removes a window.
+    pub fn apply_maintenance_windows(
+        &mut self,
+        patch: &serde_json::Value,
+        complete: bool,
+    ) -> Result<()> {
+        let mut windows = if complete {
+            serde_json::Value::Object(Default::default())
+        } else {
+            serde_json::to_value(&self.maintenance_windows)
+                .context("failed to serialize maintenance windows")?
+        };
+
+        merge_patch(&mut windows, patch, false);
+
+        let windows: BTreeMap<String, MaintenanceWindow> =
+            serde_json::from_value(windows).context("failed to parse maintenance windows")?;
+
+        for (name, window) in &windows {
+            window
+                .validate()
+                .context(format!("invalid maintenance window {name}"))?;
+        }
+
+        if let Some(RunSchedule::MaintenanceWindow(name)) =
+            self.scheduled_run.as_ref().map(|r| &r.schedule)
+            && !windows.contains_key(name)
+        {
+            warn!("scheduled run refers to removed maintenance window {name}");
+        }
+
+        self.maintenance_windows = windows;
+
+        Ok(())
+    }
+
+    pub fn validate(&self, schedule: &RunSchedule) -> Result<()> {
+        match schedule {
+            RunSchedule::At(at) => {
+                OffsetDateTime::parse(at, &Rfc3339)
+                    .context(format!("invalid schedule timestamp {at}"))?;
+            }
+            RunSchedule::MaintenanceWindow(name) => {
+                if !self.maintenance_windows.contains_key(name) {
+                    bail!("unknown maintenance window {name}")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_due(&self, now: OffsetDateTime) -> bool {
+        match self.scheduled_run.as_ref().map(|r| &r.schedule) {
+            Some(RunSchedule::At(at)) => {
+                OffsetDateTime::parse(at, &Rfc3339).is_ok_and(|at| at <= now)
+            }
+            Some(RunSchedule::MaintenanceWindow(name)) => self
+                .maintenance_windows
+                .get(name)
+                .is_some_and(|w| w.is_open(now)),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn utc(s: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(s, &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn maintenance_window_is_open() {
+        // 2026-10-16 is a friday
+        let window = MaintenanceWindow {
+            start: "23:00".to_string(),
+            duration_mins: 180,
+            days: vec![Day::Fri],
+        };
+
+        assert!(!window.is_open(utc("2026-10-16T22:59:59Z")));
+        assert!(window.is_open(utc("2026-10-16T23:00:00Z")));
+        assert!(window.is_open(utc("2026-10-17T01:59:59Z")));
+        assert!(!window.is_open(utc("2026-10-17T02:00:00Z")));
+        assert!(!window.is_open(utc("2026-10-17T23:30:00Z")));
+
+        let window = MaintenanceWindow {
+            days: vec![],
+            ..window
+        };
+        assert!(window.is_open(utc("2026-10-17T23:30:00Z")));
+    }
+
+    #[test]
+    fn apply_maintenance_windows_merges_patches() {
+        let mut schedule = UpdateSchedule::default();
+
+        schedule
+            .apply_maintenance_windows(
+                &json!({
+                    "nightly": {"start": "01:00", "duration_mins": 120},
+                    "weekend": {"start": "10:00", "duration_mins": 60, "days": ["sat", "sun"]}
+                }),
+                true,
+            )
+            .unwrap();
+        assert_eq!(schedule.maintenance_windows.len(), 2);
+
+        schedule
+            .apply_maintenance_windows(
+                &json!({"nightly": {"start": "02:00"}, "weekend": null}),
+                false,
+            )
+            .unwrap();
+        assert_eq!(schedule.maintenance_windows.len(), 1);
+        assert_eq!(schedule.maintenance_windows["nightly"].start, "02:00");
+        assert_eq!(schedule.maintenance_windows["nightly"].duration_mins, 120);
+
+        assert!(
+            schedule
+                .apply_maintenance_windows(&json!({"nightly": {"start": "25:00"}}), false)
+                .is_err()
+        );
+        assert_eq!(schedule.maintenance_windows["nightly"].start, "02:00");
+    }
+
+    #[test]
+    fn update_schedule_is_due() {
+        let mut schedule = UpdateSchedule::default();
+        schedule
+            .apply_maintenance_windows(
+                &json!({"nightly": {"start": "01:00", "duration_mins": 120}}),
+                true,
+            )
+            .unwrap();
+
+        assert!(!schedule.is_due(utc("2026-10-16T01:30:00Z")));
+        assert!(
+            schedule
+                .validate(&RunSchedule::MaintenanceWindow("weekend".to_string()))
+                .is_err()
+        );
+        assert!(
+            schedule
+                .validate(&RunSchedule::At("tomorrow".to_string()))
+                .is_err()
+        );
+
+        let mut run = ScheduledRun {
+            schedule: RunSchedule::MaintenanceWindow("nightly".to_string()),
+            validate_iothub_connection: false,
+            force: false,
+            swu_file_path: PathBuf::from("/update.swu"),
+            version: None,
+        };
+        schedule.scheduled_run = Some(run.clone());
+        assert!(!schedule.is_due(utc("2026-10-16T00:59:00Z")));
+        assert!(schedule.is_due(utc("2026-10-16T01:30:00Z")));
+
+        run.schedule = RunSchedule::At("2026-10-16T12:00:00Z".to_string());
+        schedule.scheduled_run = Some(run);
+        assert!(!schedule.is_due(utc("2026-10-16T11:59:59Z")));
+        assert!(schedule.is_due(utc("2026-10-16T12:00:00Z")));
+    }
+}
@@ -2,16 +2,39 @@ use crate::{
     bootloader_env,
     common::{RootPartition, from_json_file, to_json_file},
     systemd::{self, unit::UnitAction},
-    twin::{firmware_update::common::*, web_service},
+    twin::{
+        firmware_update::{
+            common::*,
+            os_version::OmnectOsVersion,
+            update_history::{UpdateHistory, UpdateHistoryEntry, UpdateSource},
+            update_hooks::{HookPhase, ensure_hooks_succeeded, run_hooks},
+            validation_policy::{CriterionResult, ValidationPolicy},
+        },
+        web_service,
+    },
 };
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, ensure};
+use azure_iot_sdk::client::IotMessage;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{env, fs, path::Path, sync::Arc, time::SystemTime};
+use std::{
+    env, fs,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::SystemTime,
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 use tokio::{
-    sync::{RwLock, oneshot},
-    time::{Duration, timeout},
+    sync::{
+        RwLock,
+        mpsc::{self, Sender},
+        oneshot, watch,
+    },
+    time::{Duration, interval, sleep},
 };
 
 // this file is used to detect if we have to validate an update
@@ -22,10 +45,14 @@ static UPDATE_VALIDATION_COMPLETE_BARRIER_FILE: &str =
 // this file is used to determine a recovery after a failed update validation
 static UPDATE_VALIDATION_FAILED_FILE: &str =
     "/run/omnect-device-service/omnect_validate_update_failed";
+// this file is used to defer the reboot of update-validation-observer to an extended deadline
+static UPDATE_VALIDATION_DEADLINE_FILE: &str =
+    "/run/omnect-device-service/omnect_validate_update_deadline";
 static UPDATE_VALIDATION_TIMEOUT_IN_SECS_DEFAULT: u64 = 300;
+static VALIDATION_CRITERIA_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
-#[derive(Clone, Debug, Default, Serialize)]
-enum UpdateValidationStatus {
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum UpdateValidationStatus {
     #[default]
     NoUpdate,
     ValidatingTrial(u8),
@@ -51,20 +78,61 @@ impl UpdateValidationStatus {
 pub struct UpdateValidationParams {
     deadline_timestamp: SystemTime,
     restart_count: u8,
+    // the update is only finalized after it was confirmed manually
+    #[serde(default)]
+    hold: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ExtendUpdateValidationCommand {
+    pub secs: u64,
+    #[serde(default)]
+    pub hold: bool,
+}
+
+/// Details of the update validation reported as `firmware_update.validation`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+struct ValidationReport {
+    restart_count: Option<u8>,
+    deadline: Option<String>,
+    hold: bool,
+    failure_reason: Option<String>,
+}
+
+// twin channels are not available before the twin is connected, thus a D2C
+// event might have to wait for it
+#[derive(Default)]
+struct TwinConnection {
+    tx_reported_properties: Option<Sender<serde_json::Value>>,
+    tx_outgoing_message: Option<Sender<IotMessage>>,
+    pending_event: Option<serde_json::Value>,
+}
+
+// manual interventions while an update is validated
+enum ValidationControl {
+    Confirm,
+    Reject,
+    Extend(SystemTime),
 }
 
 #[derive(Default)]
 pub struct UpdateValidation {
     params: Option<UpdateValidationParams>,
     tx_cancel_timer: Option<oneshot::Sender<()>>,
+    tx_control: Option<mpsc::Sender<ValidationControl>>,
+    hold: Arc<AtomicBool>,
     status: Arc<RwLock<UpdateValidationStatus>>,
+    criteria: Arc<RwLock<Vec<CriterionResult>>>,
+    validation_report: Arc<RwLock<ValidationReport>>,
     local_update: bool,
+    twin: Arc<RwLock<TwinConnection>>,
 }
 
 impl UpdateValidation {
     pub async fn new() -> Result<Self> {
         let new_self = match UpdateValidationStatus::init() {
             UpdateValidationStatus::ValidatingTrial(trial) => Self::start_validation(trial == 0)?,
+            UpdateValidationStatus::Recovered => Self::recovered(),
             status => UpdateValidation {
                 status: Arc::new(RwLock::new(status)),
                 ..Default::default()
@@ -75,6 +143,61 @@ impl UpdateValidation {
         Ok(new_self)
     }
 
+    fn recovered() -> Self {
+        let mut finished = false;
+        let history = Self::update_history(|h| finished = Self::finish_recovered(h))
+            .unwrap_or_else(UpdateHistory::load);
+
+        Self::recovered_from(&history, finished)
+    }
+
+    // finishes the pending entry of the recovered update, returns false if
+    // it was finished by a former start already
+    fn finish_recovered(history: &mut UpdateHistory) -> bool {
+        // the failure reason was saved before the reboot to the old root partition
+        let Some(error) = history.pending_mut().map(|e| e.error.take()) else {
+            return false;
+        };
+
+        history.finish(Some(UpdateValidationStatus::Recovered), error);
+        true
+    }
+
+    fn recovered_from(history: &UpdateHistory, finished: bool) -> Self {
+        let failure_reason = history
+            .last()
+            .filter(|e| e.validation == Some(UpdateValidationStatus::Recovered))
+            .and_then(|e| {
+                e.error.clone().or_else(|| {
+                    e.reboot_reason
+                        .as_ref()
+                        .and_then(|r| r["reason"].as_str())
+                        .map(str::to_string)
+                })
+            });
+        let validation_report = ValidationReport {
+            failure_reason,
+            ..Default::default()
+        };
+
+        let mut twin = TwinConnection::default();
+
+        // the event is only sent once per recovered update, not per restart
+        if finished {
+            twin.pending_event = Some(Self::event(
+                &UpdateValidationStatus::Recovered,
+                &validation_report,
+            ));
+        }
+
+        UpdateValidation {
+            status: Arc::new(RwLock::new(UpdateValidationStatus::Recovered)),
+            validation_report: Arc::new(RwLock::new(validation_report)),
+            twin: Arc::new(RwLock::new(twin)),
+            ..Default::default()
+        }
+    }
+
     fn start_validation(first_start: bool) -> Result<Self> {
         let params = if first_start {
             UpdateValidationParams {
@@ -82,6 +205,7 @@ impl UpdateValidation {
                     .checked_add(Self::timeout())
                     .context("failed to build deadline timestamp")?,
                 restart_count: 0,
+                hold: false,
             }
         } else {
             // we detected update validation before, but were not validated before
@@ -97,6 +221,22 @@ impl UpdateValidation {
             first_start,
         )?;
 
+        // local updates start their history entry before installation, so
+        // an update without pending entry was installed from the cloud
+        if first_start {
+            Self::update_history(|h| {
+                if h.pending_mut().is_none() {
+                    h.start(UpdateHistoryEntry::new(
+                        UpdateSource::Cloud,
+                        None,
+                        OmnectOsVersion::from_sw_versions_file()
+                            .ok()
+                            .map(|v| v.to_string()),
+                    ));
+                }
+            });
+        }
+
         // check if there is an update validation config
         let mut local_update = false;
         if let Ok(true) = Path::new(&update_validation_config_path!()).try_exists() {
@@ -105,11 +245,19 @@ impl UpdateValidation {
                     .local;
         };
 
+        let validation_report = ValidationReport {
+            restart_count: Some(params.restart_count),
+            deadline: rfc3339(params.deadline_timestamp),
+            hold: params.hold,
+            failure_reason: None,
+        };
+
         let mut update_validation = UpdateValidation {
             status: Arc::new(RwLock::new(UpdateValidationStatus::ValidatingTrial(
                 params.restart_count,
             ))),
             params: Some(params),
+            validation_report: Arc::new(RwLock::new(validation_report)),
             local_update,
             ..Default::default()
         };
@@ -118,6 +266,25 @@ impl UpdateValidation {
         Ok(update_validation)
     }
 
+    pub async fn connect_twin(
+        &self,
+        tx_reported_properties: Sender<serde_json::Value>,
+        tx_outgoing_message: Sender<IotMessage>,
+    ) {
+        let event = {
+            let mut twin = self.twin.write().await;
+            twin.tx_reported_properties = Some(tx_reported_properties);
+            twin.tx_outgoing_message = Some(tx_outgoing_message);
+            twin.pending_event.take()
+        };
+
+        Self::report_twin(&self.status, &self.validation_report, &self.twin).await;
+
+        if let Some(event) = event {
+            Self::send_event(event, &self.twin).await;
+        }
+    }
+
     pub async fn set_authenticated(&mut self, authenticated: bool) -> Result<()> {
         if matches!(
             *self.status.read().await,
@@ -145,19 +312,133 @@ impl UpdateValidation {
         Ok(())
     }
 
-    async fn validate(local_update: bool) -> Result<()> {
+    /// Finalizes the update without waiting for the validation to complete.
+    pub async fn confirm(&self) -> Result<()> {
+        self.control(ValidationControl::Confirm).await
+    }
+
+    /// Fails the validation, thus the device reboots to the old root partition.
+    pub async fn reject(&self) -> Result<()> {
+        self.control(ValidationControl::Reject).await
+    }
+
+    /// Moves the deadline to `secs` from now. update-validation-observer
+    /// defers its overall timeout to the deadline, thus it stays the fallback
+    /// if omnect-device-service doesn't run anymore.
+    pub async fn extend(
+        &mut self,
+        cmd: &ExtendUpdateValidationCommand,
+    ) -> Result<serde_json::Value> {
+        self.ensure_validating().await?;
+
+        let deadline = SystemTime::now()
+            .checked_add(Duration::from_secs(cmd.secs))
+            .context("failed to build deadline timestamp")?;
+        let params = self.params.as_mut().context("validation params missing")?;
+
+        params.deadline_timestamp = deadline;
+        params.hold = cmd.hold;
+        to_json_file(params, UPDATE_VALIDATION_COMPLETE_BARRIER_FILE, false)?;
+        self.hold.store(cmd.hold, Ordering::Relaxed);
+
+        let deadline_secs = deadline
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context("failed to build deadline seconds")?
+            .as_secs();
+        fs::write(UPDATE_VALIDATION_DEADLINE_FILE, deadline_secs.to_string())
+            .context(format!("failed to write {UPDATE_VALIDATION_DEADLINE_FILE}"))?;
+
+        self.control(ValidationControl::Extend(deadline)).await?;
+
+        let deadline = OffsetDateTime::from(deadline)
+            .format(&Rfc3339)
+            .context("failed to format deadline")?;
+
+        {
+            let mut validation_report = self.validation_report.write().await;
+            validation_report.deadline = Some(deadline.clone());
+            validation_report.hold = cmd.hold;
+        }
+        Self::report_twin(&self.status, &self.validation_report, &self.twin).await;
+
+        info!(
+            "update validation deadline extended to {deadline}, hold: {}",
+            cmd.hold
+        );
+
+        Ok(json!({"deadline": deadline, "hold": cmd.hold}))
+    }
+
+    async fn ensure_validating(&self) -> Result<()> {
+        ensure!(
+            matches!(
+                *self.status.read().await,
+                UpdateValidationStatus::ValidatingTrial(_)
+            ),
+            "no update validation in progress"
+        );
+        Ok(())
+    }
+
+    async fn control(&self, control: ValidationControl) -> Result<()> {
+        self.ensure_validating().await?;
+
+        self.tx_control
+            .as_ref()
+            .context("no update validation in progress")?
+            .send(control)
+            .await
+            .context("update validation already finished")
+    }
+
+    async fn validate(
+        status: &Arc<RwLock<UpdateValidationStatus>>,
+        criteria: &Arc<RwLock<Vec<CriterionResult>>>,
+    ) -> Result<()> {
         debug!("validate update");
 
-        systemd::wait_for_system_running().await?;
+        let policy = ValidationPolicy::load()?;
+        let mut interval = interval(VALIDATION_CRITERIA_CHECK_INTERVAL);
+
+        // criteria are checked until all of them passed or the validation timed out
+        loop {
+            interval.tick().await;
+
+            let results = policy.evaluate().await;
+            let passed = results.iter().all(|r| r.passed);
+
+            if *criteria.read().await != results {
+                *criteria.write().await = results.clone();
+                Self::report_impl(status.read().await.clone(), results).await;
+            }
+
+            if passed {
+                break;
+            }
+        }
 
-        /* ToDo: if it returns with an error, we may want to handle the state
-         * "degraded" and possibly ignore certain failed services via configuration
-         */
-        info!("system is running");
+        info!("validation criteria passed");
+
+        let version = OmnectOsVersion::from_sw_versions_file()
+            .ok()
+            .map(|v| v.to_string());
+        let results = run_hooks(HookPhase::Validate, version.as_deref()).await;
+        if !results.is_empty() {
+            Self::update_history(|h| {
+                if let Some(entry) = h.pending_mut() {
+                    entry.hooks.extend(results.iter().cloned());
+                }
+            });
+        }
+        ensure_hooks_succeeded(&results)
+    }
 
+    async fn complete(local_update: bool) -> Result<()> {
         // remove iot-hub-device-service barrier file and start service as part of validation
         debug!("starting {IOT_HUB_DEVICE_UPDATE_SERVICE}");
-        fs::remove_file(UPDATE_VALIDATION_FILE).context("remove UPDATE_VALIDATION_FILE")?;
+        if Path::new(UPDATE_VALIDATION_FILE).exists() {
+            fs::remove_file(UPDATE_VALIDATION_FILE).context("remove UPDATE_VALIDATION_FILE")?;
+        }
 
         // in case of local update we don't take care of starting deviceupdate-agent.service,
         // since it might fail because of missing iothub connection.
@@ -168,7 +449,8 @@ impl UpdateValidation {
                 UnitAction::Start,
                 systemd_zbus::Mode::Fail,
             )
-            .await?;
+            .await
+            .context(format!("failed to start {IOT_HUB_DEVICE_UPDATE_SERVICE}"))?;
         }
 
         debug!("successfully started {IOT_HUB_DEVICE_UPDATE_SERVICE}");
@@ -176,19 +458,28 @@ impl UpdateValidation {
         Ok(())
     }
 
-    async fn finalize(status: Arc<RwLock<UpdateValidationStatus>>) -> Result<()> {
+    async fn finalize(
+        status: Arc<RwLock<UpdateValidationStatus>>,
+        criteria: Arc<RwLock<Vec<CriterionResult>>>,
+        validation_report: Arc<RwLock<ValidationReport>>,
+        twin: Arc<RwLock<TwinConnection>>,
+    ) -> Result<()> {
         info!("finalize update");
         let omnect_validate_update_part =
             RootPartition::from_index_string(bootloader_env::get(OMNECT_VALIDATE_UPDATE_PART)?)?;
-        bootloader_env::set(
+
+        // the new partition is permanently set together with its bootargs
+        let mut tx = bootloader_env::Transaction::default();
+        tx.set(
             OMNECT_OS_BOOTPART,
             &omnect_validate_update_part.index().to_string(),
-        )?;
+        );
 
-        Self::finalize_bootargs()?;
+        Self::finalize_bootargs(&mut tx);
 
-        bootloader_env::unset(OMNECT_VALIDATE_UPDATE)?;
-        bootloader_env::unset(OMNECT_VALIDATE_UPDATE_PART)?;
+        tx.unset(OMNECT_VALIDATE_UPDATE)
+            .unset(OMNECT_VALIDATE_UPDATE_PART);
+        tx.commit()?;
 
         fs::remove_file(UPDATE_VALIDATION_COMPLETE_BARRIER_FILE).context(format!(
             "update validation: remove {UPDATE_VALIDATION_COMPLETE_BARRIER_FILE}"
@@ -196,64 +487,237 @@ impl UpdateValidation {
 
         let _ = fs::remove_file(update_validation_config_path!());
 
-        let mut status = status.write().await;
-        *status = UpdateValidationStatus::Succeeded;
+        *status.write().await = UpdateValidationStatus::Succeeded;
+
+        Self::report_impl(
+            UpdateValidationStatus::Succeeded,
+            criteria.read().await.clone(),
+        )
+        .await;
+        Self::report_twin(&status, &validation_report, &twin).await;
+
+        if let Some(history) =
+            Self::update_history(|h| h.finish(Some(UpdateValidationStatus::Succeeded), None))
+            && let Err(e) = history
+                .report(twin.read().await.tx_reported_properties.as_ref())
+                .await
+        {
+            error!("finalize: failed to report update history: {e:#}");
+        }
 
-        Self::report_impl(status.clone()).await;
+        let event = Self::event(
+            &UpdateValidationStatus::Succeeded,
+            &*validation_report.read().await,
+        );
+        Self::send_event(event, &twin).await;
 
         Ok(())
     }
 
+    async fn fail(
+        e: &anyhow::Error,
+        status: &RwLock<UpdateValidationStatus>,
+        validation_report: &RwLock<ValidationReport>,
+        twin: &RwLock<TwinConnection>,
+    ) {
+        error!("update validation failed: {e:#}");
+
+        // saved in order to report it after the device recovered
+        let failure_reason = format!("{e:#}");
+        Self::update_history(|h| {
+            if let Some(entry) = h.pending_mut() {
+                entry.error = Some(failure_reason.clone());
+            }
+        });
+
+        validation_report.write().await.failure_reason = Some(failure_reason);
+        Self::report_twin(status, validation_report, twin).await;
+
+        if let Err(e) = systemd::reboot("swupdate-validation-failed", &e.to_string()).await {
+            error!("failed to trigger reboot: {e:#}");
+        }
+    }
+
+    // a broken history must never break the update validation
+    fn update_history<F>(f: F) -> Option<UpdateHistory>
+    where
+        F: FnOnce(&mut UpdateHistory),
+    {
+        UpdateHistory::update(f)
+            .inspect_err(|e| error!("failed to update history: {e:#}"))
+            .ok()
+    }
+
     pub async fn report(&self) {
-        Self::report_impl(self.status.read().await.clone()).await
+        Self::report_impl(
+            self.status.read().await.clone(),
+            self.criteria.read().await.clone(),
+        )
+        .await
     }
 
-    async fn report_impl(status: UpdateValidationStatus) {
+    async fn report_impl(status: UpdateValidationStatus, criteria: Vec<CriterionResult>) {
         web_service::publish(
             web_service::PublishChannel::UpdateValidationStatusV1,
-            json!({"status": status}),
+            json!({"status": status, "criteria": criteria}),
         )
         .await;
     }
 
+    // a failed report must never break the update validation
+    async fn report_twin(
+        status: &RwLock<UpdateValidationStatus>,
+        validation_report: &RwLock<ValidationReport>,
+        twin: &RwLock<TwinConnection>,
+    ) {
+        let twin = twin.read().await;
+        let Some(tx) = &twin.tx_reported_properties else {
+            debug!("report_twin: skip since tx_reported_properties is None");
+            return;
+        };
+
+        let mut validation = json!(*validation_report.read().await);
+        validation["status"] = json!(*status.read().await);
+
+        if let Err(e) = tx
+            .send(json!({
+                "firmware_update": {
+                    "validation": validation
+                }
+            }))
+            .await
+        {
+            error!("report_twin: send: {e:#}");
+        }
+    }
+
+    fn event(
+        status: &UpdateValidationStatus,
+        validation_report: &ValidationReport,
+    ) -> serde_json::Value {
+        json!({
+            "update_validation": {
+                "status": status,
+                "version": OmnectOsVersion::from_sw_versions_file().ok().map(|v| v.to_string()),
+                "restart_count": validation_report.restart_count,
+                "failure_reason": validation_report.failure_reason,
+            }
+        })
+    }
+
+    async fn send_event(event: serde_json::Value, twin: &RwLock<TwinConnection>) {
+        let mut twin = twin.write().await;
+        let Some(tx) = &twin.tx_outgoing_message else {
+            debug!("send_event: postpone until twin is connected");
+            twin.pending_event = Some(event);
+            return;
+        };
+
+        let msg = match serde_json::to_vec(&event)
+            .context("send_event: failed to serialize event")
+            .and_then(|body| {
+                IotMessage::builder()
+                    .set_body(body)
+                    .set_content_type("application/json")
+                    .set_content_encoding("utf-8")
+                    .build()
+                    .context("send_event: failed to build message")
+            }) {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("{e:#}");
+                return;
+            }
+        };
+
+        if let Err(e) = tx.send(msg).await {
+            error!("send_event: send: {e:#}");
+        }
+    }
+
     fn start_timeout(&mut self) -> Result<()> {
-        let (tx_cancel_timer, rx_cancel_timer) = oneshot::channel();
-        let remaining_time = self
-            .params
-            .clone()
-            .context("validation params missing")?
-            .deadline_timestamp
+        let (tx_cancel_timer, mut rx_cancel_timer) = oneshot::channel();
+        let (tx_control, mut rx_control) = mpsc::channel(1);
+        let (tx_confirmed, mut rx_confirmed) = watch::channel(false);
+        let params = self.params.clone().context("validation params missing")?;
+        let mut deadline = params.deadline_timestamp;
+        let remaining_time = deadline
             .duration_since(SystemTime::now())
             .context("failed to build remaining timeout secs")?;
         let status = Arc::clone(&self.status);
+        let criteria = Arc::clone(&self.criteria);
         let local_update = self.local_update;
+        let validation_report = Arc::clone(&self.validation_report);
+        let twin = Arc::clone(&self.twin);
+        self.hold.store(params.hold, Ordering::Relaxed);
+        let hold = Arc::clone(&self.hold);
         self.tx_cancel_timer = Some(tx_cancel_timer);
+        self.tx_control = Some(tx_control);
         tokio::spawn(async move {
             info!("observe update with timeout: {}s", remaining_time.as_secs());
 
+            let observe_status = Arc::clone(&status);
+            let observe_criteria = Arc::clone(&criteria);
+            let observe_validation_report = Arc::clone(&validation_report);
+            let observe_twin = Arc::clone(&twin);
             let observe_update = async move {
                 // now wait that we get canceled as a result of a successful startup
-                if let Err(e) = rx_cancel_timer.await {
-                    warn!("observe update validation: {e:#}. Application stopped from outside?");
-                    return Ok(());
+                // or that the update gets confirmed manually
+                tokio::select! {
+                    result = &mut rx_cancel_timer => {
+                        if let Err(e) = result {
+                            warn!("observe update validation: {e:#}. Application stopped from outside?");
+                            return Ok(());
+                        }
+                    }
+                    _ = Self::confirmed(&mut rx_confirmed) => {}
                 }
 
-                Self::validate(local_update).await?;
-                Self::finalize(status).await
-            };
+                tokio::select! {
+                    result = Self::validate(&observe_status, &observe_criteria) => result?,
+                    _ = Self::confirmed(&mut rx_confirmed) => {}
+                }
 
-            let error = match timeout(remaining_time, observe_update).await {
-                Ok(Ok(())) => None,
-                Ok(Err(e)) => Some(e),
-                Err(e) => Some(anyhow::anyhow!(e.to_string())),
+                Self::complete(local_update).await?;
+
+                if hold.load(Ordering::Relaxed) {
+                    info!("update validation on hold until confirmed");
+                    Self::confirmed(&mut rx_confirmed).await;
+                }
+
+                Self::finalize(
+                    observe_status,
+                    observe_criteria,
+                    observe_validation_report,
+                    observe_twin,
+                )
+                .await
+            };
+            tokio::pin!(observe_update);
+
+            let error = loop {
+                let remaining_time = deadline
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+
+                tokio::select! {
+                    result = &mut observe_update => break result.err(),
+                    _ = sleep(remaining_time) => break Some(Self::timeout_error(&criteria).await),
+                    Some(control) = rx_control.recv() => match control {
+                        ValidationControl::Confirm => {
+                            info!("update validation confirmed manually");
+                            tx_confirmed.send_replace(true);
+                        }
+                        ValidationControl::Reject => {
+                            break Some(anyhow::anyhow!("update rejected manually"))
+                        }
+                        ValidationControl::Extend(extended) => deadline = extended,
+                    }
+                }
             };
 
             if let Some(e) = error {
-                error!("update validation failed: {e:#}");
-                if let Err(e) = systemd::reboot("swupdate-validation-failed", &e.to_string()).await
-                {
-                    error!("failed to trigger reboot: {e:#}");
-                }
+                Self::fail(&e, &status, &validation_report, &twin).await;
             } else {
                 info!("update successfully validated")
             }
@@ -262,20 +726,45 @@ impl UpdateValidation {
         Ok(())
     }
 
-    fn finalize_bootargs() -> Result<()> {
+    // resolves as soon as the update was confirmed manually
+    async fn confirmed(rx_confirmed: &mut watch::Receiver<bool>) {
+        if rx_confirmed.wait_for(|confirmed| *confirmed).await.is_err() {
+            // the sender lives as long as the validation task
+            std::future::pending::<()>().await
+        }
+    }
+
+    async fn timeout_error(criteria: &RwLock<Vec<CriterionResult>>) -> anyhow::Error {
+        let not_met: Vec<String> = criteria
+            .read()
+            .await
+            .iter()
+            .filter(|c| !c.passed)
+            .map(|c| c.to_string())
+            .collect();
+
+        if not_met.is_empty() {
+            anyhow::anyhow!("deadline has elapsed")
+        } else {
+            anyhow::anyhow!(
+                "deadline has elapsed, criteria not met: {}",
+                not_met.join("; ")
+            )
+        }
+    }
+
+    fn finalize_bootargs(tx: &mut bootloader_env::Transaction) {
         let omnect_validate_extra_bootargs =
             bootloader_env::get(OMNECT_VALIDATE_EXTRA_BOOTARGS).unwrap_or_default();
 
         if omnect_validate_extra_bootargs == NOARGS_SENTINEL {
-            bootloader_env::unset(OMNECT_EXTRA_BOOTARGS)?;
-            bootloader_env::unset(OMNECT_VALIDATE_EXTRA_BOOTARGS)?;
+            tx.unset(OMNECT_EXTRA_BOOTARGS)
+                .unset(OMNECT_VALIDATE_EXTRA_BOOTARGS);
         } else if !omnect_validate_extra_bootargs.is_empty() {
-            bootloader_env::set(OMNECT_EXTRA_BOOTARGS, &omnect_validate_extra_bootargs)?;
-            bootloader_env::unset(OMNECT_VALIDATE_EXTRA_BOOTARGS)?;
+            tx.set(OMNECT_EXTRA_BOOTARGS, &omnect_validate_extra_bootargs)
+                .unset(OMNECT_VALIDATE_EXTRA_BOOTARGS);
         }
         // else empty omnect_validate_extra_bootargs -> no change to omnect_extra_bootargs
-
-        Ok(())
     }
 
     fn timeout() -> Duration {
@@ -295,6 +784,13 @@ impl UpdateValidation {
     }
 }
 
+fn rfc3339(timestamp: SystemTime) -> Option<String> {
+    OffsetDateTime::from(timestamp)
+        .format(&Rfc3339)
+        .inspect_err(|e| error!("failed to format timestamp: {e:#}"))
+        .ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -308,7 +804,9 @@ mod tests {
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "old_value").expect("set extra");
         bootloader_env::set(OMNECT_VALIDATE_EXTRA_BOOTARGS, NOARGS_SENTINEL).expect("set validate");
 
-        UpdateValidation::finalize_bootargs().expect("finalize_bootargs");
+        let mut tx = bootloader_env::Transaction::default();
+        UpdateValidation::finalize_bootargs(&mut tx);
+        tx.commit().expect("commit");
 
         assert!(
             bootloader_env::get(OMNECT_EXTRA_BOOTARGS)
@@ -335,7 +833,9 @@ mod tests {
         )
         .expect("set validate");
 
-        UpdateValidation::finalize_bootargs().expect("finalize_bootargs");
+        let mut tx = bootloader_env::Transaction::default();
+        UpdateValidation::finalize_bootargs(&mut tx);
+        tx.commit().expect("commit");
 
         assert_eq!(
             bootloader_env::get(OMNECT_EXTRA_BOOTARGS).expect("get extra"),
@@ -356,7 +856,9 @@ mod tests {
         bootloader_env::set(OMNECT_EXTRA_BOOTARGS, "existing_args").expect("set extra");
         // OMNECT_VALIDATE_EXTRA_BOOTARGS intentionally not set
 
-        UpdateValidation::finalize_bootargs().expect("finalize_bootargs");
+        let mut tx = bootloader_env::Transaction::default();
+        UpdateValidation::finalize_bootargs(&mut tx);
+        tx.commit().expect("commit");
 
         assert_eq!(
             bootloader_env::get(OMNECT_EXTRA_BOOTARGS).expect("get extra"),
@@ -364,4 +866,116 @@ mod tests {
             "expected omnect_extra_bootargs to remain unchanged"
         );
     }
+
+    fn event_body(message: IotMessage) -> serde_json::Value {
+        serde_json::from_slice(&message.body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn report_and_event_succeeded() {
+        let (tx_reported_properties, mut rx_reported_properties) = mpsc::channel(10);
+        let (tx_outgoing_message, mut rx_outgoing_message) = mpsc::channel(10);
+        let update_validation = UpdateValidation {
+            status: Arc::new(RwLock::new(UpdateValidationStatus::Succeeded)),
+            validation_report: Arc::new(RwLock::new(ValidationReport {
+                restart_count: Some(1),
+                deadline: Some("2026-10-16T12:05:00Z".to_string()),
+                hold: false,
+                failure_reason: None,
+            })),
+            ..Default::default()
+        };
+
+        // the event waits for the twin to be connected
+        let event = UpdateValidation::event(
+            &UpdateValidationStatus::Succeeded,
+            &*update_validation.validation_report.read().await,
+        );
+        UpdateValidation::send_event(event, &update_validation.twin).await;
+
+        update_validation
+            .connect_twin(tx_reported_properties, tx_outgoing_message)
+            .await;
+
+        assert_eq!(
+            rx_reported_properties.recv().await.unwrap(),
+            json!({
+                "firmware_update": {
+                    "validation": {
+                        "status": "Succeeded",
+                        "restart_count": 1,
+                        "deadline": "2026-10-16T12:05:00Z",
+                        "hold": false,
+                        "failure_reason": null
+                    }
+                }
+            })
+        );
+
+        let event = event_body(rx_outgoing_message.recv().await.unwrap());
+        assert_eq!(event["update_validation"]["status"], json!("Succeeded"));
+        assert_eq!(event["update_validation"]["restart_count"], json!(1));
+        assert_eq!(event["update_validation"]["failure_reason"], json!(null));
+        assert!(rx_outgoing_message.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn report_and_event_recovered_once() {
+        let mut history = UpdateHistory::default();
+        history.start(UpdateHistoryEntry::new(
+            UpdateSource::Local,
+            Some("1.0.0.0".to_string()),
+            Some("2.0.0.0".to_string()),
+        ));
+        history.pending_mut().unwrap().error = Some("unit is not active".to_string());
+
+        // the first start after the recovery finishes the update
+        assert!(UpdateValidation::finish_recovered(&mut history));
+        assert!(!UpdateValidation::finish_recovered(&mut history));
+
+        let (tx_reported_properties, mut rx_reported_properties) = mpsc::channel(10);
+        let (tx_outgoing_message, mut rx_outgoing_message) = mpsc::channel(10);
+        let update_validation = UpdateValidation::recovered_from(&history, true);
+
+        update_validation
+            .connect_twin(tx_reported_properties, tx_outgoing_message)
+            .await;
+
+        assert_eq!(
+            rx_reported_properties.recv().await.unwrap(),
+            json!({
+                "firmware_update": {
+                    "validation": {
+                        "status": "Recovered",
+                        "restart_count": null,
+                        "deadline": null,
+                        "hold": false,
+                        "failure_reason": "unit is not active"
+                    }
+                }
+            })
+        );
+
+        let event = event_body(rx_outgoing_message.recv().await.unwrap());
+        assert_eq!(event["update_validation"]["status"], json!("Recovered"));
+        assert_eq!(
+            event["update_validation"]["failure_reason"],
+            json!("unit is not active")
+        );
+
+        // further starts only report the recovered update
+        let (tx_reported_properties, mut rx_reported_properties) = mpsc::channel(10);
+        let (tx_outgoing_message, mut rx_outgoing_message) = mpsc::channel(10);
+        let update_validation = UpdateValidation::recovered_from(&history, false);
+
+        update_validation
+            .connect_twin(tx_reported_properties, tx_outgoing_message)
+            .await;
+
+        assert_eq!(
+            rx_reported_properties.recv().await.unwrap()["firmware_update"]["validation"]["status"],
+            json!("Recovered")
+        );
+        assert!(rx_outgoing_message.try_recv().is_err());
+    }
 }
@@ -0,0 +1,389 @@
+use crate::{
+    common::from_json_file,
+    systemd::{self, unit},
+};
+use anyhow::{Context, Result, ensure};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{env, fs, path::Path, process::Stdio};
+use tokio::{
+    net::TcpStream,
+    time::{Duration, timeout},
+};
+
+macro_rules! update_validation_policy_path {
+    () => {
+        env::var("UPDATE_VALIDATION_POLICY_PATH")
+            .unwrap_or("/etc/omnect/update-validation-policy.json".to_string())
+    };
+}
+
+const CHECK_TIMEOUT_SECS_DEFAULT: u64 = 10;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    pub name: String,
+    pub command: Vec<String>,
+}
+
+/// Criteria an update must meet in addition to the system being running.
+/// Without policy file the system must be running without failed units.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ValidationPolicy {
+    #[serde(default)]
+    pub required_active_units: Vec<String>,
+    /// glob patterns of units that may fail, thus a "degraded" system is
+    /// accepted if only those failed
+    #[serde(default)]
+    pub tolerated_failed_units: Vec<String>,
+    #[serde(default)]
+    pub health_checks: Vec<HealthCheck>,
+    /// "host:port" that must accept tcp connections
+    #[serde(default)]
+    pub reachability_targets: Vec<String>,
+    #[serde(default)]
+    pub min_uptime_secs: u64,
+    pub check_timeout_secs: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Criterion {
+    SystemRunning,
+    ActiveUnit,
+    HealthCheck,
+    Reachability,
+    MinUptime,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CriterionResult {
+    pub criterion: Criterion,
+    pub name: Option<String>,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+impl CriterionResult {
+    fn new(criterion: Criterion, name: Option<&str>, result: Result<()>) -> Self {
+        CriterionResult {
+            criterion,
+            name: name.map(str::to_string),
+            passed: result.is_ok(),
+            error: result.err().map(|e| format!("{e:#}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Criterion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let criterion = match self {
+            Criterion::SystemRunning => "system_running",
+            Criterion::ActiveUnit => "active_unit",
+            Criterion::HealthCheck => "health_check",
+            Criterion::Reachability => "reachability",
+            Criterion::MinUptime => "min_uptime",
+        };
+        write!(f, "{criterion}")
+    }
+}
+
+impl std::fmt::Display for CriterionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.criterion)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name}")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ValidationPolicy {
+    pub fn load() -> Result<Self> {
+        let path = update_validation_policy_path!();
+
+        if !Path::new(&path).exists() {
+            debug!("no update validation policy at {path}");
+            return Ok(Self::default());
+        }
+
+        let policy: Self = from_json_file(&path)?;
+
+        for pattern in &policy.tolerated_failed_units {
+            glob::Pattern::new(pattern)
+                .context(format!("invalid tolerated failed unit pattern {pattern}"))?;
+        }
+
+        for check in &policy.health_checks {
+            ensure!(
+                !check.command.is_empty(),
+                "health check {} has no command",
+                check.name
+            );
+        }
+
+        Ok(policy)
+    }
+
+    /// Evaluates all criteria. Criteria that fail now might pass later, e.g.
+    /// because a unit is still starting.
+    pub async fn evaluate(&self) -> Vec<CriterionResult> {
+        let mut results = vec![CriterionResult::new(
+            Criterion::SystemRunning,
+            None,
+            self.system_running().await,
+        )];
+
+        for unit in &self.required_active_units {
+            results.push(CriterionResult::new(
+                Criterion::ActiveUnit,
+                Some(unit),
+                unit_active(unit).await,
+            ));
+        }
+
+        for check in &self.health_checks {
+            results.push(CriterionResult::new(
+                Criterion::HealthCheck,
+                Some(&check.name),
+                run_health_check(check, self.check_timeout()).await,
+            ));
+        }
+
+        for target in &self.reachability_targets {
+            results.push(CriterionResult::new(
+                Criterion::Reachability,
+                Some(target),
+                reachable(target, self.check_timeout()).await,
+            ));
+        }
+
+        if self.min_uptime_secs > 0 {
+            results.push(CriterionResult::new(
+                Criterion::MinUptime,
+                None,
+                uptime().and_then(|uptime| self.min_uptime_reached(uptime)),
+            ));
+        }
+
+        results
+    }
+
+    async fn system_running(&self) -> Result<()> {
+        let state = systemd::system_state().await?;
+
+        let failed_units = if state == "degraded" {
+            unit::failed_units().await?
+        } else {
+            vec![]
+        };
+
+        self.system_state_accepted(&state, &failed_units)
+    }
+
+    fn system_state_accepted(&self, state: &str, failed_units: &[String]) -> Result<()> {
+        match state {
+            "running" => Ok(()),
+            "degraded" => {
+                let not_tolerated: Vec<&str> = failed_units
+                    .iter()
+                    .filter(|unit| {
+                        !self.tolerated_failed_units.iter().any(|pattern| {
+                            glob::Pattern::new(pattern).is_ok_and(|p| p.matches(unit))
+                        })
+                    })
+                    .map(String::as_str)
+                    .collect();
+
+                ensure!(
+                    not_tolerated.is_empty(),
+                    "system is degraded, failed units: {}",
+                    not_tolerated.join(", ")
+                );
+                Ok(())
+            }
+            state => anyhow::bail!("system is {state}"),
+        }
+    }
+
+    fn min_uptime_reached(&self, uptime: Duration) -> Result<()> {
+        ensure!(
+            uptime.as_secs() >= self.min_uptime_secs,
+            "uptime {}s is less than {}s",
+            uptime.as_secs(),
+            self.min_uptime_secs
+        );
+        Ok(())
+    }
+
+    fn check_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.check_timeout_secs
+                .unwrap_or(CHECK_TIMEOUT_SECS_DEFAULT),
+        )
+    }
+}
+
+#[cfg(not(feature = "mock"))]
+async fn unit_active(unit: &str) -> Result<()> {
+    ensure!(
+        !unit::active_units_by_patterns(&[unit]).await?.is_empty(),
+        "unit is not active"
+    );
+    Ok(())
+}
+
+// there are no active units under mock, but required units must not fail
+// every update validation
+#[cfg(feature = "mock")]
+async fn unit_active(_unit: &str) -> Result<()> {
+    Ok(())
+}
+
+async fn run_health_check(check: &HealthCheck, check_timeout: Duration) -> Result<()> {
+    let status = timeout(
+        check_timeout,
+        tokio::process::Command::new(&check.command[0])
+            .args(&check.command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status(),
+    )
+    .await
+    .context(format!("timed out after {check_timeout:?}"))?
+    .context("failed to execute")?;
+
+    ensure!(status.success(), "{status}");
+    Ok(())
+}
+
+async fn reachable(target: &str, check_timeout: Duration) -> Result<()> {
+    timeout(check_timeout, TcpStream::connect(target))
+        .await
+        .context(format!("timed out after {check_timeout:?}"))?
+        .context("failed to connect")?;
+    Ok(())
+}
+
+fn uptime() -> Result<Duration> {
+    let uptime = fs::read_to_string("/proc/uptime").context("failed to read /proc/uptime")?;
+    let secs = uptime
+        .split_whitespace()
+        .next()
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .context("failed to parse /proc/uptime")?;
+
+    Ok(Duration::from_secs_f64(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_state_accepted() {
+        let policy = ValidationPolicy {
+            tolerated_failed_units: vec!["optional-*.service".to_string()],
+            ..Default::default()
+        };
+
+        assert!(policy.system_state_accepted("running", &[]).is_ok());
+        assert!(policy.system_state_accepted("starting", &[]).is_err());
+        assert!(
+            policy
+                .system_state_accepted("degraded", &["optional-app.service".to_string()])
+                .is_ok()
+        );
+
+        let e = policy
+            .system_state_accepted(
+                "degraded",
+                &[
+                    "optional-app.service".to_string(),
+                    "app.service".to_string(),
+                ],
+            )
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "system is degraded, failed units: app.service"
+        );
+
+        assert!(
+            ValidationPolicy::default()
+                .system_state_accepted("degraded", &["optional-app.service".to_string()])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn min_uptime_reached() {
+        let policy = ValidationPolicy {
+            min_uptime_secs: 60,
+            ..Default::default()
+        };
+
+        assert!(policy.min_uptime_reached(Duration::from_secs(59)).is_err());
+        assert!(policy.min_uptime_reached(Duration::from_secs(60)).is_ok());
+        assert!(uptime().is_ok());
+    }
+
+    #[tokio::test]
+    async fn health_checks_and_reachability() {
+        let check = |command: &[&str]| HealthCheck {
+            name: "check".to_string(),
+            command: command.iter().map(|c| c.to_string()).collect(),
+        };
+        let check_timeout = Duration::from_secs(5);
+
+        assert!(
+            run_health_check(&check(&["sh", "-c", "exit 0"]), check_timeout)
+                .await
+                .is_ok()
+        );
+        assert!(
+            run_health_check(&check(&["sh", "-c", "exit 1"]), check_timeout)
+                .await
+                .is_err()
+        );
+        assert!(
+            run_health_check(&check(&["sleep", "10"]), Duration::from_millis(100))
+                .await
+                .is_err()
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+
+        assert!(reachable(&target, check_timeout).await.is_ok());
+        drop(listener);
+        assert!(reachable(&target, check_timeout).await.is_err());
+    }
+
+    #[test]
+    fn parse_policy() {
+        let policy: ValidationPolicy = serde_json::from_value(serde_json::json!({
+            "required_active_units": ["docker.service"],
+            "tolerated_failed_units": ["optional-*.service"],
+            "health_checks": [{"name": "app", "command": ["/usr/bin/app-health"]}],
+            "reachability_targets": ["example.com:443"],
+            "min_uptime_secs": 120
+        }))
+        .unwrap();
+
+        assert_eq!(policy.required_active_units, vec!["docker.service"]);
+        assert_eq!(policy.check_timeout(), Duration::from_secs(10));
+
+        assert!(
+            serde_json::from_value::<ValidationPolicy>(serde_json::json!({"unknown": true}))
+                .is_err()
+        );
+    }
+}
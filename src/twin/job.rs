@@ -0,0 +1,434 @@
+use crate::twin::feature::*;
+use futures::{FutureExt, future::LocalBoxFuture, stream::FuturesUnordered};
+use log::debug;
+use serde::Serialize;
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet, VecDeque},
+};
+use tokio_util::sync::CancellationToken;
+
+pub(crate) type JobId = u64;
+
+// max number of requests queued per busy feature
+const QUEUE_MAX_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
+/// State of a job as it is reported to the twin and published to the web
+/// service.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct JobReport {
+    pub id: JobId,
+    pub feature: String,
+    pub command: String,
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobReport {
+    fn new(id: JobId, feature: String, cmd: &Command) -> Self {
+        let command: &'static str = cmd.into();
+
+        JobReport {
+            id,
+            feature,
+            command: command.to_string(),
+            status: JobStatus::Running,
+            result: None,
+            error: None,
+        }
+    }
+
+    pub fn finish(&mut self, result: &CommandResult) {
+        match result {
+            Ok(result) => {
+                self.status = JobStatus::Succeeded;
+                self.result = result.clone();
+            }
+            Err(e) => {
+                self.status = if e.is::<CommandTimeoutError>() {
+                    JobStatus::TimedOut
+                } else {
+                    JobStatus::Failed
+                };
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+}
+
+pub(crate) struct JobFinished {
+    pub feature: Box<DynFeature<'static>>,
+    pub command: Command,
+    pub report: Option<JobReport>,
+    pub result: CommandResult,
+}
+
+struct BusyFeature {
+    name: String,
+    version: u8,
+    cancel: CancellationToken,
+    handler: Option<Box<dyn BusyHandler>>,
+}
+
+/// Runs long-running commands (see `Command::is_long_running`) concurrently to
+/// the twin run loop.
+///
+/// While a job is running the feature is owned by the job. Requests for a busy
+/// feature are queued and handled in order as soon as the job finished, which
+/// serializes all commands per feature. Only the busy handler of the feature
+/// (see `Feature::busy_handler`) may answer requests in the meantime.
+///
+/// Feature futures are not `Send`, thus jobs are not spawned onto the runtime,
+/// but polled by the run loop itself. That is what a task spawned by
+/// `tokio::task::spawn_local` would do as well: it runs on the thread of the run
+/// loop, so a synchronous section would stall the loop either way. Instead,
+/// blocking work inside a feature, e.g. extracting and hashing an update or
+/// swupdate, has to be moved to `tokio::task::spawn_blocking` by the feature.
+#[derive(Default)]
+pub(crate) struct Jobs {
+    next_id: JobId,
+    busy: HashMap<TypeId, BusyFeature>,
+    queued: HashMap<TypeId, VecDeque<CommandRequest>>,
+    deferred_connect_twin: HashSet<TypeId>,
+    pub running: FuturesUnordered<LocalBoxFuture<'static, JobFinished>>,
+}
+
+impl Jobs {
+    pub fn is_busy(&self, feature_id: &TypeId) -> bool {
+        self.busy.contains_key(feature_id)
+    }
+
+    /// Returns name and version of all features currently owned by a job.
+    pub fn busy_features(&self) -> impl Iterator<Item = (&String, u8)> {
+        self.busy.values().map(|f| (&f.name, f.version))
+    }
+
+    pub fn start(
+        &mut self,
+        mut feature: Box<DynFeature<'static>>,
+        command: Command,
+        with_report: bool,
+        cancel: CancellationToken,
+    ) -> Option<JobReport> {
+        let report = with_report.then(|| {
+            self.next_id += 1;
+            JobReport::new(self.next_id, feature.name(), &command)
+        });
+
+        self.busy.insert(
+            command.feature_id(),
+            BusyFeature {
+                name: feature.name(),
+                version: feature.version(),
+                cancel: cancel.clone(),
+                handler: feature.busy_handler(),
+            },
+        );
+
+        let job_report = report.clone();
+
+        self.running.push(
+            async move {
+                let result = command_with_timeout(&mut feature, &command, cancel).await;
+
+                JobFinished {
+                    feature,
+                    command,
+                    report: job_report,
+                    result,
+                }
+            }
+            .boxed_local(),
+        );
+
+        report
+    }
+
+    /// Marks the feature as idle again and returns whether `connect_twin`
+    /// was missed while it was busy.
+    pub fn finish(&mut self, feature_id: &TypeId) -> bool {
+        self.busy.remove(feature_id);
+        self.deferred_connect_twin.remove(feature_id)
+    }
+
+    /// Lets the busy handler of the feature answer `cmd`. Returns `None` if
+    /// the request has to be queued.
+    pub fn handle_busy(&self, cmd: &Command) -> Option<CommandResult> {
+        let busy = self.busy.get(&cmd.feature_id())?;
+
+        busy.handler.as_ref()?.handle(cmd, &busy.cancel)
+    }
+
+    /// Queues a request for a busy feature. Requests without reply channel, e.g.
+    /// ticks or scheduled runs, are dropped if an equal one is already queued.
+    /// If the queue is full, the request is handed back in order to be rejected.
+    pub fn enqueue(&mut self, request: CommandRequest) -> Result<(), CommandRequest> {
+        let queue = self.queued.entry(request.command.feature_id()).or_default();
+
+        if request.reply.is_none()
+            && queue
+                .iter()
+                .any(|r| r.reply.is_none() && r.command == request.command)
+        {
+            debug!("enqueue: {:?} already queued", request.command);
+            return Ok(());
+        }
+
+        if queue.len() >= QUEUE_MAX_LEN {
+            return Err(request);
+        }
+
+        queue.push_back(request);
+        Ok(())
+    }
+
+    pub fn dequeue(&mut self, feature_id: &TypeId) -> Option<CommandRequest> {
+        self.queued.get_mut(feature_id)?.pop_front()
+    }
+
+    /// Busy features cannot be connected to the twin. Remember them in order
+    /// to connect as soon as their job finished.
+    pub fn defer_connect_twin(&mut self) {
+        self.deferred_connect_twin.extend(self.busy.keys().copied());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use futures::StreamExt;
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    struct BlockingFeature;
+
+    // answers network reloads and cancels the job on ticks
+    struct TestBusyHandler;
+
+    impl BusyHandler for TestBusyHandler {
+        fn handle(&self, cmd: &Command, job: &CancellationToken) -> Option<CommandResult> {
+            match cmd {
+                Command::ReloadNetwork => Some(Ok(Some(json!("busy")))),
+                _ => {
+                    job.cancel();
+                    None
+                }
+            }
+        }
+    }
+
+    impl Feature for BlockingFeature {
+        fn name(&self) -> String {
+            "blocking".to_string()
+        }
+
+        fn version(&self) -> u8 {
+            1
+        }
+
+        fn is_enabled(&self) -> bool {
+            true
+        }
+
+        fn busy_handler(&self) -> Option<Box<dyn BusyHandler>> {
+            Some(Box::new(TestBusyHandler))
+        }
+
+        async fn command(&mut self, _cmd: &Command, _cancel: CancellationToken) -> CommandResult {
+            tokio::task::spawn_blocking(|| std::thread::sleep(Duration::from_millis(500))).await?;
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn job_report_test() {
+        let cmd = Command::ReloadNetwork;
+        let mut report = JobReport::new(1, "network_status".to_string(), &cmd);
+
+        assert_eq!(report.status, JobStatus::Running);
+        assert_eq!(report.command, "ReloadNetwork");
+
+        report.finish(&Ok(Some(json!({"foo": "bar"}))));
+        assert_eq!(report.status, JobStatus::Succeeded);
+        assert_eq!(report.result, Some(json!({"foo": "bar"})));
+
+        report.finish(&Err(anyhow!("failed")));
+        assert_eq!(report.status, JobStatus::Failed);
+        assert_eq!(report.error, Some("failed".to_string()));
+
+        report.finish(&Err(CommandTimeoutError {
+            command: "ReloadNetwork",
+            timeout: std::time::Duration::from_secs(1),
+        }
+        .into()));
+        assert_eq!(report.status, JobStatus::TimedOut);
+        assert_eq!(
+            report.error,
+            Some("ReloadNetwork timed out after 1s".to_string())
+        );
+    }
+
+    #[test]
+    fn queue_is_per_feature_and_ordered_test() {
+        let mut jobs = Jobs::default();
+        let network_id = Command::ReloadNetwork.feature_id();
+        let reboot_id = Command::CancelReboot.feature_id();
+
+        assert!(
+            jobs.enqueue(CommandRequest {
+                command: Command::ReloadNetwork,
+                reply: None,
+            })
+            .is_ok()
+        );
+        assert!(
+            jobs.enqueue(CommandRequest {
+                command: Command::CancelReboot,
+                reply: None,
+            })
+            .is_ok()
+        );
+        assert!(
+            jobs.enqueue(CommandRequest {
+                command: Command::Tick(TickCommand {
+                    feature_id: network_id,
+                }),
+                reply: None,
+            })
+            .is_ok()
+        );
+
+        assert_eq!(
+            jobs.dequeue(&network_id).unwrap().command,
+            Command::ReloadNetwork
+        );
+        assert!(matches!(
+            jobs.dequeue(&network_id).unwrap().command,
+            Command::Tick(_)
+        ));
+        assert!(jobs.dequeue(&network_id).is_none());
+        assert_eq!(
+            jobs.dequeue(&reboot_id).unwrap().command,
+            Command::CancelReboot
+        );
+    }
+
+    #[test]
+    fn queue_coalesces_and_is_bounded_test() {
+        let mut jobs = Jobs::default();
+        let reboot_id = Command::RunScheduledReboot.feature_id();
+
+        // periodic requests pile up only once
+        for _ in 0..3 {
+            assert!(
+                jobs.enqueue(CommandRequest {
+                    command: Command::RunScheduledReboot,
+                    reply: None,
+                })
+                .is_ok()
+            );
+        }
+
+        // requests with reply channel are never coalesced
+        let mut replies = vec![];
+        for _ in 1..QUEUE_MAX_LEN {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            replies.push(rx);
+            assert!(
+                jobs.enqueue(CommandRequest {
+                    command: Command::CancelReboot,
+                    reply: Some(tx),
+                })
+                .is_ok()
+            );
+        }
+
+        // the queue is full
+        let rejected = jobs
+            .enqueue(CommandRequest {
+                command: Command::ClearRebootSafeMode,
+                reply: None,
+            })
+            .unwrap_err();
+        assert_eq!(rejected.command, Command::ClearRebootSafeMode);
+
+        assert_eq!(
+            jobs.dequeue(&reboot_id).unwrap().command,
+            Command::RunScheduledReboot
+        );
+        for _ in 1..QUEUE_MAX_LEN {
+            assert_eq!(
+                jobs.dequeue(&reboot_id).unwrap().command,
+                Command::CancelReboot
+            );
+        }
+        assert!(jobs.dequeue(&reboot_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn running_job_does_not_block_run_loop_test() {
+        let mut jobs = Jobs::default();
+        let feature_id = Command::ReloadNetwork.feature_id();
+        let start = Instant::now();
+
+        jobs.start(
+            DynFeature::new_box(BlockingFeature),
+            Command::ReloadNetwork,
+            false,
+            CancellationToken::new(),
+        );
+        assert!(jobs.is_busy(&feature_id));
+
+        // the run loop polls the job and gets back control while it is running
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), jobs.running.next())
+                .await
+                .is_err()
+        );
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        let finished = jobs.running.next().await.unwrap();
+        assert!(finished.result.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn busy_handler_answers_requests_test() {
+        let mut jobs = Jobs::default();
+        let network_id = Command::ReloadNetwork.feature_id();
+        let cancel = CancellationToken::new();
+        let tick = Command::Tick(TickCommand {
+            feature_id: network_id,
+        });
+
+        // idle features have no busy handler
+        assert!(jobs.handle_busy(&Command::ReloadNetwork).is_none());
+
+        jobs.start(
+            DynFeature::new_box(BlockingFeature),
+            Command::ReloadNetwork,
+            false,
+            cancel.clone(),
+        );
+
+        assert_eq!(
+            jobs.handle_busy(&Command::ReloadNetwork).unwrap().unwrap(),
+            Some(json!("busy"))
+        );
+        assert!(!cancel.is_cancelled());
+        assert!(jobs.handle_busy(&tick).is_none());
+        assert!(cancel.is_cancelled());
+    }
+}
@@ -1,12 +1,14 @@
 mod consent;
 mod factory_reset;
 pub mod feature;
-mod firmware_update;
+pub(crate) mod firmware_update;
+mod job;
 #[cfg(test)]
 #[path = "mod_test.rs"]
 mod mod_test;
 mod modem_info;
 mod network;
+mod outbox;
 mod provisioning_config;
 mod reboot;
 mod ssh_tunnel;
@@ -61,6 +63,8 @@ pub struct Twin {
     waiting_for_reboot: bool,
     cancel: CancellationToken,
     fs_watcher_handle: Option<JoinHandle<()>>,
+    outbox: outbox::Outbox,
+    jobs: job::Jobs,
 }
 
 impl Twin {
@@ -109,7 +113,7 @@ impl Twin {
             ),
             (
                 TypeId::of::<reboot::Reboot>(),
-                DynFeature::new_box(reboot::Reboot::default()),
+                DynFeature::new_box(reboot::Reboot::new()),
             ),
             (
                 TypeId::of::<ssh_tunnel::SshTunnel>(),
@@ -136,6 +140,8 @@ impl Twin {
             waiting_for_reboot: false,
             cancel,
             fs_watcher_handle,
+            outbox: outbox::Outbox::new(),
+            jobs: job::Jobs::default(),
         };
 
         twin.connect_web_service().await?;
@@ -157,6 +163,11 @@ impl Twin {
             features.insert(f.name(), value);
         }
 
+        // features owned by a running job are enabled by definition
+        for (name, version) in self.jobs.busy_features() {
+            features.insert(name.clone(), json!({ "version": version }));
+        }
+
         serde_json::Value::Object(features)
     }
 
@@ -177,6 +188,9 @@ impl Twin {
             }
         }
 
+        // features owned by a running job get connected when the job finished
+        self.jobs.defer_connect_twin();
+
         Ok(())
     }
 
@@ -210,6 +224,11 @@ impl Twin {
                     self.connect_twin().await?;
 
                     self.state = TwinState::Authenticated;
+
+                    // unsent items stay in the outbox for the next connect
+                    if let Err(e) = self.flush_outbox() {
+                        error!("couldn't flush outbox: {e:#}");
+                    }
                 }
             }
             AuthenticationStatus::Unauthenticated(reason) => {
@@ -260,7 +279,53 @@ impl Twin {
         Ok(restart_twin)
     }
 
+    fn report(&mut self, reported: serde_json::Value) -> Result<()> {
+        match &self.client {
+            Some(client) if self.state == TwinState::Authenticated => client.twin_report(reported),
+            _ => {
+                debug!("report: iothub not connected, keep reported properties in outbox");
+                self.outbox.push_reported(reported);
+                Ok(())
+            }
+        }
+    }
+
+    fn send_d2c_message(&mut self, message: IotMessage) -> Result<()> {
+        match &self.client {
+            Some(client) if self.state == TwinState::Authenticated => {
+                client.send_d2c_message(message)
+            }
+            _ => {
+                debug!("send_d2c_message: iothub not connected, keep message in outbox");
+                self.outbox.push_message(message);
+                Ok(())
+            }
+        }
+    }
+
+    fn flush_outbox(&mut self) -> Result<()> {
+        if self.outbox.is_empty() {
+            return Ok(());
+        }
+
+        let client = self
+            .client
+            .as_ref()
+            .context("flush_outbox: client not present")?;
+
+        // whatever could not be sent is flushed on the next connect
+        self.outbox.flush(
+            |reported| client.twin_report(reported),
+            |message| client.send_d2c_message(message),
+        )
+    }
+
     async fn handle_request(&mut self, request: CommandRequest) -> Result<()> {
+        if self.jobs.is_busy(&request.command.feature_id()) {
+            self.handle_busy_request(request);
+            return Ok(());
+        }
+
         let cmd = request.command;
         let reply = request.reply;
         let cmd_string = format!("{cmd:?}");
@@ -296,26 +361,167 @@ impl Twin {
 
         info!("handle_request: {}({cmd_string})", feature.name());
 
-        let result = feature.command(&cmd).await;
+        if cmd.is_long_running() {
+            return self.start_job(cmd, reply).await;
+        }
+
+        let result = command_with_timeout(feature, &cmd, self.cancel.child_token()).await;
+
+        Self::log_result(&cmd_string, &result);
 
-        match &result {
+        // e.g. an inhibited reboot fails without rebooting
+        #[cfg(not(feature = "mock"))]
+        if cmd.triggers_reboot() && result.is_ok() {
+            self.waiting_for_reboot = true;
+        }
+
+        if let Some(reply) = reply
+            && reply.send(result).is_err()
+        {
+            error!("handle_request: {cmd_string} receiver dropped");
+        }
+
+        Ok(())
+    }
+
+    fn log_result(cmd_string: &str, result: &CommandResult) {
+        match result {
             Ok(inner_result) => {
                 info!("handle_request: {cmd_string} succeeded with result: {inner_result:?}")
             }
             Err(e) => error!("handle_request: {cmd_string} returned error: {e:#}"),
         }
+    }
 
-        if let Some(reply) = reply
-            && reply.send(result).is_err()
+    // the feature is owned by a job: requests are queued, unless the busy
+    // handler of the feature answers them
+    fn handle_busy_request(&mut self, request: CommandRequest) {
+        if let Some(result) = self.jobs.handle_busy(&request.command) {
+            let cmd_string = format!("{:?}", request.command);
+
+            Self::log_result(&cmd_string, &result);
+
+            if let Some(reply) = request.reply
+                && reply.send(result).is_err()
+            {
+                error!("handle_request: {cmd_string} receiver dropped");
+            }
+            return;
+        }
+
+        debug!("handle_request: feature busy, queue {:?}", request.command);
+
+        if let Err(request) = self.jobs.enqueue(request) {
+            let msg = format!(
+                "handle_request: feature busy and queue full, reject {:?}",
+                request.command
+            );
+            warn!("{msg}");
+            if let Some(reply) = request.reply
+                && reply.send(Err(anyhow!("{msg}"))).is_err()
+            {
+                error!("handle_request: receiver dropped");
+            }
+        }
+    }
+
+    // Requests with a reply channel immediately get the job id as result. The
+    // outcome is reported via the job status.
+    async fn start_job(
+        &mut self,
+        cmd: Command,
+        reply: Option<tokio::sync::oneshot::Sender<CommandResult>>,
+    ) -> Result<()> {
+        let feature = self
+            .features
+            .remove(&cmd.feature_id())
+            .context("start_job: failed to get feature")?;
+        let cmd_string = format!("{cmd:?}");
+
+        if let Some(report) =
+            self.jobs
+                .start(feature, cmd, reply.is_some(), self.cancel.child_token())
         {
-            error!("handle_request: {cmd_string} receiver dropped");
+            info!("start_job: {cmd_string} started as job {}", report.id);
+
+            if let Some(reply) = reply
+                && reply.send(Ok(Some(json!({"job_id": report.id})))).is_err()
+            {
+                error!("start_job: {cmd_string} receiver dropped");
+            }
+
+            self.report_job(&report).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_job_finished(&mut self, finished: job::JobFinished) -> Result<()> {
+        let job::JobFinished {
+            feature,
+            command,
+            report,
+            result,
+        } = finished;
+        let feature_id = command.feature_id();
+        let cmd_string = format!("{command:?}");
+
+        Self::log_result(&cmd_string, &result);
+
+        let connect_twin = self.jobs.finish(&feature_id);
+        self.features.insert(feature_id, feature);
+
+        if connect_twin {
+            let feature = self
+                .features
+                .get_mut(&feature_id)
+                .context("handle_job_finished: failed to get feature mutable")?;
+
+            feature
+                .connect_twin(
+                    self.tx_reported_properties.clone(),
+                    self.tx_outgoing_message.clone(),
+                )
+                .await?;
+        }
+
+        if let Some(mut report) = report {
+            report.finish(&result);
+            self.report_job(&report).await?;
         }
 
         #[cfg(not(feature = "mock"))]
-        if cmd.triggers_reboot() {
+        if command.triggers_reboot() && result.is_ok() {
             self.waiting_for_reboot = true;
         }
 
+        // handle requests queued while the feature was busy, until the next
+        // long-running command takes the feature again
+        while !self.waiting_for_reboot
+            && !self.jobs.is_busy(&feature_id)
+            && let Some(request) = self.jobs.dequeue(&feature_id)
+        {
+            self.handle_request(request).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn report_job(&mut self, report: &job::JobReport) -> Result<()> {
+        // results might be large, so they are only published to the web service
+        self.report(json!({
+            report.feature.clone(): {
+                "job": {
+                    "id": report.id,
+                    "command": report.command,
+                    "status": report.status,
+                    "error": report.error,
+                }
+            }
+        }))?;
+
+        web_service::publish(web_service::PublishChannel::JobStatusV1, json!(report)).await;
+
         Ok(())
     }
 
@@ -352,21 +558,27 @@ impl Twin {
 
         self.cancel.cancel();
 
-        if let Some(client) = self.client.as_mut() {
-            // report remaining properties
-            while let Ok(reported) = rx_reported_properties.try_recv() {
-                client
-                    .twin_report(reported)
-                    .unwrap_or_else(|e| error!("couldn't report while shutting down: {e:#}"));
-            }
+        // if connected, flush what was buffered before; otherwise remaining
+        // properties and messages end up in the outbox (and get persisted if
+        // configured)
+        if self.state == TwinState::Authenticated {
+            self.flush_outbox()
+                .unwrap_or_else(|e| error!("couldn't flush outbox while shutting down: {e:#}"));
+        }
 
-            // send remaining messages
-            while let Ok(message) = rx_outgoing_message.try_recv() {
-                client
-                    .send_d2c_message(message)
-                    .unwrap_or_else(|e| error!("couldn't send while shutting down: {e:#}"));
-            }
+        // report remaining properties
+        while let Ok(reported) = rx_reported_properties.try_recv() {
+            self.report(reported)
+                .unwrap_or_else(|e| error!("couldn't report while shutting down: {e:#}"));
+        }
+
+        // send remaining messages
+        while let Ok(message) = rx_outgoing_message.try_recv() {
+            self.send_d2c_message(message)
+                .unwrap_or_else(|e| error!("couldn't send while shutting down: {e:#}"));
+        }
 
+        if let Some(client) = self.client.as_mut() {
             client.shutdown(Duration::from_secs(5)).await;
             self.client = None;
         }
@@ -520,21 +732,14 @@ impl Twin {
                 requests = command_requests.select_next_some(), if !twin.waiting_for_reboot => {
                     twin.handle_request(requests).await?
                 },
+                Some(finished) = twin.jobs.running.next() => {
+                    twin.handle_job_finished(finished).await?
+                },
                 Some(reported) = rx_reported_properties.recv() => {
-                    let Some(client) = &twin.client else {
-                        error!("couldn't report properties since client not present");
-                        continue
-                    };
-
-                    client.twin_report(reported)?
+                    twin.report(reported)?
                 },
                 Some(message) = rx_outgoing_message.recv() => {
-                    let Some(client) = &twin.client else {
-                        error!("couldn't send msg since client not present");
-                        continue
-                    };
-
-                    client.send_d2c_message(message)?
+                    twin.send_d2c_message(message)?
                 },
             );
         }
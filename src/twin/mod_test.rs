@@ -218,6 +218,26 @@ pub mod mod_test {
                 "REBOOT_REASON_DIR_PATH",
                 "testfiles/positive/reboot_reason",
             );
+            crate::common::set_env_var(
+                "UPDATE_HISTORY_PATH",
+                format!("{}/update_history.json", test_env.dirpath()),
+            );
+            crate::common::set_env_var(
+                "UPDATE_SCHEDULE_PATH",
+                format!("{}/update_schedule.json", test_env.dirpath()),
+            );
+            crate::common::set_env_var(
+                "PENDING_REBOOT_PATH",
+                format!("{}/pending_reboot.json", test_env.dirpath()),
+            );
+            crate::common::set_env_var(
+                "REBOOT_SAFE_MODE_PATH",
+                format!("{}/reboot_safe_mode.json", test_env.dirpath()),
+            );
+            crate::common::set_env_var(
+                "REBOOT_REASON_HISTORY_SENT_PATH",
+                format!("{}/reboot_reason_history_sent", test_env.dirpath()),
+            );
 
             env_vars
                 .iter()
@@ -311,7 +331,7 @@ pub mod mod_test {
                 .with(eq(json!({
                     "device_update_consent": {"version": 1},
                     "factory_reset": {"version": 3},
-                    "firmware_update": {"version": 1},
+                    "firmware_update": {"version": 2},
                     "modem_info": null,
                     "network_status": {"version": 3},
                     "provisioning_config": {"version": 1},
@@ -323,6 +343,27 @@ pub mod mod_test {
                 .times(2)
                 .returning(|_| Ok(()));
 
+            mock.expect_twin_report()
+                .with(eq(json!({"firmware_update": {"history": []}})))
+                .times(2)
+                .returning(|_| Ok(()));
+
+            mock.expect_twin_report()
+                .with(eq(json!({"firmware_update": {"scheduled_run": null}})))
+                .times(2)
+                .returning(|_| Ok(()));
+
+            mock.expect_twin_report()
+                .with(eq(json!({"firmware_update": {"validation": {
+                    "status": "NoUpdate",
+                    "restart_count": null,
+                    "deadline": null,
+                    "hold": false,
+                    "failure_reason": null
+                }}})))
+                .times(2)
+                .returning(|_| Ok(()));
+
             mock.expect_twin_report()
                 .with(eq(json!({
                 "system_info":{
@@ -337,6 +378,23 @@ pub mod mod_test {
                         "current_boot_id": "56e51a56-f85f-4abd-95bb-fc2335d9b696",
                         "reason": "power-loss"
                     },
+                    "reboot_reason_history": {
+                        "boots": [{
+                            "boot_id": "56e51a56-f85f-4abd-95bb-fc2335d9b696",
+                            "reason": "power-loss",
+                            "extra_info": "",
+                            "os_version": "4.0.25.603211037",
+                            "boot_time": "2025-04-03T17:42:53Z",
+                            "reason_time": null
+                        }],
+                        "counters": {
+                            "power_loss": 1,
+                            "watchdog": 0,
+                            "swupdate": 0,
+                            "factory_reset": 0,
+                            "other": 0
+                        }
+                    },
                     "hostname": "my-hostname"
                 }})))
                 .times(2)
@@ -392,6 +450,11 @@ pub mod mod_test {
                 .times(2)
                 .returning(|_| Ok(()));
 
+            mock.expect_twin_report()
+                .with(eq(json!({"reboot": {"pending": null, "safe_mode": null}})))
+                .times(2)
+                .returning(|_| Ok(()));
+
             mock.expect_twin_report()
                 .with(eq(
                     json!({"device_update_consent":{"general_consent":["swupdate"], "reset_consent_on_fail": false}}),
@@ -462,7 +525,7 @@ pub mod mod_test {
                 .with(eq(json!({
                     "device_update_consent": {"version": 1},
                     "factory_reset": null,
-                    "firmware_update": {"version": 1},
+                    "firmware_update": {"version": 2},
                     "modem_info": null,
                     "network_status": {"version": 3},
                     "provisioning_config": {"version": 1},
@@ -474,6 +537,27 @@ pub mod mod_test {
                 .times(1)
                 .returning(|_| Ok(()));
 
+            mock.expect_twin_report()
+                .with(eq(json!({"firmware_update": {"history": []}})))
+                .times(1)
+                .returning(|_| Ok(()));
+
+            mock.expect_twin_report()
+                .with(eq(json!({"firmware_update": {"scheduled_run": null}})))
+                .times(1)
+                .returning(|_| Ok(()));
+
+            mock.expect_twin_report()
+                .with(eq(json!({"firmware_update": {"validation": {
+                    "status": "NoUpdate",
+                    "restart_count": null,
+                    "deadline": null,
+                    "hold": false,
+                    "failure_reason": null
+                }}})))
+                .times(1)
+                .returning(|_| Ok(()));
+
             let s = IotHubClient::sdk_version_string();
             mock.expect_twin_report()
                 .with(eq(json!({"system_info":{
@@ -488,6 +572,23 @@ pub mod mod_test {
                         "current_boot_id": "56e51a56-f85f-4abd-95bb-fc2335d9b696",
                         "reason": "power-loss"
                     },
+                    "reboot_reason_history": {
+                        "boots": [{
+                            "boot_id": "56e51a56-f85f-4abd-95bb-fc2335d9b696",
+                            "reason": "power-loss",
+                            "extra_info": "",
+                            "os_version": "4.0.25.603211037",
+                            "boot_time": "2025-04-03T17:42:53Z",
+                            "reason_time": null
+                        }],
+                        "counters": {
+                            "power_loss": 1,
+                            "watchdog": 0,
+                            "swupdate": 0,
+                            "factory_reset": 0,
+                            "other": 0
+                        }
+                    },
                     "hostname": "my-hostname"
                 }})))
                 .times(1)
@@ -543,6 +644,11 @@ pub mod mod_test {
                 .times(1)
                 .returning(|_| Ok(()));
 
+            mock.expect_twin_report()
+                .with(eq(json!({"reboot": {"pending": null, "safe_mode": null}})))
+                .times(1)
+                .returning(|_| Ok(()));
+
             mock.expect_twin_report()
                 .with(eq(
                     json!({"device_update_consent":{"general_consent":["swupdate"], "reset_consent_on_fail": false}}),
@@ -636,6 +742,7 @@ pub mod mod_test {
                         command: Command::FactoryReset(factory_reset::FactoryResetCommand {
                             mode: factory_reset::FactoryResetMode::Mode1,
                             preserve: vec![],
+                            force: false,
                         }),
                         reply: Some(tx),
                     })
@@ -656,6 +763,73 @@ pub mod mod_test {
         TestCase::run(test_files, vec![], env_vars, expect, test);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn long_running_command_runs_as_job_test() {
+        let test_files = vec![
+            "testfiles/positive/os-release",
+            "testfiles/positive/consent_conf.json",
+            "testfiles/positive/request_consent.json",
+            "testfiles/positive/history_consent.json",
+        ];
+
+        let expect = |mock: &mut MockMyIotHub| {
+            mock.expect_twin_report().returning(|_| Ok(()));
+        };
+
+        let test = |test_attr: &mut TestConfig| {
+            let feature_id = TypeId::of::<firmware_update::FirmwareUpdate>();
+            let load = || {
+                Command::LoadFirmwareUpdate(firmware_update::LoadUpdateCommand {
+                    update_file_path: PathBuf::from("/nonexistent/update.tar"),
+                    version_options: firmware_update::VersionOptions::default(),
+                })
+            };
+
+            // caller immediately gets the job id while the feature is busy
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            assert!(
+                block_on(async {
+                    test_attr
+                        .twin
+                        .handle_request(CommandRequest {
+                            command: load(),
+                            reply: Some(tx),
+                        })
+                        .await
+                })
+                .is_ok()
+            );
+            assert_eq!(block_on(rx).unwrap().unwrap(), Some(json!({"job_id": 1})));
+            assert!(test_attr.twin.jobs.is_busy(&feature_id));
+
+            // requests for a busy feature are queued
+            let (tx, mut rx) = tokio::sync::oneshot::channel();
+            assert!(
+                block_on(async {
+                    test_attr
+                        .twin
+                        .handle_request(CommandRequest {
+                            command: load(),
+                            reply: Some(tx),
+                        })
+                        .await
+                })
+                .is_ok()
+            );
+            assert!(rx.try_recv().is_err());
+
+            let finished = block_on(test_attr.twin.jobs.running.next()).unwrap();
+            assert!(finished.result.is_err());
+            assert!(block_on(async { test_attr.twin.handle_job_finished(finished).await }).is_ok());
+
+            // the queued request was started as next job
+            assert_eq!(block_on(rx).unwrap().unwrap(), Some(json!({"job_id": 2})));
+            assert!(test_attr.twin.jobs.is_busy(&feature_id));
+        };
+
+        TestCase::run(test_files, vec![], vec![], expect, test);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn fs_watcher_exit_bails_and_shuts_down_test() {
         let test_files = vec![
@@ -792,7 +966,7 @@ pub mod mod_test {
         ];
 
         let expect = |mock: &mut MockMyIotHub| {
-            mock.expect_twin_report().times(10).returning(|_| Ok(()));
+            mock.expect_twin_report().times(14).returning(|_| Ok(()));
 
             mock.expect_twin_report()
                 .with(eq(json!({
@@ -837,11 +1011,14 @@ pub mod mod_test {
                         .features
                         .get_mut(&TypeId::of::<consent::DeviceUpdateConsent>())
                         .expect("consent feature present")
-                        .command(&Command::FsEvent(FsEventCommand {
-                            kind: FsEventKind::FileModified,
-                            feature_id: TypeId::of::<consent::DeviceUpdateConsent>(),
-                            path: history_path,
-                        }))
+                        .command(
+                            &Command::FsEvent(FsEventCommand {
+                                kind: FsEventKind::FileModified,
+                                feature_id: TypeId::of::<consent::DeviceUpdateConsent>(),
+                                path: history_path,
+                            }),
+                            CancellationToken::new(),
+                        )
                         .await
                 })
                 .is_ok()
@@ -863,7 +1040,7 @@ pub mod mod_test {
         let test_dirs = vec!["testfiles/positive/test_component"];
 
         let expect = |mock: &mut MockMyIotHub| {
-            mock.expect_twin_report().times(10).returning(|_| Ok(()));
+            mock.expect_twin_report().times(14).returning(|_| Ok(()));
         };
 
         let test = |test_attr: &mut TestConfig| {
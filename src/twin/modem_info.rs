@@ -378,7 +378,7 @@ impl Feature for ModemInfo {
         }
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         let Command::Tick(_) = cmd else {
             bail!("unexpected event: {cmd:?}")
         };
@@ -105,7 +105,7 @@ impl Feature for Network {
         }
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         match cmd {
             Command::Tick(_) => self.report(false).await?,
             Command::ReloadNetwork => {
@@ -114,7 +114,8 @@ impl Feature for Network {
                     unit::UnitAction::Reload,
                     systemd_zbus::Mode::Fail,
                 )
-                .await?;
+                .await
+                .context("failed to reload network")?;
             }
             _ => bail!("unexpected command"),
         }
@@ -0,0 +1,431 @@
+use crate::common::{from_json_file, merge_patch, to_json_file};
+use anyhow::{Context, Result};
+use azure_iot_sdk::client::IotMessage;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    path::{Path, PathBuf},
+};
+
+static OUTBOX_MAX_MESSAGES_DEFAULT: usize = 100;
+
+macro_rules! outbox_file_path {
+    () => {
+        env::var("OUTBOX_FILE_PATH")
+            .unwrap_or("/var/lib/omnect-device-service/outbox.json".to_string())
+    };
+}
+
+/// Serializable form of an `IotMessage`, so that buffered D2C messages
+/// survive a service restart if persistence is enabled.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct OutboxMessage {
+    body: Vec<u8>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    properties: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_encoding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_queue: Option<String>,
+}
+
+impl From<IotMessage> for OutboxMessage {
+    fn from(msg: IotMessage) -> Self {
+        OutboxMessage {
+            body: msg.body,
+            properties: msg.properties,
+            content_type: msg.system_properties.content_type,
+            content_encoding: msg.system_properties.content_encoding,
+            output_queue: msg.system_properties.output_queue,
+        }
+    }
+}
+
+impl OutboxMessage {
+    fn into_iot_message(self) -> Result<IotMessage> {
+        let mut builder = IotMessage::builder().set_body(self.body);
+
+        if let Some(content_type) = &self.content_type {
+            builder = builder.set_content_type(content_type);
+        }
+        if let Some(content_encoding) = &self.content_encoding {
+            builder = builder.set_content_encoding(content_encoding);
+        }
+        if let Some(output_queue) = &self.output_queue {
+            builder = builder.set_output_queue(output_queue);
+        }
+
+        let mut msg = builder
+            .build()
+            .context("into_iot_message: failed to build message")?;
+        msg.properties = self.properties;
+
+        Ok(msg)
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct OutboxContent {
+    #[serde(default)]
+    reported: VecDeque<serde_json::Value>,
+    messages: VecDeque<OutboxMessage>,
+}
+
+/// Holds reported properties and D2C messages while there is no authenticated
+/// iothub connection.
+///
+/// Reported property patches are merged, so only the latest value per key is
+/// kept. A patch that can't be merged without changing its meaning, e.g. an
+/// object for a key that was deleted before, is kept as separate patch. D2C
+/// messages are kept in order and bounded by `OUTBOX_MAX_MESSAGES`;
+/// if the bound is hit the oldest message is dropped. If `PERSIST_OUTBOX` is
+/// set to "true" the content is mirrored to `OUTBOX_FILE_PATH` and restored on
+/// startup.
+pub(crate) struct Outbox {
+    content: OutboxContent,
+    max_messages: usize,
+    file_path: Option<PathBuf>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        let max_messages = match env::var("OUTBOX_MAX_MESSAGES") {
+            Ok(max) => max.parse::<usize>().unwrap_or_else(|_| {
+                error!(
+                    "ignore invalid OUTBOX_MAX_MESSAGES {max} and use default {OUTBOX_MAX_MESSAGES_DEFAULT}"
+                );
+                OUTBOX_MAX_MESSAGES_DEFAULT
+            }),
+            Err(_) => OUTBOX_MAX_MESSAGES_DEFAULT,
+        };
+
+        let file_path = (env::var("PERSIST_OUTBOX")
+            .unwrap_or("false".to_string())
+            .to_lowercase()
+            == "true")
+            .then(|| PathBuf::from(outbox_file_path!()));
+
+        let content = match &file_path {
+            Some(path) if matches!(path.try_exists(), Ok(true)) => Self::restore(path)
+                .unwrap_or_else(|e| {
+                    error!("outbox: failed to restore, start with empty outbox: {e:#}");
+                    OutboxContent::default()
+                }),
+            _ => OutboxContent::default(),
+        };
+
+        let mut outbox = Outbox {
+            content,
+            max_messages,
+            file_path,
+        };
+
+        // the bound might have changed since the content was persisted
+        outbox.truncate();
+
+        outbox
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.reported.is_empty() && self.content.messages.is_empty()
+    }
+
+    pub fn push_reported(&mut self, patch: serde_json::Value) {
+        debug!("outbox: buffer reported properties");
+
+        match self.content.reported.back_mut() {
+            Some(reported) if mergeable(reported, &patch) => merge_patch(reported, &patch, true),
+            _ => self.content.reported.push_back(patch),
+        }
+
+        self.persist();
+    }
+
+    pub fn push_message(&mut self, msg: IotMessage) {
+        debug!("outbox: buffer d2c message");
+
+        self.content.messages.push_back(msg.into());
+        self.truncate();
+        self.persist();
+    }
+
+    /// Sends the buffered reported properties and afterwards the buffered
+    /// messages, both in the order they were pushed. Whatever could not be sent
+    /// stays in the outbox.
+    pub fn flush(
+        &mut self,
+        mut report: impl FnMut(serde_json::Value) -> Result<()>,
+        mut send: impl FnMut(IotMessage) -> Result<()>,
+    ) -> Result<()> {
+        info!(
+            "outbox: flush {} reported properties patch(es) and {} message(s)",
+            self.content.reported.len(),
+            self.content.messages.len()
+        );
+
+        let result = self.send_all(&mut report, &mut send);
+
+        self.persist();
+
+        result
+    }
+
+    fn send_all(
+        &mut self,
+        report: &mut impl FnMut(serde_json::Value) -> Result<()>,
+        send: &mut impl FnMut(IotMessage) -> Result<()>,
+    ) -> Result<()> {
+        while let Some(reported) = self.content.reported.front() {
+            report(reported.clone())?;
+            self.content.reported.pop_front();
+        }
+
+        while let Some(msg) = self.content.messages.front() {
+            match msg.clone().into_iot_message() {
+                Ok(msg) => send(msg)?,
+                Err(e) => error!("outbox: drop message: {e:#}"),
+            }
+            self.content.messages.pop_front();
+        }
+
+        Ok(())
+    }
+
+    fn truncate(&mut self) {
+        while self.content.messages.len() > self.max_messages {
+            self.content.messages.pop_front();
+            warn!(
+                "outbox: drop oldest message since limit of {} messages is reached",
+                self.max_messages
+            );
+        }
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.file_path else {
+            return;
+        };
+
+        if self.is_empty() {
+            if let Err(e) = std::fs::remove_file(path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                error!("outbox: failed to remove {path:?}: {e:#}");
+            }
+        } else if let Err(e) = to_json_file(&self.content, path, true) {
+            error!("outbox: failed to persist: {e:#}");
+        }
+    }
+
+    fn restore(path: &Path) -> Result<OutboxContent> {
+        let content: OutboxContent = from_json_file(path)?;
+
+        info!(
+            "outbox: restored {} reported properties patch(es) and {} message(s)",
+            content.reported.len(),
+            content.messages.len()
+        );
+
+        Ok(content)
+    }
+}
+
+/// A patch can be merged into a former one unless it sets an object for a key
+/// the former one sets to a non-object value. Applied one after the other the
+/// object replaces e.g. a deleted (null) key, while the merged object would be
+/// merged into the value the key had before.
+fn mergeable(target: &serde_json::Value, patch: &serde_json::Value) -> bool {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => patch
+            .iter()
+            .all(|(k, v)| target.get(k).is_none_or(|t| mergeable(t, v))),
+        (_, serde_json::Value::Object(_)) => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(body: &str) -> IotMessage {
+        IotMessage::builder()
+            .set_body(body.as_bytes().to_vec())
+            .set_content_type("application/json")
+            .set_content_encoding("utf-8")
+            .build()
+            .unwrap()
+    }
+
+    fn outbox(max_messages: usize, file_path: Option<PathBuf>) -> Outbox {
+        Outbox {
+            content: OutboxContent::default(),
+            max_messages,
+            file_path,
+        }
+    }
+
+    fn flush(outbox: &mut Outbox) -> (Vec<serde_json::Value>, Vec<IotMessage>) {
+        let mut reported = vec![];
+        let mut messages = vec![];
+
+        outbox
+            .flush(
+                |patch| {
+                    reported.push(patch);
+                    Ok(())
+                },
+                |msg| {
+                    messages.push(msg);
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        (reported, messages)
+    }
+
+    #[test]
+    fn merge_reported_keeps_latest_value_per_key() {
+        let mut outbox = outbox(10, None);
+
+        assert!(outbox.is_empty());
+
+        outbox.push_reported(json!({"reboot": {"wait_online_timeout_secs": 1}, "a": {"c": 1}}));
+        outbox.push_reported(json!({"reboot": {"pending": null}, "a": {"b": 2}}));
+        outbox.push_reported(json!({"reboot": {"wait_online_timeout_secs": 3}, "d": 4}));
+
+        assert!(!outbox.is_empty());
+
+        let (reported, messages) = flush(&mut outbox);
+
+        assert_eq!(
+            reported,
+            vec![json!({
+                "reboot": {"wait_online_timeout_secs": 3, "pending": null},
+                "a": {"b": 2, "c": 1},
+                "d": 4
+            })]
+        );
+        assert!(messages.is_empty());
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn merge_reported_keeps_deletions() {
+        let mut outbox = outbox(10, None);
+
+        outbox.push_reported(json!({"a": null, "b": 1}));
+        outbox.push_reported(json!({"a": {"b": 2}, "b": 2}));
+        outbox.push_reported(json!({"a": {"c": null}, "e": 1}));
+        outbox.push_reported(json!({"e": {"f": 1}}));
+
+        // an object must not be merged into a deleted key or a value, since
+        // the twin would merge it with the former value of the key
+        let (reported, _) = flush(&mut outbox);
+
+        assert_eq!(
+            reported,
+            vec![
+                json!({"a": null, "b": 1}),
+                json!({"a": {"b": 2, "c": null}, "b": 2, "e": 1}),
+                json!({"e": {"f": 1}}),
+            ]
+        );
+    }
+
+    #[test]
+    fn flush_keeps_what_was_not_sent() {
+        let mut outbox = outbox(10, None);
+
+        outbox.push_reported(json!({"a": null}));
+        outbox.push_reported(json!({"a": {"b": 1}}));
+        outbox.push_message(message("1"));
+        outbox.push_message(message("2"));
+
+        let mut reported = 0;
+        assert!(
+            outbox
+                .flush(
+                    |_| {
+                        reported += 1;
+                        anyhow::ensure!(reported == 1, "not connected");
+                        Ok(())
+                    },
+                    |_| Ok(()),
+                )
+                .is_err()
+        );
+        assert_eq!(outbox.content.reported, vec![json!({"a": {"b": 1}})]);
+        assert_eq!(outbox.content.messages.len(), 2);
+
+        let mut sent = 0;
+        assert!(
+            outbox
+                .flush(
+                    |_| Ok(()),
+                    |_| {
+                        sent += 1;
+                        anyhow::ensure!(sent == 1, "not connected");
+                        Ok(())
+                    },
+                )
+                .is_err()
+        );
+        assert!(outbox.content.reported.is_empty());
+        assert_eq!(outbox.content.messages.len(), 1);
+        assert_eq!(outbox.content.messages[0].body, b"2".to_vec());
+
+        let (reported, messages) = flush(&mut outbox);
+        assert!(reported.is_empty());
+        assert_eq!(messages.len(), 1);
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn messages_are_bounded_and_ordered() {
+        let mut outbox = outbox(2, None);
+
+        outbox.push_message(message("1"));
+        outbox.push_message(message("2"));
+        outbox.push_message(message("3"));
+
+        let bodies: Vec<Vec<u8>> = outbox
+            .content
+            .messages
+            .iter()
+            .map(|m| m.body.clone())
+            .collect();
+
+        assert_eq!(bodies, vec![b"2".to_vec(), b"3".to_vec()]);
+
+        let (reported, messages) = flush(&mut outbox);
+
+        assert!(reported.is_empty());
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[test]
+    fn persist_and_restore() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file_path = tmp_dir.path().join("outbox.json");
+
+        let mut outbox = outbox(10, Some(file_path.clone()));
+        outbox.push_reported(json!({"a": 1}));
+        outbox.push_message(message("1"));
+
+        assert!(file_path.exists());
+
+        let restored = Outbox::restore(&file_path).unwrap();
+        assert_eq!(restored.reported, vec![json!({"a": 1})]);
+        assert_eq!(restored.messages, outbox.content.messages);
+
+        let _ = flush(&mut outbox);
+        assert!(!file_path.exists());
+    }
+}
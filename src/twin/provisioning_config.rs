@@ -155,7 +155,7 @@ impl Feature for ProvisioningConfig {
         }
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         let Command::Tick(_) = cmd else {
             bail!("unexpected event: {cmd:?}")
         };
@@ -338,9 +338,12 @@ mod tests {
         crate::common::set_env_var("EST_CERT_FILE_PATH", "testfiles/positive/deviceid2-*.cer");
 
         config
-            .command(&Command::Tick(TickCommand {
-                feature_id: TypeId::of::<ProvisioningConfig>(),
-            }))
+            .command(
+                &Command::Tick(TickCommand {
+                    feature_id: TypeId::of::<ProvisioningConfig>(),
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
         let Method::X509(est2) = config.method.clone() else {
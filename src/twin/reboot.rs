@@ -1,24 +1,223 @@
 use crate::{
-    systemd,
+    common::{from_json_file, to_json_file},
+    reboot_guard::{self, SafeMode},
+    reboot_inhibitor,
+    systemd::{
+        self,
+        unit::{JobResult, Mode, UnitAction},
+    },
     twin::{Feature, feature::*},
     web_service,
 };
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, bail, ensure};
 use azure_iot_sdk::client::IotMessage;
-use log::{debug, info};
-use serde::Deserialize;
+use futures::{StreamExt, stream};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{env, time::Duration};
-use tokio::sync::mpsc::Sender;
+use std::{env, fs, path::Path, time::Duration};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::{
+    select,
+    sync::{mpsc::Sender, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+macro_rules! pending_reboot_file_path {
+    () => {
+        env::var("PENDING_REBOOT_PATH")
+            .unwrap_or("/var/lib/omnect-device-service/pending_reboot.json".to_string())
+    };
+}
+
+macro_rules! restart_units_file_path {
+    () => {
+        env::var("RESTART_UNITS_PATH").unwrap_or("/etc/omnect/restart-units.json".to_string())
+    };
+}
+
+static REBOOT_COUNTDOWN_INTERVAL: Duration = Duration::from_secs(1);
+// the countdown is published every second only during the last minute
+static REBOOT_COUNTDOWN_FINAL_SECS: i64 = 60;
+
+/// Reboots immediately, or deferred if `at` (RFC 3339) or `delay_secs` is set.
+/// `force` ignores reboot inhibitors, `replace` replaces a pending reboot.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct RebootCommand {
+    pub at: Option<String>,
+    pub delay_secs: Option<u64>,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub replace: bool,
+}
+
+impl RebootCommand {
+    pub fn is_deferred(&self) -> bool {
+        self.at.is_some() || self.delay_secs.is_some()
+    }
+}
+
+/// `force` ignores reboot inhibitors, which also apply to a poweroff.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct PoweroffCommand {
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RestartUnitCommand {
+    pub unit: String,
+}
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct SetWaitOnlineTimeoutCommand {
     pub timeout_secs: Option<u64>,
 }
 
-#[derive(Default)]
+/// Deferred reboot, persisted to survive service restarts and reboots in the
+/// meantime.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct PendingReboot {
+    at: String,
+    force: bool,
+}
+
+impl PendingReboot {
+    fn new(cmd: &RebootCommand, now: OffsetDateTime) -> Result<Self> {
+        let at = match (&cmd.at, cmd.delay_secs) {
+            (Some(at), None) => {
+                OffsetDateTime::parse(at, &Rfc3339).context(format!("invalid reboot time {at}"))?
+            }
+            (None, Some(0)) => bail!("delay_secs must be greater than 0"),
+            (None, Some(secs)) => now + Duration::from_secs(secs),
+            (Some(_), Some(_)) => bail!("at and delay_secs must not be set both"),
+            (None, None) => bail!("either at or delay_secs must be set"),
+        };
+
+        // an immediate reboot has to be requested without at and delay_secs
+        ensure!(at > now, "reboot time {at} is not in the future");
+
+        Ok(PendingReboot {
+            at: at
+                .format(&Rfc3339)
+                .context("failed to format reboot time")?,
+            force: cmd.force,
+        })
+    }
+
+    fn load() -> Option<Self> {
+        let path = pending_reboot_file_path!();
+
+        if !Path::new(&path).exists() {
+            return None;
+        }
+
+        from_json_file(&path)
+            .and_then(|pending: Self| pending.at().map(|_| pending))
+            .inspect_err(|e| error!("ignore invalid pending reboot: {e:#}"))
+            .ok()
+    }
+
+    fn save(&self) -> Result<()> {
+        to_json_file(self, pending_reboot_file_path!(), true)
+    }
+
+    fn remove() -> Result<()> {
+        let path = pending_reboot_file_path!();
+
+        if Path::new(&path).exists() {
+            fs::remove_file(&path).context(format!("failed to remove {path}"))?;
+        }
+
+        Ok(())
+    }
+
+    fn at(&self) -> Result<OffsetDateTime> {
+        OffsetDateTime::parse(&self.at, &Rfc3339)
+            .context(format!("invalid reboot time {}", self.at))
+    }
+
+    fn is_due(&self, now: OffsetDateTime) -> bool {
+        self.at().is_ok_and(|at| at <= now)
+    }
+
+    fn remaining_secs(&self, now: OffsetDateTime) -> i64 {
+        self.at().map_or(0, |at| (at - now).whole_seconds()).max(0)
+    }
+}
+
+/// Scheduled reboot that was refused when it became due, e.g. by a reboot
+/// inhibitor, the rate limit or safe mode. Kept until the next reboot is
+/// scheduled.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct RefusedReboot {
+    at: String,
+    refused_at: String,
+    reason: String,
+}
+
+impl RefusedReboot {
+    fn new(pending: &PendingReboot, reason: &anyhow::Error, now: OffsetDateTime) -> Result<Self> {
+        Ok(RefusedReboot {
+            at: pending.at.clone(),
+            refused_at: now
+                .format(&Rfc3339)
+                .context("failed to format refusal time")?,
+            reason: format!("{reason:#}"),
+        })
+    }
+}
+
+/// Returns whether the countdown is to be published with `remaining_secs`:
+/// once per started minute and every second during the last minute.
+/// `last_minute` is the minute published last.
+fn countdown_due(remaining_secs: i64, last_minute: &mut Option<i64>) -> bool {
+    if remaining_secs <= REBOOT_COUNTDOWN_FINAL_SECS {
+        return true;
+    }
+
+    let minute = (remaining_secs + 59) / 60;
+
+    if *last_minute == Some(minute) {
+        return false;
+    }
+
+    *last_minute = Some(minute);
+    true
+}
+
+/// Units that may be restarted, names or glob patterns. Without config no
+/// unit may be restarted.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+struct RestartUnitsConfig {
+    #[serde(default)]
+    allowed_units: Vec<String>,
+}
+
+impl RestartUnitsConfig {
+    fn load() -> Result<Self> {
+        let path = restart_units_file_path!();
+
+        if !Path::new(&path).exists() {
+            return Ok(Self::default());
+        }
+
+        from_json_file(&path)
+    }
+
+    fn allows(&self, unit: &str) -> bool {
+        self.allowed_units
+            .iter()
+            .any(|pattern| glob::Pattern::new(pattern).is_ok_and(|p| p.matches(unit)))
+    }
+}
+
 pub struct Reboot {
     tx_reported_properties: Option<Sender<serde_json::Value>>,
+    // the persisted pending reboot, loaded once and watched by the countdown
+    pending: watch::Sender<Option<PendingReboot>>,
+    refused: Option<RefusedReboot>,
 }
 
 impl Feature for Reboot {
@@ -41,16 +240,80 @@ impl Feature for Reboot {
     ) -> Result<()> {
         self.tx_reported_properties = Some(tx_reported_properties);
 
-        self.report_wait_online_timeout().await
+        self.report_wait_online_timeout().await?;
+        self.report_state().await
     }
 
     async fn connect_web_service(&self) -> Result<()> {
-        self.report_wait_online_timeout().await
+        self.report_wait_online_timeout().await?;
+        Self::publish_pending(self.pending().as_ref(), self.refused.as_ref()).await;
+        Self::publish_safe_mode(reboot_guard::safe_mode().as_ref()).await;
+        Ok(())
+    }
+
+    fn command_request_stream(&mut self, _cancel: CancellationToken) -> CommandRequestStreamResult {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            stream::unfold(
+                (self.pending.subscribe(), None),
+                |(mut rx, mut last_minute)| async move {
+                    loop {
+                        // no ticks as long as no reboot is pending
+                        let pending = rx.borrow_and_update().clone();
+
+                        let Some(pending) = pending else {
+                            last_minute = None;
+                            rx.changed().await.ok()?;
+                            continue;
+                        };
+
+                        select! {
+                            biased;
+
+                            changed = rx.changed() => {
+                                changed.ok()?;
+                                continue;
+                            }
+                            _ = tokio::time::sleep(REBOOT_COUNTDOWN_INTERVAL) => {}
+                        }
+
+                        let now = OffsetDateTime::now_utc();
+
+                        if pending.is_due(now) {
+                            return Some((
+                                CommandRequest {
+                                    command: Command::RunScheduledReboot,
+                                    reply: None,
+                                },
+                                (rx, last_minute),
+                            ));
+                        }
+
+                        // the countdown enables local UIs to warn users, changes
+                        // of the pending reboot are published immediately anyway.
+                        // scheduling a reboot resets a refused one.
+                        if countdown_due(pending.remaining_secs(now), &mut last_minute) {
+                            Self::publish_pending(Some(&pending), None).await;
+                        }
+                    }
+                },
+            )
+            .boxed(),
+        ))
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         match cmd {
-            Command::Reboot => self.reboot().await,
+            Command::CancelReboot => self.cancel().await,
+            Command::ClearRebootSafeMode => self.clear_safe_mode().await,
+            Command::Poweroff(cmd) => self.poweroff(cmd).await,
+            Command::Reboot(cmd) if cmd.is_deferred() => self.schedule(cmd).await,
+            Command::Reboot(cmd) => self.reboot("initiated by portal or API", cmd.force).await,
+            Command::RestartUnit(cmd) => self.restart_unit(cmd).await,
+            Command::RunScheduledReboot => self.run_scheduled().await,
             Command::SetWaitOnlineTimeout(cmd) => self.set_wait_online_timeout(cmd).await,
             _ => bail!("unexpected command"),
         }
@@ -61,14 +324,168 @@ impl Reboot {
     const REBOOT_VERSION: u8 = 2;
     const ID: &'static str = "reboot";
 
-    async fn reboot(&self) -> CommandResult {
+    pub fn new() -> Self {
+        // a detected reboot loop is reported as soon as twin or web service
+        // are connected
+        reboot_guard::check_reboot_loop();
+
+        Reboot {
+            tx_reported_properties: None,
+            pending: watch::Sender::new(PendingReboot::load()),
+            refused: None,
+        }
+    }
+
+    fn pending(&self) -> Option<PendingReboot> {
+        self.pending.borrow().clone()
+    }
+
+    // persists the pending reboot and updates the countdown
+    fn set_pending(&self, pending: Option<PendingReboot>) -> Result<()> {
+        match &pending {
+            Some(pending) => pending.save()?,
+            None => PendingReboot::remove()?,
+        }
+
+        self.pending.send_replace(pending);
+
+        Ok(())
+    }
+
+    async fn reboot(&self, extra_info: &str, force: bool) -> CommandResult {
         info!("reboot requested");
 
-        systemd::reboot("ods-reboot", "initiated by portal or API").await?;
+        // safe mode and rate limit apply to forced reboots as well
+        reboot_guard::ensure_reboot_allowed()?;
+        reboot_inhibitor::ensure_reboot_allowed(force).await?;
+
+        // a pending reboot is obsolete and must not be run after the reboot
+        self.discard_pending().await?;
+
+        systemd::reboot("ods-reboot", extra_info).await?;
 
         Ok(None)
     }
 
+    async fn poweroff(&self, cmd: &PoweroffCommand) -> CommandResult {
+        info!("poweroff requested");
+
+        reboot_inhibitor::ensure_reboot_allowed(cmd.force).await?;
+
+        // a pending reboot must not be run after the device was powered on
+        self.discard_pending().await?;
+
+        systemd::poweroff("ods-poweroff", "initiated by portal or API").await?;
+
+        Ok(None)
+    }
+
+    async fn restart_unit(&self, cmd: &RestartUnitCommand) -> CommandResult {
+        info!("restart unit requested: {}", cmd.unit);
+
+        if !RestartUnitsConfig::load()?.allows(&cmd.unit) {
+            bail!("restart of unit {} is not allowed", cmd.unit)
+        }
+
+        let result =
+            systemd::unit::unit_action(&cmd.unit, UnitAction::Restart, Mode::Replace).await?;
+
+        if result != JobResult::Done {
+            bail!("restart of unit {} finished with {result:?}", cmd.unit)
+        }
+
+        Ok(None)
+    }
+
+    async fn schedule(&mut self, cmd: &RebootCommand) -> CommandResult {
+        let pending = PendingReboot::new(cmd, OffsetDateTime::now_utc())?;
+
+        if let Some(current) = self.pending()
+            && !cmd.replace
+        {
+            bail!("reboot already pending at {}", current.at)
+        }
+
+        self.set_pending(Some(pending.clone()))?;
+        self.refused = None;
+
+        info!("scheduled reboot: {pending:?}");
+
+        self.report_pending(Some(&pending)).await?;
+        Self::publish_pending(Some(&pending), None).await;
+
+        Ok(Some(json!({"pending": pending})))
+    }
+
+    async fn run_scheduled(&mut self) -> CommandResult {
+        // the reboot might have been cancelled since it was found due. this
+        // must fail, since a successful command is expected to reboot.
+        let Some(pending) = self
+            .pending()
+            .filter(|p| p.is_due(OffsetDateTime::now_utc()))
+        else {
+            bail!("run_scheduled: no scheduled reboot due");
+        };
+
+        info!("run scheduled reboot");
+
+        // an inhibited scheduled reboot must not be repeated
+        self.discard_pending().await?;
+
+        let Err(e) = self
+            .reboot("scheduled by portal or API", pending.force)
+            .await
+        else {
+            return Ok(None);
+        };
+
+        // the operator who scheduled the reboot has to be able to see why it
+        // did not happen
+        match RefusedReboot::new(&pending, &e, OffsetDateTime::now_utc()) {
+            Ok(refused) => {
+                self.refused = Some(refused);
+
+                if let Err(e) = self.report_pending(None).await {
+                    error!("run_scheduled: couldn't report refused reboot: {e:#}");
+                }
+                Self::publish_pending(None, self.refused.as_ref()).await;
+            }
+            Err(e) => error!("run_scheduled: {e:#}"),
+        }
+
+        Err(e)
+    }
+
+    async fn cancel(&self) -> CommandResult {
+        if self.discard_pending().await? {
+            info!("scheduled reboot cancelled");
+        }
+
+        Ok(None)
+    }
+
+    async fn clear_safe_mode(&self) -> CommandResult {
+        if reboot_guard::clear_safe_mode()? {
+            self.report_safe_mode(None).await?;
+            Self::publish_safe_mode(None).await;
+        }
+
+        Ok(None)
+    }
+
+    async fn discard_pending(&self) -> Result<bool> {
+        if self.pending().is_none() {
+            return Ok(false);
+        }
+
+        self.set_pending(None)?;
+
+        self.report_pending(None).await?;
+        Self::publish_pending(None, self.refused.as_ref()).await;
+
+        Ok(true)
+    }
+
     async fn set_wait_online_timeout(&self, cmd: &SetWaitOnlineTimeoutCommand) -> CommandResult {
         info!("set wait_online_timeout requested: {cmd:?}");
 
@@ -81,6 +498,77 @@ impl Reboot {
         Ok(None)
     }
 
+    async fn report_state(&self) -> Result<()> {
+        let Some(tx) = &self.tx_reported_properties else {
+            debug!("report_state: skip since tx_reported_properties is None");
+            return Ok(());
+        };
+
+        tx.send(json!({
+            "reboot": {
+                "pending": self.pending(),
+                "refused": self.refused,
+                "safe_mode": reboot_guard::safe_mode()
+            }
+        }))
+        .await
+        .context("report_state: send")
+    }
+
+    async fn report_safe_mode(&self, safe_mode: Option<&SafeMode>) -> Result<()> {
+        let Some(tx) = &self.tx_reported_properties else {
+            debug!("report_safe_mode: skip since tx_reported_properties is None");
+            return Ok(());
+        };
+
+        tx.send(json!({
+            "reboot": {
+                "safe_mode": safe_mode
+            }
+        }))
+        .await
+        .context("report_safe_mode: send")
+    }
+
+    async fn publish_safe_mode(safe_mode: Option<&SafeMode>) {
+        web_service::publish(
+            web_service::PublishChannel::RebootSafeModeV1,
+            json!({ "safe_mode": safe_mode }),
+        )
+        .await;
+    }
+
+    async fn report_pending(&self, pending: Option<&PendingReboot>) -> Result<()> {
+        let Some(tx) = &self.tx_reported_properties else {
+            debug!("report_pending: skip since tx_reported_properties is None");
+            return Ok(());
+        };
+
+        tx.send(json!({
+            "reboot": {
+                "pending": pending,
+                "refused": self.refused
+            }
+        }))
+        .await
+        .context("report_pending: send")
+    }
+
+    async fn publish_pending(pending: Option<&PendingReboot>, refused: Option<&RefusedReboot>) {
+        web_service::publish(
+            web_service::PublishChannel::PendingRebootV1,
+            json!({
+                "pending": pending.map(|p| json!({
+                    "at": p.at,
+                    "force": p.force,
+                    "remaining_secs": p.remaining_secs(OffsetDateTime::now_utc())
+                })),
+                "refused": refused
+            }),
+        )
+        .await;
+    }
+
     async fn report_wait_online_timeout(&self) -> Result<()> {
         let timeout = systemd::networkd::networkd_wait_online_timeout()?;
 
@@ -105,3 +593,144 @@ impl Reboot {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(s, &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn restart_units_config_allows() {
+        let config: RestartUnitsConfig = serde_json::from_value(json!({
+            "allowed_units": ["docker.service", "my-app@*.service"]
+        }))
+        .unwrap();
+
+        assert!(config.allows("docker.service"));
+        assert!(config.allows("my-app@1.service"));
+        assert!(!config.allows("my-app.service"));
+        assert!(!config.allows("sshd.service"));
+        assert!(!RestartUnitsConfig::default().allows("docker.service"));
+    }
+
+    #[test]
+    fn refused_reboot_from_pending() {
+        let pending = PendingReboot {
+            at: "2026-10-16T12:00:00Z".to_string(),
+            force: false,
+        };
+
+        let refused = RefusedReboot::new(
+            &pending,
+            &anyhow::anyhow!("reboot inhibited by my-app (writing data)"),
+            utc("2026-10-16T12:00:01Z"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(refused).unwrap(),
+            json!({
+                "at": "2026-10-16T12:00:00Z",
+                "refused_at": "2026-10-16T12:00:01Z",
+                "reason": "reboot inhibited by my-app (writing data)"
+            })
+        );
+    }
+
+    #[test]
+    fn countdown_published_every_minute_then_every_second() {
+        let mut last_minute = None;
+
+        assert!(countdown_due(600, &mut last_minute));
+        assert!(!countdown_due(599, &mut last_minute));
+        assert!(!countdown_due(541, &mut last_minute));
+        assert!(countdown_due(540, &mut last_minute));
+        assert!(!countdown_due(481, &mut last_minute));
+        assert!(countdown_due(120, &mut last_minute));
+        assert!(!countdown_due(61, &mut last_minute));
+        assert!(countdown_due(60, &mut last_minute));
+        assert!(countdown_due(59, &mut last_minute));
+        assert!(countdown_due(1, &mut last_minute));
+    }
+
+    #[test]
+    fn pending_reboot_from_command() {
+        let now = utc("2026-10-16T12:00:00Z");
+
+        let pending = PendingReboot::new(
+            &RebootCommand {
+                delay_secs: Some(90),
+                ..Default::default()
+            },
+            now,
+        )
+        .unwrap();
+        assert_eq!(pending.at, "2026-10-16T12:01:30Z");
+        assert!(!pending.force);
+        assert_eq!(pending.remaining_secs(now), 90);
+        assert!(!pending.is_due(now));
+        assert!(pending.is_due(utc("2026-10-16T12:01:30Z")));
+        assert_eq!(pending.remaining_secs(utc("2026-10-16T13:00:00Z")), 0);
+
+        let pending = PendingReboot::new(
+            &RebootCommand {
+                at: Some("2026-10-17T02:00:00+02:00".to_string()),
+                force: true,
+                ..Default::default()
+            },
+            now,
+        )
+        .unwrap();
+        assert_eq!(pending.at, "2026-10-17T02:00:00+02:00");
+        assert!(pending.force);
+        assert_eq!(pending.remaining_secs(now), 12 * 3600);
+
+        assert!(PendingReboot::new(&RebootCommand::default(), now).is_err());
+        assert!(
+            PendingReboot::new(
+                &RebootCommand {
+                    delay_secs: Some(0),
+                    ..Default::default()
+                },
+                now
+            )
+            .is_err()
+        );
+        for at in ["2026-10-16T12:00:00Z", "2026-10-16T11:59:59Z"] {
+            assert!(
+                PendingReboot::new(
+                    &RebootCommand {
+                        at: Some(at.to_string()),
+                        ..Default::default()
+                    },
+                    now
+                )
+                .is_err()
+            );
+        }
+        assert!(
+            PendingReboot::new(
+                &RebootCommand {
+                    at: Some("tomorrow".to_string()),
+                    ..Default::default()
+                },
+                now
+            )
+            .is_err()
+        );
+        assert!(
+            PendingReboot::new(
+                &RebootCommand {
+                    at: Some("2026-10-17T02:00:00Z".to_string()),
+                    delay_secs: Some(60),
+                    ..Default::default()
+                },
+                now
+            )
+            .is_err()
+        );
+    }
+}
@@ -20,6 +20,7 @@ use tokio::{
     process::{Child, Command},
     sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError, mpsc::Sender},
 };
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 static MAX_ACTIVE_TUNNELS: usize = 5;
@@ -152,7 +153,7 @@ impl Feature for SshTunnel {
         self.report().await
     }
 
-    async fn command(&mut self, cmd: &FeatureCommand) -> CommandResult {
+    async fn command(&mut self, cmd: &FeatureCommand, _cancel: CancellationToken) -> CommandResult {
         match cmd {
             FeatureCommand::DesiredUpdateDeviceSshCa(cmd) => self.update_device_ssh_ca(cmd).await,
             FeatureCommand::CloseSshTunnel(cmd) => self.close_ssh_tunnel(cmd).await,
@@ -764,11 +765,12 @@ mod tests {
         crate::common::set_env_var("DEVICE_CERT_FILE", tmp_file.path());
 
         let _response = ssh_tunnel
-            .command(&FeatureCommand::DesiredUpdateDeviceSshCa(
-                UpdateDeviceSshCaCommand {
+            .command(
+                &FeatureCommand::DesiredUpdateDeviceSshCa(UpdateDeviceSshCaCommand {
                     ssh_tunnel_ca_pub: CERTIFICATE_DATA.to_string(),
-                },
-            ))
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
 
@@ -808,9 +810,10 @@ mod tests {
         .unwrap();
 
         let response = ssh_tunnel
-            .command(&FeatureCommand::GetSshPubKey(GetSshPubKeyCommand {
-                tunnel_id,
-            }))
+            .command(
+                &FeatureCommand::GetSshPubKey(GetSshPubKeyCommand { tunnel_id }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap()
             .unwrap()
@@ -827,9 +830,10 @@ mod tests {
         let tunnel_id = "b7afb216-5f7a-4755-a300-9374f8a0e9ff".to_string();
 
         let response = ssh_tunnel
-            .command(&FeatureCommand::GetSshPubKey(GetSshPubKeyCommand {
-                tunnel_id,
-            }))
+            .command(
+                &FeatureCommand::GetSshPubKey(GetSshPubKeyCommand { tunnel_id }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap()
             .unwrap()
@@ -858,16 +862,20 @@ mod tests {
 
         // test successful
         ssh_tunnel
-            .command(&FeatureCommand::OpenSshTunnel(OpenSshTunnelCommand {
-                tunnel_id: "b7afb216-5f7a-4755-a300-9374f8a0e9ff".to_string(),
-                certificate: std::fs::read_to_string(cert_path.clone()).unwrap(),
-                bastion_config: BastionConfig {
-                    host: "test-host".to_string(),
-                    port: 2222,
-                    user: "test-user".to_string(),
-                    socket_path: std::path::PathBuf::from_str("/some/test/socket/path").unwrap(),
-                },
-            }))
+            .command(
+                &FeatureCommand::OpenSshTunnel(OpenSshTunnelCommand {
+                    tunnel_id: "b7afb216-5f7a-4755-a300-9374f8a0e9ff".to_string(),
+                    certificate: std::fs::read_to_string(cert_path.clone()).unwrap(),
+                    bastion_config: BastionConfig {
+                        host: "test-host".to_string(),
+                        port: 2222,
+                        user: "test-user".to_string(),
+                        socket_path: std::path::PathBuf::from_str("/some/test/socket/path")
+                            .unwrap(),
+                    },
+                }),
+                CancellationToken::new(),
+            )
             .await
             .unwrap();
 
@@ -887,16 +895,19 @@ mod tests {
         // the first 5 requests should succeed
         for pipe_name in &pipe_names[0..=4] {
             ssh_tunnel
-                .command(&FeatureCommand::OpenSshTunnel(OpenSshTunnelCommand {
-                    tunnel_id: "b7afb216-5f7a-4755-a300-9374f8a0e9ff".to_string(),
-                    certificate: std::fs::read_to_string(cert_path.clone()).unwrap(),
-                    bastion_config: BastionConfig {
-                        host: pipe_name.to_str().unwrap().to_string(),
-                        port: 2222,
-                        user: "test-user".to_string(),
-                        socket_path: PathBuf::from_str("/some/test/socket/path").unwrap(),
-                    },
-                }))
+                .command(
+                    &FeatureCommand::OpenSshTunnel(OpenSshTunnelCommand {
+                        tunnel_id: "b7afb216-5f7a-4755-a300-9374f8a0e9ff".to_string(),
+                        certificate: std::fs::read_to_string(cert_path.clone()).unwrap(),
+                        bastion_config: BastionConfig {
+                            host: pipe_name.to_str().unwrap().to_string(),
+                            port: 2222,
+                            user: "test-user".to_string(),
+                            socket_path: PathBuf::from_str("/some/test/socket/path").unwrap(),
+                        },
+                    }),
+                    CancellationToken::new(),
+                )
                 .await
                 .unwrap();
         }
@@ -904,16 +915,19 @@ mod tests {
         // the final should fail
         assert!(
             ssh_tunnel
-                .command(&FeatureCommand::OpenSshTunnel(OpenSshTunnelCommand {
-                    tunnel_id: "b7afb216-5f7a-4755-a300-9374f8a0e9ff".to_string(),
-                    certificate: std::fs::read_to_string(cert_path.clone()).unwrap(),
-                    bastion_config: BastionConfig {
-                        host: "test-host".to_string(),
-                        port: 2222,
-                        user: "test-user".to_string(),
-                        socket_path: PathBuf::from_str("/some/test/socket/path").unwrap(),
-                    },
-                }))
+                .command(
+                    &FeatureCommand::OpenSshTunnel(OpenSshTunnelCommand {
+                        tunnel_id: "b7afb216-5f7a-4755-a300-9374f8a0e9ff".to_string(),
+                        certificate: std::fs::read_to_string(cert_path.clone()).unwrap(),
+                        bastion_config: BastionConfig {
+                            host: "test-host".to_string(),
+                            port: 2222,
+                            user: "test-user".to_string(),
+                            socket_path: PathBuf::from_str("/some/test/socket/path").unwrap(),
+                        },
+                    }),
+                    CancellationToken::new()
+                )
                 .await
                 .is_err()
         );
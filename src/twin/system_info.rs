@@ -21,6 +21,14 @@ use tokio_util::sync::CancellationToken;
 
 static BOOTLOADER_UPDATED_FILE: &str = "/run/omnect-device-service/omnect_bootloader_updated";
 
+// the marker doesn't survive a reboot, thus the history is sent once per boot
+macro_rules! reboot_reason_history_sent_file_path {
+    () => {
+        env::var("REBOOT_REASON_HISTORY_SENT_PATH")
+            .unwrap_or("/run/omnect-device-service/reboot_reason_history_sent".to_string())
+    };
+}
+
 lazy_static! {
     static ref REFRESH_SYSTEM_INFO_INTERVAL_SECS: u64 = {
         const REFRESH_SYSTEM_INFO_INTERVAL_SECS_DEFAULT: &str = "60";
@@ -116,6 +124,8 @@ pub struct SystemInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     reboot_reason: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reboot_reason_history: Option<reboot_reason::RebootReasonHistory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     fleet_id: Option<String>,
 }
 impl Feature for SystemInfo {
@@ -138,7 +148,8 @@ impl Feature for SystemInfo {
     ) -> Result<()> {
         self.tx_outgoing_message = Some(tx_outgoing_message);
         self.tx_reported_properties = Some(tx_reported_properties);
-        self.report().await
+        self.report().await?;
+        self.send_reboot_reason_history().await
     }
 
     async fn connect_web_service(&self) -> Result<()> {
@@ -154,7 +165,7 @@ impl Feature for SystemInfo {
         })
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         match cmd {
             Command::Tick(_) => {
                 self.metrics().await?;
@@ -239,6 +250,7 @@ impl SystemInfo {
             },
             hostname: Self::hostname()?,
             reboot_reason: reboot_reason::current_reboot_reason(),
+            reboot_reason_history: reboot_reason::reboot_reason_history(),
             fleet_id: None,
         })
     }
@@ -258,6 +270,43 @@ impl SystemInfo {
             .context("report: send")
     }
 
+    async fn send_reboot_reason_history(&self) -> Result<()> {
+        let path = reboot_reason_history_sent_file_path!();
+
+        if Path::new(&path).exists() {
+            debug!("send_reboot_reason_history: already sent since boot");
+            return Ok(());
+        }
+
+        let (Some(tx), Some(history)) = (&self.tx_outgoing_message, &self.reboot_reason_history)
+        else {
+            warn!("send_reboot_reason_history: skip since no history or not connected");
+            return Ok(());
+        };
+
+        let msg = IotMessage::builder()
+            .set_body(
+                serde_json::to_vec(&json!({ "reboot_reason_history": history }))
+                    .context("send_reboot_reason_history: build body")?,
+            )
+            .set_content_type("application/json")
+            .set_content_encoding("utf-8")
+            .build()
+            .context("send_reboot_reason_history: build message")?;
+
+        tx.send(msg)
+            .await
+            .context("send_reboot_reason_history: send message")?;
+
+        std::fs::write(&path, "").context(format!(
+            "send_reboot_reason_history: failed to create {path}"
+        ))?;
+
+        info!("send_reboot_reason_history: message transmitted");
+
+        Ok(())
+    }
+
     #[cfg(not(feature = "mock"))]
     fn os_info() -> Result<OsInfo> {
         let os_info = OsInfo {
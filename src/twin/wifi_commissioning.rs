@@ -179,7 +179,7 @@ impl Feature for WifiCommissioning {
         Ok(None)
     }
 
-    async fn command(&mut self, cmd: &Command) -> CommandResult {
+    async fn command(&mut self, cmd: &Command, _cancel: CancellationToken) -> CommandResult {
         let Command::Tick(_) = cmd else {
             bail!("unexpected command: {cmd:?}")
         };
@@ -446,6 +446,11 @@ mod tests {
     #[tokio::test]
     async fn command_rejects_non_tick() {
         let mut feature = WifiCommissioning::default();
-        assert!(feature.command(&Command::Reboot).await.is_err());
+        assert!(
+            feature
+                .command(&Command::CancelReboot, CancellationToken::new())
+                .await
+                .is_err()
+        );
     }
 }
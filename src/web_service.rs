@@ -1,6 +1,8 @@
 use crate::{
     common::{from_json_file, to_json_file},
-    twin::feature::*,
+    reboot_guard::{RebootRateLimitedError, RebootSafeModeError},
+    reboot_inhibitor::{self, RebootInhibitedError, RebootInhibitorRequest},
+    twin::{feature::*, firmware_update},
 };
 use actix_server::ServerHandle;
 use actix_web::{App, HttpResponse, HttpServer, http::StatusCode, web};
@@ -55,9 +57,15 @@ macro_rules! publish_endpoints_path {
 
 #[derive(Debug, strum_macros::Display)]
 pub enum PublishChannel {
+    DownloadProgressV1,
     FactoryResetV1,
+    FirmwareUpdateHistoryV1,
+    FirmwareUpdateProgressV1,
+    JobStatusV1,
     NetworkStatusV1,
     OnlineStatusV1,
+    PendingRebootV1,
+    RebootSafeModeV1,
     SystemInfoV1,
     TimeoutsV1,
     UpdateValidationStatusV1,
@@ -67,9 +75,15 @@ pub enum PublishChannel {
 impl PublishChannel {
     fn to_status_string(&self) -> String {
         match self {
+            PublishChannel::DownloadProgressV1 => "DownloadProgress".to_string(),
             PublishChannel::FactoryResetV1 => "FactoryResetResult".to_string(),
+            PublishChannel::FirmwareUpdateHistoryV1 => "FirmwareUpdateHistory".to_string(),
+            PublishChannel::FirmwareUpdateProgressV1 => "FirmwareUpdateProgress".to_string(),
+            PublishChannel::JobStatusV1 => "JobStatus".to_string(),
             PublishChannel::NetworkStatusV1 => "NetworkStatus".to_string(),
             PublishChannel::OnlineStatusV1 => "OnlineStatus".to_string(),
+            PublishChannel::PendingRebootV1 => "PendingReboot".to_string(),
+            PublishChannel::RebootSafeModeV1 => "RebootSafeMode".to_string(),
             PublishChannel::SystemInfoV1 => "SystemInfo".to_string(),
             PublishChannel::TimeoutsV1 => "Timeouts".to_string(),
             PublishChannel::UpdateValidationStatusV1 => "UpdateValidationStatus".to_string(),
@@ -110,19 +124,40 @@ struct PublishEndpointRequest {
     endpoint: PublishEndpoint,
 }
 
+// an update is either loaded from a local file or downloaded from an url first
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LoadFwUpdateRequest {
+    Download(firmware_update::DownloadUpdateCommand),
+    Load(firmware_update::LoadUpdateCommand),
+}
+
 pub struct WebService {
     srv_handle: ServerHandle,
 }
 
 macro_rules! json_command_handler {
     ($fn_name:ident, $cmd_variant:path) => {
+        json_command_handler!($fn_name, $cmd_variant, serde_json::from_slice);
+    };
+    // the body is optional, without body the command's default is used
+    ($fn_name:ident, $cmd_variant:path, optional_body) => {
+        json_command_handler!($fn_name, $cmd_variant, |body: &[u8]| {
+            if body.is_empty() {
+                Ok(Default::default())
+            } else {
+                serde_json::from_slice(body)
+            }
+        });
+    };
+    ($fn_name:ident, $cmd_variant:path, $parse:expr) => {
         async fn $fn_name(
             body: web::Bytes,
             tx_request: web::Data<mpsc::Sender<CommandRequest>>,
         ) -> HttpResponse {
             debug!("WebService::{}", stringify!($fn_name));
 
-            match serde_json::from_slice(&body) {
+            match ($parse)(&body[..]) {
                 Ok(cmd) => {
                     let (tx_reply, rx_reply) = oneshot::channel();
                     let req = CommandRequest {
@@ -182,11 +217,48 @@ impl WebService {
                     web::delete().to(Self::unregister_publish_endpoint),
                 )
                 .route("/factory-reset/v1", web::post().to(Self::factory_reset))
+                .route("/fwupdate/cancel/v1", web::post().to(Self::cancel_fwupdate))
+                .route(
+                    "/fwupdate/cancel-schedule/v1",
+                    web::post().to(Self::cancel_fwupdate_schedule),
+                )
+                .route(
+                    "/fwupdate/history/v1",
+                    web::get().to(Self::fwupdate_history),
+                )
                 .route("/fwupdate/load/v1", web::post().to(Self::load_fwupdate))
                 .route("/fwupdate/run/v1", web::post().to(Self::run_fwupdate))
+                .route("/fwupdate/status/v1", web::get().to(Self::fwupdate_status))
+                .route(
+                    "/fwupdate/validation/confirm/v1",
+                    web::post().to(Self::confirm_fwupdate_validation),
+                )
+                .route(
+                    "/fwupdate/validation/extend/v1",
+                    web::post().to(Self::extend_fwupdate_validation),
+                )
+                .route(
+                    "/fwupdate/validation/reject/v1",
+                    web::post().to(Self::reject_fwupdate_validation),
+                )
                 .route("/healthcheck/v1", web::post().to(Self::healthcheck))
+                .route("/poweroff/v1", web::post().to(Self::poweroff))
                 .route("/reboot/v1", web::post().to(Self::reboot))
+                .route("/reboot/cancel/v1", web::post().to(Self::cancel_reboot))
+                .route(
+                    "/reboot/safe-mode/clear/v1",
+                    web::post().to(Self::clear_reboot_safe_mode),
+                )
+                .route(
+                    "/reboot-inhibitor/v1",
+                    web::post().to(Self::register_reboot_inhibitor),
+                )
+                .route(
+                    "/reboot-inhibitor/v1/{id}",
+                    web::delete().to(Self::unregister_reboot_inhibitor),
+                )
                 .route("/reload-network/v1", web::post().to(Self::reload_network))
+                .route("/restart-unit/v1", web::post().to(Self::restart_unit))
                 .route("/republish/v1/{id}", web::post().to(Self::republish))
                 .route("/status/v1", web::get().to(Self::status))
         });
@@ -293,6 +365,8 @@ impl WebService {
 
     json_command_handler!(factory_reset, Command::FactoryReset);
 
+    json_command_handler!(restart_unit, Command::RestartUnit);
+
     async fn exec_simple_command(
         tx_request: web::Data<mpsc::Sender<CommandRequest>>,
         command: Command,
@@ -313,8 +387,57 @@ impl WebService {
         HttpResponse::Ok().finish()
     }
 
-    async fn reboot(tx_request: web::Data<mpsc::Sender<CommandRequest>>) -> HttpResponse {
-        Self::exec_simple_command(tx_request, Command::Reboot, "reboot").await
+    json_command_handler!(poweroff, Command::Poweroff, optional_body);
+
+    json_command_handler!(reboot, Command::Reboot, optional_body);
+
+    async fn cancel_reboot(tx_request: web::Data<mpsc::Sender<CommandRequest>>) -> HttpResponse {
+        Self::exec_simple_command(tx_request, Command::CancelReboot, "cancel_reboot").await
+    }
+
+    async fn clear_reboot_safe_mode(
+        tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        Self::exec_simple_command(
+            tx_request,
+            Command::ClearRebootSafeMode,
+            "clear_reboot_safe_mode",
+        )
+        .await
+    }
+
+    // registrations are handled directly, so that they are possible while a
+    // feature is busy
+    async fn register_reboot_inhibitor(
+        body: web::Json<RebootInhibitorRequest>,
+        _tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        debug!("WebService register_reboot_inhibitor");
+
+        match reboot_inhibitor::register(&body.into_inner()) {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(e) => Self::log_error_response(
+                e,
+                "couldn't register reboot inhibitor",
+                StatusCode::BAD_REQUEST,
+            ),
+        }
+    }
+
+    async fn unregister_reboot_inhibitor(
+        id: web::Path<String>,
+        _tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        debug!("WebService unregister_reboot_inhibitor");
+
+        match reboot_inhibitor::unregister(&id.into_inner()) {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(e) => Self::log_error_response(
+                e,
+                "couldn't unregister reboot inhibitor",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        }
     }
 
     async fn reload_network(tx_request: web::Data<mpsc::Sender<CommandRequest>>) -> HttpResponse {
@@ -351,10 +474,93 @@ impl WebService {
         )
     }
 
-    json_command_handler!(load_fwupdate, Command::LoadFirmwareUpdate);
+    // the history is read from disk directly, so that it is available while
+    // the firmware update feature is busy
+    async fn fwupdate_history(
+        _tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        debug!("WebService fwupdate_history");
+
+        HttpResponse::Ok().json(json!({"history": firmware_update::UpdateHistory::load()}))
+    }
+
+    async fn load_fwupdate(
+        body: web::Bytes,
+        tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        debug!("WebService::load_fwupdate");
+
+        let command = match serde_json::from_slice(&body) {
+            Ok(LoadFwUpdateRequest::Download(cmd)) => Command::DownloadFirmwareUpdate(cmd),
+            Ok(LoadFwUpdateRequest::Load(cmd)) => Command::LoadFirmwareUpdate(cmd),
+            Err(e) => {
+                return Self::log_error_response(
+                    e,
+                    "couldn't parse load_fwupdate body",
+                    StatusCode::BAD_REQUEST,
+                );
+            }
+        };
+
+        let (tx_reply, rx_reply) = oneshot::channel();
+        let req = CommandRequest {
+            command,
+            reply: Some(tx_reply),
+        };
+        Self::exec_request(tx_request, rx_reply, req).await
+    }
 
     json_command_handler!(run_fwupdate, Command::RunFirmwareUpdate);
 
+    async fn fwupdate_status(tx_request: web::Data<mpsc::Sender<CommandRequest>>) -> HttpResponse {
+        Self::exec_simple_command(
+            tx_request,
+            Command::GetFirmwareUpdateStatus,
+            "fwupdate_status",
+        )
+        .await
+    }
+
+    async fn cancel_fwupdate(tx_request: web::Data<mpsc::Sender<CommandRequest>>) -> HttpResponse {
+        Self::exec_simple_command(tx_request, Command::CancelFirmwareUpdate, "cancel_fwupdate")
+            .await
+    }
+
+    async fn cancel_fwupdate_schedule(
+        tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        Self::exec_simple_command(
+            tx_request,
+            Command::CancelScheduledFirmwareUpdate,
+            "cancel_fwupdate_schedule",
+        )
+        .await
+    }
+
+    async fn confirm_fwupdate_validation(
+        tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        Self::exec_simple_command(
+            tx_request,
+            Command::ConfirmUpdateValidation,
+            "confirm_fwupdate_validation",
+        )
+        .await
+    }
+
+    async fn reject_fwupdate_validation(
+        tx_request: web::Data<mpsc::Sender<CommandRequest>>,
+    ) -> HttpResponse {
+        Self::exec_simple_command(
+            tx_request,
+            Command::RejectUpdateValidation,
+            "reject_fwupdate_validation",
+        )
+        .await
+    }
+
+    json_command_handler!(extend_fwupdate_validation, Command::ExtendUpdateValidation);
+
     async fn exec_request(
         tx_request: web::Data<mpsc::Sender<CommandRequest>>,
         rx_reply: tokio::sync::oneshot::Receiver<CommandResult>,
@@ -381,6 +587,18 @@ impl WebService {
                 info!("execute request: succeeded");
                 HttpResponse::Ok().finish()
             }
+            Err(e) if e.is::<CommandTimeoutError>() => {
+                error!("execute request: {e:#}");
+                HttpResponse::build(StatusCode::GATEWAY_TIMEOUT).body(e.to_string())
+            }
+            Err(e) if e.is::<RebootInhibitedError>() || e.is::<RebootSafeModeError>() => {
+                error!("execute request: {e:#}");
+                HttpResponse::build(StatusCode::CONFLICT).body(e.to_string())
+            }
+            Err(e) if e.is::<RebootRateLimitedError>() => {
+                error!("execute request: {e:#}");
+                HttpResponse::build(StatusCode::TOO_MANY_REQUESTS).body(e.to_string())
+            }
             Err(e) => {
                 error!("execute request: request failed with: {e:#}");
                 HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR).body(e.to_string())
@@ -514,6 +732,101 @@ mod tests {
         assert!(resp.status().is_server_error());
     }
 
+    #[actix_web::test]
+    async fn reboot_deferred() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route("/reboot/v1", web::post().to(WebService::reboot))
+                .route(
+                    "/reboot/cancel/v1",
+                    web::post().to(WebService::cancel_reboot),
+                ),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            assert!(matches!(
+                &req.command,
+                Command::Reboot(cmd) if cmd.delay_secs == Some(300) && !cmd.force
+            ));
+            req.reply.unwrap().send(Ok(None)).unwrap();
+
+            let req = rx_web_service.recv().await.unwrap();
+            assert_eq!(req.command, Command::CancelReboot);
+            req.reply.unwrap().send(Ok(None)).unwrap();
+        });
+
+        let req = test::TestRequest::post()
+            .uri("/reboot/v1")
+            .set_payload(r#"{"delay_secs": 300}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/reboot/cancel/v1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/reboot/v1")
+            .set_payload(r#"{"delay_secs": "soon"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn poweroff_and_restart_unit() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route("/poweroff/v1", web::post().to(WebService::poweroff))
+                .route("/restart-unit/v1", web::post().to(WebService::restart_unit)),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            assert!(matches!(&req.command, Command::Poweroff(cmd) if !cmd.force));
+            req.reply.unwrap().send(Ok(None)).unwrap();
+
+            let req = rx_web_service.recv().await.unwrap();
+            assert!(matches!(
+                &req.command,
+                Command::RestartUnit(cmd) if cmd.unit == "my-app.service"
+            ));
+            req.reply.unwrap().send(Ok(None)).unwrap();
+        });
+
+        let req = test::TestRequest::post().uri("/poweroff/v1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/restart-unit/v1")
+            .set_payload(r#"{"unit": "my-app.service"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        // a unit is required
+        let req = test::TestRequest::post()
+            .uri("/restart-unit/v1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
     #[actix_web::test]
     async fn healthcheck_ok() {
         let (tx_web_service, _rx_web_service) = tokio::sync::mpsc::channel::<CommandRequest>(100);
@@ -700,6 +1013,143 @@ mod tests {
         assert!(resp.status().is_server_error());
     }
 
+    #[actix_web::test]
+    async fn fwupdate_history_ok() {
+        let (tx_web_service, _rx_web_service) = tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route(
+                    "/fwupdate/history/v1",
+                    web::get().to(WebService::fwupdate_history),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/fwupdate/history/v1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["history"].is_array());
+    }
+
+    #[actix_web::test]
+    async fn fwupdate_status_and_cancel_ok() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route(
+                    "/fwupdate/status/v1",
+                    web::get().to(WebService::fwupdate_status),
+                )
+                .route(
+                    "/fwupdate/cancel/v1",
+                    web::post().to(WebService::cancel_fwupdate),
+                ),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            assert_eq!(req.command, Command::GetFirmwareUpdateStatus);
+            req.reply
+                .unwrap()
+                .send(Ok(Some(json!({"loaded": false}))))
+                .unwrap();
+
+            let req = rx_web_service.recv().await.unwrap();
+            assert_eq!(req.command, Command::CancelFirmwareUpdate);
+            req.reply.unwrap().send(Ok(None)).unwrap();
+        });
+
+        let req = test::TestRequest::get()
+            .uri("/fwupdate/status/v1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!({"loaded": false}));
+
+        let req = test::TestRequest::post()
+            .uri("/fwupdate/cancel/v1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn fwupdate_validation_ok() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route(
+                    "/fwupdate/validation/confirm/v1",
+                    web::post().to(WebService::confirm_fwupdate_validation),
+                )
+                .route(
+                    "/fwupdate/validation/extend/v1",
+                    web::post().to(WebService::extend_fwupdate_validation),
+                )
+                .route(
+                    "/fwupdate/validation/reject/v1",
+                    web::post().to(WebService::reject_fwupdate_validation),
+                ),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            assert_eq!(
+                req.command,
+                Command::ExtendUpdateValidation(firmware_update::ExtendUpdateValidationCommand {
+                    secs: 600,
+                    hold: true,
+                })
+            );
+            req.reply.unwrap().send(Ok(None)).unwrap();
+
+            let req = rx_web_service.recv().await.unwrap();
+            assert_eq!(req.command, Command::ConfirmUpdateValidation);
+            req.reply.unwrap().send(Ok(None)).unwrap();
+
+            let req = rx_web_service.recv().await.unwrap();
+            assert_eq!(req.command, Command::RejectUpdateValidation);
+            req.reply
+                .unwrap()
+                .send(Err(anyhow::anyhow!("no update validation in progress")))
+                .unwrap();
+        });
+
+        let req = test::TestRequest::post()
+            .uri("/fwupdate/validation/extend/v1")
+            .set_payload(r#"{"secs":600,"hold":true}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/fwupdate/validation/confirm/v1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/fwupdate/validation/reject/v1")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.status().is_success());
+    }
+
     #[actix_web::test]
     async fn load_fwupdate_ok() {
         let (tx_web_service, mut rx_web_service) =
@@ -728,6 +1178,43 @@ mod tests {
         assert!(resp.status().is_success());
     }
 
+    #[actix_web::test]
+    async fn load_fwupdate_from_url_ok() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route(
+                    "/fwupdate/load/v1",
+                    web::post().to(WebService::load_fwupdate),
+                ),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            assert_eq!(
+                req.command,
+                Command::DownloadFirmwareUpdate(firmware_update::DownloadUpdateCommand {
+                    url: "https://my-host/update.tar".to_string(),
+                    headers: HashMap::new(),
+                    sha256: Some("abc".to_string()),
+                    version_options: firmware_update::VersionOptions::default(),
+                })
+            );
+            req.reply.unwrap().send(Ok(None)).unwrap();
+        });
+
+        let req = test::TestRequest::post()
+            .uri("/fwupdate/load/v1")
+            .set_payload(r#"{"url":"https://my-host/update.tar","sha256":"abc"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
     #[actix_web::test]
     async fn load_fwupdate_invalid_json() {
         let (tx_web_service, _rx_web_service) = tokio::sync::mpsc::channel::<CommandRequest>(100);
@@ -1052,6 +1539,111 @@ mod tests {
         assert_eq!(result["result"], "success");
     }
 
+    #[actix_web::test]
+    async fn exec_request_timeout() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route("/reboot/v1", web::post().to(WebService::reboot)),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            req.reply
+                .unwrap()
+                .send(Err(CommandTimeoutError {
+                    command: "Reboot",
+                    timeout: std::time::Duration::from_secs(60),
+                }
+                .into()))
+                .unwrap();
+        });
+
+        let req = test::TestRequest::post().uri("/reboot/v1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[actix_web::test]
+    async fn exec_request_reboot_inhibited() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route("/reboot/v1", web::post().to(WebService::reboot)),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            req.reply
+                .unwrap()
+                .send(Err(RebootInhibitedError {
+                    by: vec!["my-app (writing data)".to_string()],
+                }
+                .into()))
+                .unwrap();
+        });
+
+        let req = test::TestRequest::post().uri("/reboot/v1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "reboot inhibited by my-app (writing data)");
+    }
+
+    #[actix_web::test]
+    async fn exec_request_reboot_refused() {
+        let (tx_web_service, mut rx_web_service) =
+            tokio::sync::mpsc::channel::<CommandRequest>(100);
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(tx_web_service.clone()))
+                .route("/reboot/v1", web::post().to(WebService::reboot)),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            let req = rx_web_service.recv().await.unwrap();
+            req.reply
+                .unwrap()
+                .send(Err(RebootRateLimitedError {
+                    count: 5,
+                    window_secs: 3600,
+                }
+                .into()))
+                .unwrap();
+
+            let req = rx_web_service.recv().await.unwrap();
+            req.reply
+                .unwrap()
+                .send(Err(RebootSafeModeError {
+                    reason: "reboot loop detected".to_string(),
+                }
+                .into()))
+                .unwrap();
+        });
+
+        let req = test::TestRequest::post().uri("/reboot/v1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let req = test::TestRequest::post().uri("/reboot/v1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "reboot refused in safe mode: reboot loop detected");
+    }
+
     #[actix_web::test]
     async fn publish_endpoint_headers_valid() {
         let endpoint = PublishEndpoint {
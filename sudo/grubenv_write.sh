@@ -0,0 +1,71 @@
+#!/bin/bash
+# Writes the grub environment block read from stdin – only variables managed by
+# omnect-device-service may be changed.
+set -efuo pipefail
+
+GRUBENV=/boot/EFI/BOOT/grubenv
+GRUBENV_HEADER="# GRUB Environment Block"
+
+die() {
+    echo "ERROR: $*" >&2
+    exit 1
+}
+
+# checks a change of variable $1: $2 is "set" or "unset", $3 the new value.
+# the allowed values are the same as of the former grub-editenv sudo rules.
+allowed() {
+    local key="$1" op="$2" value="$3"
+
+    case "$key" in
+        factory-reset) [[ "$op" == set ]] ;;
+        omnect_bootloader_updated) [[ "$op" == set && "$value" == 1 ]] ;;
+        omnect_os_bootpart) [[ "$op" == set && "$value" =~ ^[2-3]$ ]] ;;
+        omnect_validate_update) [[ "$op" == unset ]] ;;
+        omnect_validate_update_part) [[ "$op" == unset || "$value" =~ ^[2-3]$ ]] ;;
+        omnect_extra_bootargs | omnect_validate_extra_bootargs) true ;;
+        *) false ;;
+    esac
+}
+
+# reads the variables of a grubenv block into the associative array named $2
+read_env() {
+    local -n env="$2"
+    local line
+
+    while IFS= read -r line; do
+        [[ "$line" == *=* ]] || die "invalid line '${line}' in $1"
+        env["${line%%=*}"]="${line#*=}"
+    done < <(grep -av '^#' "$1" || true)
+}
+
+[[ $# -ne 0 ]] && die "Usage: $0 < <grubenv block>"
+
+# like grub-editenv we write a new file and rename it afterwards
+NEW="${GRUBENV}.new"
+trap 'rm -f "$NEW"' EXIT
+
+cat > "$NEW"
+
+[[ $(stat -c %s "$NEW") -eq $(stat -c %s "$GRUBENV") ]] || die "block size differs from ${GRUBENV}"
+[[ "$(head -n 1 "$NEW")" == "$GRUBENV_HEADER" ]] || die "invalid grubenv header"
+
+declare -A old_env new_env
+read_env "$GRUBENV" old_env
+read_env "$NEW" new_env
+
+# every changed variable must be allowed to change to its new value
+for key in "${!new_env[@]}"; do
+    if [[ "${old_env[$key]+set}" != set || "${old_env[$key]}" != "${new_env[$key]}" ]]; then
+        allowed "$key" set "${new_env[$key]}" || die "setting '${key}=${new_env[$key]}' is not allowed"
+    fi
+done
+
+for key in "${!old_env[@]}"; do
+    if [[ "${new_env[$key]+set}" != set ]]; then
+        allowed "$key" unset "" || die "unsetting '${key}' is not allowed"
+    fi
+done
+
+sync "$NEW"
+mv "$NEW" "$GRUBENV"
+sync "$GRUBENV"
@@ -0,0 +1,144 @@
+#!/bin/bash
+# Reads or writes one copy of the u-boot environment – only areas configured in
+# fw_env.config can be accessed and only variables managed by
+# omnect-device-service may be changed.
+set -efuo pipefail
+
+FW_ENV_CONFIG=/etc/fw_env.config
+
+usage() {
+    echo "Usage: $0 read|write <device> <offset> <size>" >&2
+    echo "       write reads the environment from stdin." >&2
+    exit 1
+}
+
+die() {
+    echo "ERROR: $*" >&2
+    exit 1
+}
+
+# only plain numbers may be evaluated arithmetically
+is_number() {
+    [[ "$1" =~ ^(0[xX][0-9a-fA-F]+|[1-9][0-9]*|0)$ ]]
+}
+
+read_area() {
+    dd if="$1" iflag=skip_bytes,count_bytes skip="$2" count="$3" status=none
+}
+
+# prints the "key=value" entries of an environment read from stdin
+entries() {
+    tail -c +$((HEADER_LEN + 1)) | tr '\0' '\n' | sed '/^$/q' | grep -a . || true
+}
+
+# reads the entries of an environment from stdin into the associative array
+# named $1
+read_env() {
+    local -n env="$1"
+    local line
+
+    while IFS= read -r line; do
+        [[ "$line" == *=* ]] || die "invalid entry '${line}'"
+        env["${line%%=*}"]="${line#*=}"
+    done < <(entries)
+}
+
+# checks a change of variable $1: $2 is "set" or "unset", $3 the new value.
+# the allowed values are the same as of the former fw_setenv sudo rules, i.e.
+# boot arguments must not contain scripts.
+allowed() {
+    local key="$1" op="$2" value="$3"
+
+    case "$key" in
+        factory-reset) [[ "$op" == set ]] ;;
+        omnect_bootloader_updated) [[ "$op" == set && "$value" == 1 ]] ;;
+        omnect_os_bootpart) [[ "$op" == set && "$value" =~ ^[2-3]$ ]] ;;
+        omnect_validate_update) [[ "$op" == unset ]] ;;
+        omnect_validate_update_part) [[ "$op" == unset || "$value" =~ ^[2-3]$ ]] ;;
+        omnect_extra_bootargs | omnect_validate_extra_bootargs) [[ "$value" != *[\;\$\`]* ]] ;;
+        *) false ;;
+    esac
+}
+
+# the crc32 (little endian) of the data after the header must match the one
+# in the header, gzip's trailer contains the crc32 of the input
+check_crc() {
+    cmp -s <(head -c 4 "$1") <(tail -c +$((HEADER_LEN + 1)) "$1" | gzip -c | tail -c 8 | head -c 4)
+}
+
+[[ $# -ne 4 ]] && usage
+
+CMD="$1"
+DEVICE="$2"
+is_number "$3" && is_number "$4" || die "invalid offset or size"
+OFFSET=$(($3))
+SIZE=$(($4))
+
+DEVICES=()
+OFFSETS=()
+while read -r dev off size _; do
+    [[ -z "$dev" || "$dev" == \#* ]] && continue
+    is_number "$off" && is_number "$size" || die "invalid line in ${FW_ENV_CONFIG}"
+    [[ $((size)) -eq $SIZE ]] || continue
+    DEVICES+=("$dev")
+    OFFSETS+=($((off)))
+done < "$FW_ENV_CONFIG"
+
+INDEX=-1
+for i in "${!DEVICES[@]}"; do
+    [[ "${DEVICES[$i]}" == "$DEVICE" && ${OFFSETS[$i]} -eq $OFFSET ]] && INDEX=$i
+done
+[[ $INDEX -ge 0 ]] || die "${DEVICE} $3 $4 is not configured in ${FW_ENV_CONFIG}"
+
+case "$CMD" in
+read)
+    read_area "$DEVICE" "$OFFSET" "$SIZE"
+    ;;
+write)
+    NEW=$(mktemp)
+    trap 'rm -f "$NEW"' EXIT
+    cat > "$NEW"
+    [[ $(stat -c %s "$NEW") -eq $SIZE ]] || die "environment size differs from ${SIZE}"
+
+    # a redundant environment has an additional flag byte and the other copy
+    # is the current one
+    if [[ ${#DEVICES[@]} -eq 2 ]]; then
+        HEADER_LEN=5
+        OTHER=$((1 - INDEX))
+    else
+        HEADER_LEN=4
+        OTHER=$INDEX
+    fi
+
+    check_crc "$NEW" || die "environment crc mismatch"
+
+    declare -A old_env new_env
+    read_env old_env < <(read_area "${DEVICES[$OTHER]}" "${OFFSETS[$OTHER]}" "$SIZE")
+    read_env new_env < "$NEW"
+
+    # every changed variable must be allowed to change to its new value
+    for key in "${!new_env[@]}"; do
+        if [[ "${old_env[$key]+set}" != set || "${old_env[$key]}" != "${new_env[$key]}" ]]; then
+            allowed "$key" set "${new_env[$key]}" || die "setting '${key}=${new_env[$key]}' is not allowed"
+        fi
+    done
+
+    for key in "${!old_env[@]}"; do
+        if [[ "${new_env[$key]+set}" != set ]]; then
+            allowed "$key" unset "" || die "unsetting '${key}' is not allowed"
+        fi
+    done
+
+    # eMMC boot partitions are read-only by default
+    FORCE_RO="/sys/block/$(basename "$DEVICE")/force_ro"
+    if [[ -f "$FORCE_RO" && "$(cat "$FORCE_RO")" == "1" ]]; then
+        echo 0 > "$FORCE_RO"
+        trap 'echo 1 > "$FORCE_RO"; rm -f "$NEW"' EXIT
+    fi
+
+    dd if="$NEW" of="$DEVICE" oflag=seek_bytes seek="$OFFSET" conv=notrunc,fsync status=none
+    ;;
+*)
+    usage
+    ;;
+esac
@@ -1,6 +1,16 @@
 #!/bin/bash -x
 script=${0}
 update_validation_file="/run/omnect-device-service/omnect_validate_update"
+# written by omnect-device-service if the update validation was extended
+deadline_file="/run/omnect-device-service/omnect_validate_update_deadline"
+
+# the overall timeout is deferred to an extended deadline, the deadline may
+# be extended again meanwhile
+while [ -f ${update_validation_file} ] && [ -f ${deadline_file} ]; do
+  remaining=$(($(cat ${deadline_file}) - $(date +%s)))
+  [ ${remaining} -gt 0 ] || break
+  sleep ${remaining}
+done
 
 if [ -f ${update_validation_file} ]; then
   echo "reboot triggered by ${script}"